@@ -50,7 +50,7 @@ fn ckv_monotonic_crud(c: &mut Criterion) {
         std::fs::remove_dir_all(&opt.work_dir).unwrap()
     };
 
-    let lsm = Lsm::open(opt);
+    let lsm = Lsm::open(opt).unwrap();
 
     c.bench_function("monotonic inserts", |b| {
         let mut count = 0_u32;
@@ -85,7 +85,7 @@ fn ckv_random_crud(c: &mut Criterion) {
     if std::fs::metadata(&opt.work_dir).is_ok() {
         std::fs::remove_dir_all(&opt.work_dir).unwrap()
     };
-    let lsm = Lsm::open(opt);
+    let lsm = Lsm::open(opt).unwrap();
     c.bench_function("random inserts", |b| {
         b.iter(|| {
             lsm.put(&random_data(), &random_data()).unwrap();
@@ -108,7 +108,7 @@ fn ckv_empty_opens(c: &mut Criterion) {
     let _ = std::fs::remove_dir_all(&opt.work_dir);
     c.bench_function("empty_opens", |b| {
         b.iter(|| {
-            Lsm::open(opt.clone());
+            Lsm::open(opt.clone()).unwrap();
         })
     });
     let _ = std::fs::remove_dir_all("work_dir/bench/empty_opens");
@@ -6,7 +6,7 @@ fn main() -> Result<()> {
         .work_dir("work_dir/lsm")
         .mem_size(1 << 12)
         .kv_separate_threshold(4);
-    let lsm = Lsm::open(opt);
+    let lsm = Lsm::open(opt)?;
     lsm.put(b"key1", b"val1")?;
     lsm.get(b"key1")?;
 
@@ -0,0 +1,23 @@
+use std::path::Path;
+
+use anyhow::Result;
+use ckv::VlogIterator;
+
+// Print the entries of a vlog file, e.g.
+// cargo run --example vlog_dump -- work_dir/lsm/00012.vlog
+fn main() -> Result<()> {
+    let Some(path) = std::env::args().nth(1) else {
+        anyhow::bail!("usage: vlog_dump <vlog file>");
+    };
+    let mut iter = VlogIterator::open(Path::new(&path))?;
+    while let Some((offset, entry)) = iter.next_entry()? {
+        println!(
+            "offset {:>10}  seq {:>10}  key {:?}  value {} bytes",
+            offset,
+            entry.seq,
+            String::from_utf8_lossy(&entry.key),
+            entry.value.len()
+        );
+    }
+    Ok(())
+}
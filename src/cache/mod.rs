@@ -2,7 +2,7 @@ mod table_cache;
 
 pub use table_cache::*;
 
-type Result<T> = anyhow::Result<T, CacheError>;
+type Result<T> = std::result::Result<T, CacheError>;
 
 /// The error type of catalog operations.
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
@@ -15,6 +15,16 @@ pub enum CacheError {
     UnpinNonPinned,
 }
 
+/// Counters of a cache, for monitoring its effectiveness.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Total charge of the cached elements.
+    pub usage: usize,
+    pub capacity: usize,
+}
+
 // pub trait Cache {
 //     // fn pin(&self, key: &Key);
 //     fn unpin(&self, key: &u64) -> Result<()>;
@@ -1,14 +1,17 @@
 use std::{
     collections::{hash_map::RandomState, HashMap, VecDeque},
     fmt::Debug,
-    hash::{BuildHasher, Hash, Hasher},
-    sync::Arc,
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use parking_lot::Mutex;
-use tracing::info;
+use tracing::debug;
 
-use super::{CacheError, Result};
+use super::{CacheError, CacheStats, Result};
 
 const NUM_SHARD_BITS: usize = 4;
 const NUM_SHARDS: usize = 1 << NUM_SHARD_BITS;
@@ -26,14 +29,16 @@ struct Node<Value> {
     value: Value,
     pinned: u32,
     handle: NodeState,
+    charge: usize,
 }
 
 impl<Value> Node<Value> {
-    fn new(value: Value, pinned: u32, handle: NodeState) -> Self {
+    fn new(value: Value, pinned: u32, handle: NodeState, charge: usize) -> Self {
         Self {
             value,
             pinned,
             handle,
+            charge,
         }
     }
 }
@@ -44,6 +49,8 @@ unsafe impl<K: Hash + Eq + Clone, V> Sync for Cache<K, V> {}
 pub struct Cache<K: Hash, V> {
     inner: Arc<Vec<Mutex<LRUInner<K, V>>>>,
     hasher: RandomState,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl<K, V> Cache<K, V>
@@ -52,13 +59,15 @@ where
 {
     pub fn with_capacity(capacity: usize) -> Self {
         let mut shards = Vec::with_capacity(NUM_SHARDS);
-        let per_shard = (capacity + (NUM_SHARDS - 1)) / NUM_SHARDS;
+        let per_shard = capacity.div_ceil(NUM_SHARDS);
         for _ in 0..NUM_SHARDS {
             shards.push(Mutex::new(LRUInner::with_capacity(per_shard)));
         }
         Self {
             inner: Arc::new(shards),
             hasher: RandomState::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
 }
@@ -75,25 +84,53 @@ where
     pub fn get(&self, key: &K) -> Option<CacheValue<V>> {
         // let mut inner = self.inner.lock();
         let mut inner = self.inner[self.shards(key)].lock();
-        inner.get(key)
+        let value = inner.get(key);
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
     }
 
-    pub fn insert(&self, key: K, value: V, charge: usize) -> Result<()> {
+    pub fn insert(&self, key: K, value: V, charge: usize) -> Result<CacheValue<V>> {
         // self.inner.lock().insert(key, value, charge)
         self.inner[self.shards(&key)]
             .lock()
             .insert(key, value, charge)
     }
 
-    pub fn evict(&self, key: K, charge: usize) -> Result<()> {
+    pub fn evict(&self, key: K) -> Result<()> {
         // self.inner.lock().evict(&key, charge)
-        self.inner[self.shards(&key)].lock().evict(&key, charge)
+        self.inner[self.shards(&key)].lock().evict(&key)
+    }
+
+    /// Evict every element whose key matches `pred`.
+    pub fn evict_if(&self, pred: impl Fn(&K) -> bool) {
+        for shard in self.inner.iter() {
+            let mut shard = shard.lock();
+            let keys: Vec<K> = shard.table.keys().filter(|k| pred(k)).cloned().collect();
+            for key in keys.iter() {
+                let _ = shard.evict(key);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (mut usage, mut capacity) = (0, 0);
+        for shard in self.inner.iter() {
+            let shard = shard.lock();
+            usage += shard.usage;
+            capacity += shard.capacity;
+        }
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            usage,
+            capacity,
+        }
     }
     fn shards(&self, key: &K) -> usize {
-        let mut hasher = self.hasher.build_hasher();
-        // let hasher = &mut RandomState::default().build_hasher();
-        key.hash(&mut hasher);
-        let h = hasher.finish();
+        let h = self.hasher.hash_one(key);
         // let data = key.to_be_bytes();
         // let (seed, m) = (0xbc9f1d34_usize, 0xc6a4a793_usize);
         // let mut h = seed ^ (m.wrapping_mul(data.len()));
@@ -177,7 +214,7 @@ where
         }
     }
 
-    pub fn insert(&mut self, key: K, value: V, charge: usize) -> Result<()> {
+    pub fn insert(&mut self, key: K, value: V, charge: usize) -> Result<CacheValue<V>> {
         // let _lock = self.lock.lock();
         match self.table.get_mut(&key) {
             Some(_) => {
@@ -195,9 +232,12 @@ where
                 }
 
                 self.usage += charge;
-                self.table
-                    .insert(key.clone(), Node::new(Arc::new(value), 1, NodeState::InUse));
-                info!(
+                let value = Arc::new(value);
+                self.table.insert(
+                    key.clone(),
+                    Node::new(value.clone(), 1, NodeState::InUse, charge),
+                );
+                debug!(
                     "insert {:?} to cache; usage: {}, capacity: {}",
                     key, self.usage, self.capacity
                 );
@@ -205,11 +245,10 @@ where
 
                 while self.usage > self.capacity && !self.lru.is_empty() {
                     let removed_key = self.lru.pop_front().unwrap();
-                    let _removed = self.table.remove(&removed_key).unwrap();
-                    // self.usage -= removed.value.size() as usize;
-                    self.usage -= charge;
+                    let removed = self.table.remove(&removed_key).unwrap();
+                    self.usage -= removed.charge;
                 }
-                Ok(())
+                Ok(value)
             }
         }
     }
@@ -232,7 +271,7 @@ where
         Ok(())
     }
 
-    pub fn evict(&mut self, key: &K, charge: usize) -> Result<()>
+    pub fn evict(&mut self, key: &K) -> Result<()>
     where
         K: Debug,
     {
@@ -247,42 +286,45 @@ where
                     self.lru.remove(idx);
                 }
             }
-            self.usage -= charge;
+            self.usage -= node.charge;
         }
         Ok(())
     }
 }
 
-// #[cfg(test)]
-// mod cache_test {
-//     use crate::cache::CacheError;
-//
-//     use super::LRUInner;
-//
-//     #[test]
-//     fn cache_test() {
-//         let mut cache = LRUInner::<i32, i32>::with_capacity(10);
-//         for i in 0..10 {
-//             cache.insert(i, i).unwrap();
-//         }
-//
-//         assert_eq!(cache.insert(11, 11), Err(CacheError::AllElementsPinned));
-//         for i in 0..5 {
-//             cache.unpin(&i).unwrap();
-//         }
-//         assert_eq!(cache.insert(9, 9), Err(CacheError::DuplicatedElements));
-//
-//         assert!(cache.insert(11, 11).is_ok()); // vicmtim 0
-//
-//         assert_eq!(cache.get(&11), Some(&11));
-//         assert_eq!(cache.get(&0), None);
-//         assert_eq!(cache.get(&1), Some(&1)); // 1 pinned
-//         assert!(cache.insert(12, 12).is_ok()); // victim 2
-//         assert_eq!(cache.get(&12), Some(&12));
-//         assert_eq!(cache.get(&2), None);
-//
-//         assert!(cache.insert(13, 13).is_ok()); // victim 3
-//         assert!(cache.insert(14, 14).is_ok()); // victim 4
-//         assert_eq!(cache.insert(15, 15), Err(CacheError::AllElementsPinned));
-//     }
-// }
+#[cfg(test)]
+mod cache_test {
+    use crate::cache::CacheError;
+
+    use super::LRUInner;
+
+    #[test]
+    fn cache_test() {
+        let mut cache = LRUInner::<i32, i32>::with_capacity(10);
+        for i in 0..5 {
+            cache.insert(i, i, 2).unwrap();
+        }
+
+        assert_eq!(cache.insert(5, 5, 1), Err(CacheError::AllElementsPinned));
+        for i in 0..5 {
+            cache.unpin(&i).unwrap();
+        }
+        assert_eq!(cache.insert(4, 4, 1), Err(CacheError::DuplicatedElements));
+
+        // evicts by the charge of the victims, not of the new element
+        cache.insert(5, 5, 3).unwrap(); // victims 0, 1
+        assert_eq!(cache.usage, 9);
+        assert!(cache.get(&0).is_none());
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get(&2).as_deref(), Some(&2)); // 2 pinned
+
+        cache.unpin(&5).unwrap();
+        cache.evict(&5).unwrap();
+        assert_eq!(cache.usage, 6);
+        cache.insert(6, 6, 6).unwrap(); // victim 3
+        assert_eq!(cache.usage, 10);
+        assert_eq!(cache.get(&2).as_deref(), Some(&2));
+        assert!(cache.get(&3).is_none());
+        assert_eq!(cache.get(&4).as_deref(), Some(&4));
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+/// What a [`CompactionFilter`] makes of an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionDecision {
+    Keep,
+    /// Drop the entry, reading as deleted from then on.
+    Remove,
+    /// Replace the value of the entry. A put that expires keeps its deadline.
+    ChangeValue(Vec<u8>),
+}
+
+/// Removes or rewrites entries by application logic while they are compacted.
+///
+/// It is asked about the newest value of each key that no snapshot sees, so a
+/// snapshot never notices the change. Values separated into the vlog are
+/// read back for it. Deletions, merge operands and the values they are merged
+/// into are left alone.
+pub trait CompactionFilter: Send + Sync {
+    /// Names the filter.
+    fn name(&self) -> &str;
+
+    /// Decide on `value` of `key`, compacted into `level`.
+    fn filter(&self, level: usize, key: &[u8], value: &[u8]) -> CompactionDecision;
+}
+
+impl fmt::Debug for dyn CompactionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
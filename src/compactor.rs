@@ -1,18 +1,21 @@
 use std::{
+    collections::HashMap,
     sync::{
         mpsc::{Receiver, RecvTimeoutError},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use tracing::error;
+
 use crate::{
     file::{path_of_file, Ext},
     lsm::LsmInner,
     mem_table::{MemTable, MemTableIterator},
     sstable::TableBuilder,
     version::{FileMetaData, Version, VersionEdit},
-    Options,
+    Options, Result,
 };
 
 #[derive(Debug)]
@@ -21,6 +24,8 @@ pub struct CompactionState {
     pub target_level: usize,
     pub base: Vec<FileMetaData>,
     pub target: Vec<FileMetaData>,
+    // bytes dropped from each vlog the output keeps referencing
+    pub discards: HashMap<u64, u64>,
 }
 pub struct GCState {
     pub level: usize,
@@ -37,14 +42,21 @@ pub enum Task {
     Compact,
     Seek(SeekTask),
     Major,
+    GC,
 }
 
+// how often the compactor looks for vlogs worth collecting, besides after
+// the compactions that leave garbage in them
+const GC_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct SeekTask {
+    pub cf: u32,
     pub level: u32,
     pub fid: u64,
 }
 
 #[derive(Clone)]
+#[allow(dead_code)]
 pub struct L0Task {
     pub version: Arc<Version>,
     pub imm: Arc<MemTable>,
@@ -52,33 +64,35 @@ pub struct L0Task {
     pub fid: u64,
 }
 
+#[allow(dead_code)]
 impl L0Task {
-    pub fn execute(&self, opt: Options) -> VersionEdit {
+    pub fn execute(&self, opt: Options) -> Result<VersionEdit> {
         {
             // let inner = self.mem_inner.read();
             let mut edit = VersionEdit::new();
             // let fid = self.version.new_file_number();
             let mut file_meta = FileMetaData::new(self.fid);
-            // imm  to sst
 
+            // pick level to push
+            let level = self.imm.user_key_range().map_or(0, |(smallest, largest)| {
+                self.version
+                    .pick_level_for_mem_table_output(&smallest, &largest)
+            });
+
+            // imm  to sst
             TableBuilder::build_table(
                 path_of_file(&opt.work_dir, self.fid, Ext::SST).as_path(),
                 opt.clone(),
+                level as usize,
                 MemTableIterator::new(&self.imm),
+                &self.imm.range_tombstones(),
                 &mut file_meta,
-            )
-            .unwrap();
-
-            // pick level to push
-            let level = self.version.pick_level_for_mem_table_output(
-                file_meta.smallest().user_key(),
-                file_meta.largest().user_key(),
-            );
+            )?;
 
             edit.add_file(level, file_meta);
             edit.log_number(self.log_number);
             self.version.derefs();
-            edit
+            Ok(edit)
             // info!("Minor compact {:05}.sst to level {:?}", fid, level);
         }
     }
@@ -89,42 +103,60 @@ impl Compactor {
     }
 
     pub fn run_compactor(&self) {
+        let mut last_gc = Instant::now();
+        // a task a finished one asked for, run before waiting for more
+        let mut next: Option<Task> = None;
         loop {
-            match self.handle.recv_timeout(Duration::from_secs(2)) {
-                Ok(task) => match task {
-                    Task::Compact => {
-                        let sz = self.lsm_inner.imms_sz();
-                        if self.lsm_inner.imms_sz() > 0 {
-                            for _ in 0..(sz.max(4) - 3) {
-                                self.lsm_inner.compact_mem_table();
-                            }
-                        } else {
-                            // compact sst
-                            self.lsm_inner.major_compaction().unwrap();
-                        }
-                    }
-                    Task::Seek(seek_task) => {
-                        self.lsm_inner.seek_compaction(&seek_task).unwrap();
-                    }
-                    Task::Major => {
-                        self.lsm_inner.major_compaction().unwrap();
+            let task = match next.take() {
+                Some(task) => task,
+                // a busy compactor still collects on time
+                None if last_gc.elapsed() >= GC_INTERVAL => Task::GC,
+                None => match self.handle.recv_timeout(Duration::from_secs(2)) {
+                    Ok(task) => task,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        break;
                     }
+                    Err(RecvTimeoutError::Timeout) => Task::Compact,
                 },
-                Err(RecvTimeoutError::Disconnected) => {
-                    break;
+            };
+            // leave the queued tasks to the next open
+            if self.lsm_inner.is_closing() {
+                break;
+            }
+            let result = match task {
+                Task::Compact => self.compact(),
+                Task::Seek(seek_task) => self.lsm_inner.seek_compaction(&seek_task),
+                Task::Major => self.lsm_inner.major_compaction(),
+                Task::GC => {
+                    last_gc = Instant::now();
+                    let ratio = self.lsm_inner.options().value_log_gc_ratio;
+                    // a table was rewritten, another may be worth it too
+                    self.lsm_inner
+                        .value_log_gc(ratio)
+                        .map(|rewritten| rewritten.then_some(Task::GC))
                 }
-                Err(RecvTimeoutError::Timeout) => {
-                    let sz = self.lsm_inner.imms_sz();
-                    if self.lsm_inner.imms_sz() > 0 {
-                        for _ in 0..(sz.max(4) - 3) {
-                            self.lsm_inner.compact_mem_table();
-                        }
-                    } else {
-                        // compact sst
-                        self.lsm_inner.major_compaction().unwrap();
-                    }
+            };
+            // the inputs stay in place, so the task is retried later
+            match result {
+                Ok(task) => next = task,
+                Err(err) => {
+                    error!("background task failed: {}", err);
+                    self.lsm_inner.record_bg_error(err);
                 }
             }
         }
     }
+
+    fn compact(&self) -> Result<Option<Task>> {
+        let sz = self.lsm_inner.imms_sz();
+        if sz > 0 {
+            for _ in 0..(sz.max(4) - 3) {
+                self.lsm_inner.compact_mem_table()?;
+            }
+            Ok(None)
+        } else {
+            // compact sst
+            self.lsm_inner.major_compaction()
+        }
+    }
 }
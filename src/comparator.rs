@@ -0,0 +1,40 @@
+use std::{cmp::Ordering, fmt};
+
+/// Orders the user keys of a storage.
+///
+/// Mem-tables, tables, the level search and the iterators all order keys by
+/// it, so a storage must always be opened with the same comparator. Its name
+/// is kept in the manifest and checked on open.
+///
+/// Keys that compare equal must be equal bytes.
+pub trait Comparator: Send + Sync {
+    /// Names the comparator in the manifest. Changing the order of keys
+    /// calls for a new name.
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+impl fmt::Debug for dyn Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Orders keys lexicographically by their bytes, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BytewiseComparator;
+
+impl BytewiseComparator {
+    pub const NAME: &'static str = "ckv.BytewiseComparator";
+}
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
@@ -0,0 +1,60 @@
+/// Compression of the blocks of a table. Each block records the type it was
+/// stored with, so the type can change without rewriting tables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    None = 0,
+    #[default]
+    Lz4 = 1,
+    Zstd = 2,
+    Snappy = 3,
+}
+
+impl CompressionType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompressionType::None => "none",
+            CompressionType::Lz4 => "lz4",
+            CompressionType::Zstd => "zstd",
+            CompressionType::Snappy => "snappy",
+        }
+    }
+
+    pub(crate) fn compress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            CompressionType::Zstd => zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .map_err(|err| err.to_string()),
+            CompressionType::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(|err| err.to_string()),
+        }
+    }
+
+    pub(crate) fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Lz4 => {
+                lz4_flex::decompress_size_prepended(data).map_err(|err| err.to_string())
+            }
+            CompressionType::Zstd => zstd::stream::decode_all(data).map_err(|err| err.to_string()),
+            CompressionType::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+impl TryFrom<u8> for CompressionType {
+    type Error = String;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Lz4),
+            2 => Ok(CompressionType::Zstd),
+            3 => Ok(CompressionType::Snappy),
+            _ => Err(format!("unknown compression type {}", v)),
+        }
+    }
+}
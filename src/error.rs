@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use crate::cache::CacheError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The error type of the storage engine.
+///
+/// It is cheap to clone, so one failure can be handed to every writer of a
+/// write group.
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(Arc<std::io::Error>),
+    #[error("corruption in {file} at offset {offset}: {reason}")]
+    Corruption {
+        file: String,
+        offset: u64,
+        reason: String,
+    },
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("busy: {0}")]
+    Busy(String),
+    #[error("merge failed: {0}")]
+    MergeFailed(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("timed out: {0}")]
+    TimedOut(String),
+    #[error("deadlock: {0}")]
+    Deadlock(String),
+}
+
+impl Error {
+    pub(crate) fn corruption(file: impl Into<String>, offset: u64, reason: impl ToString) -> Self {
+        Error::Corruption {
+            file: file.into(),
+            offset,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(Arc::new(err))
+    }
+}
+
+impl From<CacheError> for Error {
+    fn from(err: CacheError) -> Self {
+        Error::Busy(err.to_string())
+    }
+}
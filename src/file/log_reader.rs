@@ -1,8 +1,8 @@
-use std::io::Error;
+use std::io::ErrorKind;
 
 use bytes::Buf;
 
-use crate::utils::codec::verify_checksum;
+use crate::{utils::codec::verify_checksum, Error, Result};
 
 use super::{RandomAccess, SequentialAccess};
 
@@ -16,17 +16,45 @@ impl Reader {
         Self { file, offset: 0 }
     }
 
-    pub fn read_record(&mut self) -> Result<Vec<u8>, Error> {
+    /// A reader of a file whose first `offset` bytes were already consumed.
+    pub fn with_offset(file: Box<dyn SequentialAccess>, offset: u64) -> Self {
+        Self { file, offset }
+    }
+
+    pub fn name(&self) -> &str {
+        self.file.name()
+    }
+
+    /// Offset of the next record.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Read the next record, none at the end of the file. A record cut short
+    /// by a crash in the middle of a write also ends the file.
+    pub fn read_record(&mut self) -> Result<Option<Vec<u8>>> {
         let mut buf = vec![0_u8; 12];
-        self.file.read(&mut buf)?;
+        if !self.read_or_eof(&mut buf)? {
+            return Ok(None);
+        }
         let checksum = (&buf[..]).get_u64();
         let len = (&buf[8..]).get_u32();
         let mut data = vec![0_u8; len as usize];
-        self.file.read(&mut data)?;
-        let data = lz4_flex::decompress_size_prepended(&data).unwrap();
-        verify_checksum(&data, checksum).unwrap();
-        self.offset += 12 + data.len() as u64;
-        Ok(data)
+        if !self.read_or_eof(&mut data)? {
+            return Ok(None);
+        }
+        let data = decode_record(&data, checksum)
+            .map_err(|reason| Error::corruption(self.file.name(), self.offset, reason))?;
+        self.offset += 12 + len as u64;
+        Ok(Some(data))
+    }
+
+    fn read_or_eof(&mut self, buf: &mut [u8]) -> Result<bool> {
+        match self.file.read(buf) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err.into()),
+        }
     }
 }
 
@@ -39,7 +67,18 @@ impl RandomReader {
         Self { file }
     }
 
-    pub fn read_record(&mut self, offset: u64) -> Result<Vec<u8>, Error> {
+    pub fn name(&self) -> &str {
+        self.file.name()
+    }
+
+    /// Size of the record at `offset`, including its header.
+    pub fn record_size(&self, offset: u64) -> Result<u64> {
+        let mut buf = vec![0_u8; 12];
+        self.file.read(&mut buf, offset)?;
+        Ok(12 + (&buf[8..]).get_u32() as u64)
+    }
+
+    pub fn read_record(&self, offset: u64) -> Result<Vec<u8>> {
         let mut buf = vec![0_u8; 12];
         self.file.read(&mut buf, offset)?;
         let checksum = (&buf[..]).get_u64();
@@ -48,8 +87,16 @@ impl RandomReader {
         let mut data = vec![0_u8; len as usize];
         self.file.read(&mut data, offset + 12)?;
 
-        let data = lz4_flex::decompress_size_prepended(&data).unwrap();
-        verify_checksum(&data, checksum).unwrap();
-        Ok(data)
+        decode_record(&data, checksum)
+            .map_err(|reason| Error::corruption(self.file.name(), offset, reason))
     }
 }
+
+// +----------+-----+--------------+
+// | checksum | len | lz4(payload) |
+// +----------+-----+--------------+
+fn decode_record(data: &[u8], checksum: u64) -> std::result::Result<Vec<u8>, String> {
+    let data = lz4_flex::decompress_size_prepended(data).map_err(|err| err.to_string())?;
+    verify_checksum(&data, checksum).map_err(|err| err.to_string())?;
+    Ok(data)
+}
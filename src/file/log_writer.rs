@@ -1,34 +1,39 @@
 use std::io::Error;
 
-use bytes::{BufMut, Bytes};
+use bytes::BufMut;
 use parking_lot::Mutex;
 
 use crate::utils::codec::calculate_checksum;
 
-use super::{writeable::WritableFileImpl, Writable};
+use super::Writable;
 
 pub struct Writer {
     inner: Mutex<WriterInner>,
 }
 
 struct WriterInner {
-    file: WritableFileImpl,
+    file: Box<dyn Writable>,
     offset: u64,
 }
 impl WriterInner {
-    pub fn new(file: WritableFileImpl) -> Self {
-        Self { file, offset: 0 }
+    pub fn new(file: Box<dyn Writable>, offset: u64) -> Self {
+        Self { file, offset }
     }
 }
 
 impl Writer {
-    pub fn new(file: WritableFileImpl) -> Self {
+    pub fn new(file: impl Writable) -> Self {
+        Self::with_offset(file, 0)
+    }
+
+    /// A writer appending records after the first `offset` bytes of `file`.
+    pub fn with_offset(file: impl Writable, offset: u64) -> Self {
         Self {
-            inner: Mutex::new(WriterInner::new(file)),
+            inner: Mutex::new(WriterInner::new(Box::new(file), offset)),
         }
     }
 
-    pub fn add_recore(&self, data: &[u8]) -> Result<(), anyhow::Error> {
+    pub fn add_recore(&self, data: &[u8]) -> Result<(), Error> {
         let checksum = calculate_checksum(data);
         let compressed = lz4_flex::compress_prepend_size(data);
         let mut buf = Vec::new();
@@ -45,31 +50,19 @@ impl Writer {
         Ok(())
     }
 
-    pub fn add_recore_batch(&self, data: &Vec<Bytes>) -> Result<(), anyhow::Error> {
-        let mut buf = Vec::new();
-        for b in data {
-            let checksum = calculate_checksum(b);
-            let compressed = lz4_flex::compress_prepend_size(b);
-            buf.put_u64(checksum);
-            // let mut buf = checksum.to_le_bytes().to_vec();
-            buf.put_u32(compressed.len() as u32);
-            buf.put_slice(&compressed);
-        }
-
-        let mut inner = self.inner.lock();
-        inner.file.append(&buf)?;
-
-        inner.offset += buf.len() as u64;
-        Ok(())
-    }
-
     pub fn offset(&self) -> u64 {
         self.inner.lock().offset
     }
 
-    pub fn flush(&mut self) -> Result<(), Error> {
+    pub fn flush(&self) -> Result<(), Error> {
         let mut inner = self.inner.lock();
         inner.file.flush()?;
         Ok(())
     }
+
+    pub fn sync(&self) -> Result<(), Error> {
+        let mut inner = self.inner.lock();
+        inner.file.sync()?;
+        Ok(())
+    }
 }
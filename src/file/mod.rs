@@ -15,13 +15,19 @@ pub use writeable::*;
 pub trait SequentialAccess {
     // read n bytes
     fn read(&mut self, buf: &mut [u8]) -> Result<(), Error>;
+
+    // name of the file, for error reports
+    fn name(&self) -> &str;
 }
 
-pub trait RandomAccess {
+pub trait RandomAccess: Send + Sync {
     // read n bytes
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<(), Error>;
 
     fn size(&self) -> Result<u64, Error>;
+
+    // name of the file, for error reports
+    fn name(&self) -> &str;
 }
 
 pub trait Writable: Sync + Send + 'static {
@@ -37,6 +43,7 @@ pub trait Writable: Sync + Send + 'static {
     fn size(&self) -> Result<u64, Error>;
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum Ext {
     WAL,
     SST,
@@ -57,11 +64,13 @@ pub fn path_of_file(work_dir: &str, id: u64, ext: Ext) -> PathBuf {
     Path::new(work_dir).join(format!("{:05}{}", id, file_ext))
 }
 
+#[allow(dead_code)]
 pub enum ReadableFile {
     Sequential(SequentialFileImpl),
     Random(RandomAccessFileImpl),
 }
 
+#[allow(dead_code)]
 pub enum WritableFile {
     Sequential(WritableFileImpl),
 }
@@ -6,18 +6,17 @@ use std::path::Path;
 use super::{RandomAccess, SequentialAccess};
 
 pub struct RandomAccessFileImpl {
-    // filename: String,
-    // path: Path,
+    name: String,
     file: std::fs::File,
 }
 
 impl RandomAccessFileImpl {
-    pub fn open(path: &Path) -> Self {
-        let file = match File::open(path) {
-            Ok(f) => f,
-            Err(err) => panic!("open {} fail: {}", path.as_os_str().to_str().unwrap(), err),
-        };
-        Self { file }
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        Ok(Self {
+            name: path.display().to_string(),
+            file,
+        })
     }
 }
 
@@ -30,9 +29,14 @@ impl RandomAccess for RandomAccessFileImpl {
         let meta = self.file.metadata()?;
         Ok(meta.len())
     }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 pub struct SequentialFileImpl {
+    name: String,
     file: std::fs::File,
     offset: u64,
     // file_sz: u64,
@@ -40,17 +44,15 @@ pub struct SequentialFileImpl {
 
 impl SequentialFileImpl {
     // Open a file in read-only mode
-    pub fn new(path: &Path) -> Self {
-        let file = match File::open(path) {
-            Ok(f) => f,
-            Err(err) => panic!("{}", err),
-        };
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path)?;
         // let file_sz = file.metadata().unwrap().len();
-        Self {
+        Ok(Self {
+            name: path.display().to_string(),
             file,
             offset: 0,
             // file_sz,
-        }
+        })
     }
 }
 
@@ -60,4 +62,8 @@ impl SequentialAccess for SequentialFileImpl {
         self.offset += buf.len() as u64;
         Ok(())
     }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
 }
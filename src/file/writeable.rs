@@ -29,17 +29,14 @@ impl Writable for WritableFileImpl {
     }
 }
 impl WritableFileImpl {
-    pub fn new(path: &Path) -> Self {
-        // Open a file in write-only mode, returns `io::Result<File>`
-        let file = match File::options().append(true).create(true).open(path) {
-            Ok(f) => f,
-            Err(err) => panic!("{}", err),
-        };
-        Self {
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        // Open a file in write-only mode
+        let file = File::options().append(true).create(true).open(path)?;
+        Ok(Self {
             // filename: String::from(""),
             // path,
             file,
-        }
+        })
     }
 }
 
@@ -55,7 +52,7 @@ mod file_test {
         if std::fs::metadata(path).is_ok() {
             std::fs::remove_file(path).unwrap()
         };
-        let mut f = WritableFileImpl::new(path);
+        let mut f = WritableFileImpl::new(path).unwrap();
         f.append(b"hello ").unwrap();
         f.append(b"world!\n").unwrap();
         f.append(b"hello rust").unwrap();
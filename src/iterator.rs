@@ -0,0 +1,485 @@
+use std::{cmp::Ordering, ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+
+use crate::{
+    merge::{self, MergeOperator},
+    range_del::RangeTombstones,
+    sstable::{resolve_value, MergeIterator},
+    utils::{
+        build_internal_key, live_value, now_millis, parse_internal_key, MAX_SEQUENCE,
+        OP_TYPE_DELETE, OP_TYPE_MERGE, OP_TYPE_PUT, OP_TYPE_PUT_TTL,
+    },
+    version::Version,
+    vlog::VlogCache,
+    Comparator, Error, Result,
+};
+
+/// A bidirectional cursor over internal keys.
+///
+/// Keys are internal keys (`user_key | seq << 8 | type`) ordered by
+/// [`compare_internal_key`](crate::utils::compare_internal_key) with the
+/// comparator of the storage. `key` and `value` may only be called while
+/// `valid` returns true.
+pub trait InternalIterator {
+    fn valid(&self) -> bool;
+
+    fn seek_to_first(&mut self);
+
+    fn seek_to_last(&mut self);
+
+    /// Position at the first entry whose key is at or past `target`.
+    fn seek(&mut self, target: &[u8]);
+
+    /// Position at the last entry whose key is at or before `target`.
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.seek(target);
+        if !self.valid() {
+            self.seek_to_last();
+        } else if self.key() != target {
+            // past the target, as seek stops at or past it
+            self.prev();
+        }
+    }
+
+    fn next(&mut self);
+
+    fn prev(&mut self);
+
+    fn key(&self) -> &[u8];
+
+    fn value(&self) -> &[u8];
+
+    /// The first error met while iterating. An iterator that fails becomes
+    /// invalid rather than skipping data, so callers check this once it ends.
+    fn status(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// An iterator over the user keys of an [`Lsm`](crate::lsm::Lsm).
+///
+/// It merges the mem-tables and every table of a pinned version, yields only
+/// the newest version of each key visible at the read sequence, hides
+/// deletions, expired puts and keys covered by range tombstones, combines
+/// merge operands with the value below them, and reads separated values back
+/// from the vlog.
+///
+/// The iterator becomes invalid on the first error it meets, which is then
+/// reported by `status`.
+pub struct LsmIterator {
+    iter: MergeIterator,
+    ucmp: Arc<dyn Comparator>,
+    version: Arc<Version>,
+    // children before this index are mem-tables, whose values are stored raw
+    num_mems: usize,
+    sequence: u64,
+    // expiring puts are read as of when the iterator was created
+    now: u64,
+    range_dels: RangeTombstones,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    direction: Direction,
+    valid: bool,
+    key: Vec<u8>,
+    value: Bytes,
+    // value of `key` while scanning backward, as stored in its source, or
+    // raw once an expiring put is resolved
+    saved_value: Vec<u8>,
+    saved_in_mem: bool,
+    // merge operands of `key` while scanning backward, oldest first
+    saved_operands: Vec<(Vec<u8>, bool)>,
+    status: Option<Error>,
+}
+
+impl LsmIterator {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        iter: MergeIterator,
+        ucmp: Arc<dyn Comparator>,
+        version: Arc<Version>,
+        num_mems: usize,
+        sequence: u64,
+        range_dels: RangeTombstones,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        lower: Bound<Vec<u8>>,
+        upper: Bound<Vec<u8>>,
+    ) -> Self {
+        Self {
+            iter,
+            ucmp,
+            version,
+            num_mems,
+            sequence,
+            now: now_millis(),
+            range_dels,
+            merge_operator,
+            lower,
+            upper,
+            direction: Direction::Forward,
+            valid: false,
+            key: Vec::new(),
+            value: Bytes::new(),
+            saved_value: Vec::new(),
+            saved_in_mem: false,
+            saved_operands: Vec::new(),
+            status: None,
+        }
+    }
+
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+
+    /// The user key of the current entry.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// The error that stopped the iteration, if any.
+    pub fn status(&self) -> Result<()> {
+        match &self.status {
+            Some(err) => Err(err.clone()),
+            None => self.iter.status(),
+        }
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.direction = Direction::Forward;
+        match &self.lower {
+            Bound::Unbounded => self.iter.seek_to_first(),
+            Bound::Included(lower) | Bound::Excluded(lower) => {
+                let target = build_internal_key(lower, self.sequence, OP_TYPE_PUT);
+                self.iter.seek(&target);
+            }
+        }
+        let skipping = matches!(self.lower, Bound::Excluded(_));
+        if let Bound::Excluded(lower) = &self.lower {
+            self.key = lower.clone();
+        }
+        self.find_next_user_entry(skipping);
+        self.check_upper_bound();
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.direction = Direction::Reverse;
+        match &self.upper {
+            Bound::Unbounded => self.iter.seek_to_last(),
+            Bound::Included(upper) => {
+                let target = build_internal_key(upper, 0, OP_TYPE_PUT);
+                self.iter.seek_for_prev(&target);
+            }
+            Bound::Excluded(upper) => {
+                let target = build_internal_key(upper, MAX_SEQUENCE, OP_TYPE_PUT);
+                self.iter.seek_for_prev(&target);
+            }
+        }
+        self.find_prev_user_entry();
+        self.check_lower_bound();
+    }
+
+    /// Position at the first key at or past `key`.
+    pub fn seek(&mut self, key: &[u8]) {
+        if self.below_lower_bound(key) {
+            return self.seek_to_first();
+        }
+        self.direction = Direction::Forward;
+        let target = build_internal_key(key, self.sequence, OP_TYPE_PUT);
+        self.iter.seek(&target);
+        self.find_next_user_entry(false);
+        self.check_upper_bound();
+    }
+
+    /// Position at the last key at or before `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        if self.beyond_upper_bound(key) {
+            return self.seek_to_last();
+        }
+        self.direction = Direction::Reverse;
+        let target = build_internal_key(key, 0, OP_TYPE_PUT);
+        self.iter.seek_for_prev(&target);
+        self.find_prev_user_entry();
+        self.check_lower_bound();
+    }
+
+    pub fn next(&mut self) {
+        if !self.valid {
+            return;
+        }
+        if self.direction == Direction::Reverse {
+            // the merged iterator is just before the entries of key(),
+            // step into them and let them be skipped below
+            self.direction = Direction::Forward;
+            if self.iter.valid() {
+                InternalIterator::next(&mut self.iter);
+            } else {
+                self.iter.seek_to_first();
+            }
+        } else {
+            InternalIterator::next(&mut self.iter);
+        }
+        self.find_next_user_entry(true);
+        self.check_upper_bound();
+    }
+
+    pub fn prev(&mut self) {
+        if !self.valid {
+            return;
+        }
+        if self.direction == Direction::Forward {
+            // the merged iterator is at the current entry, scan backward
+            // until the user key changes
+            loop {
+                self.iter.prev();
+                if !self.iter.valid() {
+                    self.valid = false;
+                    self.key.clear();
+                    return;
+                }
+                let (user_key, _, _) = parse_internal_key(self.iter.key());
+                if self.ucmp.compare(user_key, &self.key) == Ordering::Less {
+                    break;
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.find_prev_user_entry();
+        self.check_lower_bound();
+    }
+
+    // With `skipping` set, entries whose user key is not past `self.key`
+    // are hidden.
+    fn find_next_user_entry(&mut self, mut skipping: bool) {
+        while self.iter.valid() {
+            let (user_key, seq, typ) = parse_internal_key(self.iter.key());
+            if seq <= self.sequence {
+                if typ == OP_TYPE_DELETE || self.range_dels.covers(user_key, seq) {
+                    self.key = user_key.to_vec();
+                    skipping = true;
+                } else if !(skipping && self.ucmp.compare(user_key, &self.key) != Ordering::Greater)
+                {
+                    self.key = user_key.to_vec();
+                    let value = if typ == OP_TYPE_MERGE {
+                        self.merge_forward().map(Some)
+                    } else {
+                        self.resolve_current()
+                            .and_then(|value| live_value(typ, value, self.now))
+                    };
+                    if let Ok(None) = value {
+                        // expired, it hides the older entries like a deletion
+                        skipping = true;
+                    } else {
+                        self.set_value(value.map(Option::unwrap_or_default));
+                        return;
+                    }
+                }
+            }
+            InternalIterator::next(&mut self.iter);
+        }
+        self.valid = false;
+        self.key.clear();
+    }
+
+    fn find_prev_user_entry(&mut self) {
+        let mut value_type = OP_TYPE_DELETE;
+        // whether a value is below the merge operands of key()
+        let mut existing = false;
+        self.saved_operands.clear();
+        while self.iter.valid() {
+            let (user_key, seq, typ) = parse_internal_key(self.iter.key());
+            if seq <= self.sequence {
+                if value_type != OP_TYPE_DELETE
+                    && self.ucmp.compare(user_key, &self.key) == Ordering::Less
+                {
+                    // the newest visible entry of key() has been seen
+                    break;
+                }
+                value_type = if self.range_dels.covers(user_key, seq) {
+                    OP_TYPE_DELETE
+                } else {
+                    typ
+                };
+                let mut in_mem = self.iter.current_index().unwrap() < self.num_mems;
+                let mut value = self.iter.value().to_vec();
+                if value_type == OP_TYPE_PUT_TTL {
+                    // the deadline is part of the value, which may be in the
+                    // vlog, so it is resolved now and kept raw
+                    let live = Self::resolve(self.version.vlog_cache(), &value, in_mem)
+                        .and_then(|value| live_value(typ, value, self.now));
+                    match live {
+                        Ok(Some(live)) => {
+                            (value, in_mem) = (live.to_vec(), true);
+                            value_type = OP_TYPE_PUT;
+                        }
+                        Ok(None) => value_type = OP_TYPE_DELETE,
+                        Err(err) => {
+                            self.set_value(Err(err));
+                            return;
+                        }
+                    }
+                }
+                if value_type == OP_TYPE_DELETE {
+                    self.key.clear();
+                    self.saved_value.clear();
+                    self.saved_operands.clear();
+                    existing = false;
+                } else if value_type == OP_TYPE_MERGE {
+                    self.key = user_key.to_vec();
+                    self.saved_operands.push((value, in_mem));
+                } else {
+                    self.key = user_key.to_vec();
+                    self.saved_value = value;
+                    self.saved_in_mem = in_mem;
+                    self.saved_operands.clear();
+                    existing = true;
+                }
+            }
+            self.iter.prev();
+        }
+
+        if value_type == OP_TYPE_DELETE {
+            self.valid = false;
+            self.key.clear();
+            self.saved_value.clear();
+            self.direction = Direction::Forward;
+        } else if value_type == OP_TYPE_MERGE {
+            let value = self.merge_backward(existing);
+            self.set_value(value);
+        } else {
+            let value = Self::resolve(
+                self.version.vlog_cache(),
+                &self.saved_value,
+                self.saved_in_mem,
+            );
+            self.set_value(value);
+        }
+    }
+
+    // Combine the merge operands of key(), from the newest visible one the
+    // merged iterator is at down to the value or deletion ending them. The
+    // merged iterator is put back at the newest operand.
+    fn merge_forward(&mut self) -> Result<Bytes> {
+        let mut operands = vec![];
+        let mut existing = None;
+        while self.iter.valid() {
+            let (user_key, seq, typ) = parse_internal_key(self.iter.key());
+            if user_key != &self.key[..]
+                || typ == OP_TYPE_DELETE
+                || self.range_dels.covers(user_key, seq)
+            {
+                break;
+            }
+            let value = self.resolve_current()?;
+            if typ != OP_TYPE_MERGE {
+                existing = live_value(typ, value, self.now)?;
+                break;
+            }
+            operands.push(value);
+            InternalIterator::next(&mut self.iter);
+        }
+        let target = build_internal_key(&self.key, self.sequence, OP_TYPE_PUT);
+        self.iter.seek(&target);
+        merge::full_merge(
+            self.merge_operator.as_deref(),
+            &self.key,
+            existing.as_deref(),
+            &operands,
+        )
+    }
+
+    // Combine the merge operands of key() saved while scanning backward with
+    // the value below them, if `existing`.
+    fn merge_backward(&self, existing: bool) -> Result<Bytes> {
+        let vlog = self.version.vlog_cache();
+        let existing = if existing {
+            Some(Self::resolve(vlog, &self.saved_value, self.saved_in_mem)?)
+        } else {
+            None
+        };
+        let operands = self
+            .saved_operands
+            .iter()
+            .rev()
+            .map(|(operand, in_mem)| Self::resolve(vlog, operand, *in_mem))
+            .collect::<Result<Vec<_>>>()?;
+        merge::full_merge(
+            self.merge_operator.as_deref(),
+            &self.key,
+            existing.as_deref(),
+            &operands,
+        )
+    }
+
+    // the value of the entry the merged iterator is at
+    fn resolve_current(&self) -> Result<Bytes> {
+        let in_mem = self.iter.current_index().unwrap() < self.num_mems;
+        Self::resolve(self.version.vlog_cache(), self.iter.value(), in_mem)
+    }
+
+    fn resolve(vlog: &VlogCache, value: &[u8], in_mem: bool) -> Result<Bytes> {
+        if in_mem {
+            Ok(Bytes::copy_from_slice(value))
+        } else {
+            resolve_value(vlog, value)
+        }
+    }
+
+    fn set_value(&mut self, value: Result<Bytes>) {
+        match value {
+            Ok(value) => {
+                self.value = value;
+                self.valid = true;
+            }
+            Err(err) => {
+                self.status.get_or_insert(err);
+                self.valid = false;
+                self.key.clear();
+            }
+        }
+    }
+
+    fn check_upper_bound(&mut self) {
+        if self.valid && self.beyond_upper_bound(&self.key) {
+            self.valid = false;
+        }
+    }
+
+    fn check_lower_bound(&mut self) {
+        if self.valid && self.below_lower_bound(&self.key) {
+            self.valid = false;
+        }
+    }
+
+    fn beyond_upper_bound(&self, key: &[u8]) -> bool {
+        match &self.upper {
+            Bound::Included(upper) => self.ucmp.compare(key, upper) == Ordering::Greater,
+            Bound::Excluded(upper) => self.ucmp.compare(key, upper) != Ordering::Less,
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn below_lower_bound(&self, key: &[u8]) -> bool {
+        match &self.lower {
+            Bound::Included(lower) => self.ucmp.compare(key, lower) == Ordering::Less,
+            Bound::Excluded(lower) => self.ucmp.compare(key, lower) != Ordering::Greater,
+            Bound::Unbounded => false,
+        }
+    }
+}
+
+impl Drop for LsmIterator {
+    fn drop(&mut self) {
+        self.version.derefs();
+    }
+}
@@ -1,34 +1,103 @@
+use std::sync::Arc;
+
 mod cache;
+mod compaction_filter;
 mod compactor;
+mod comparator;
+mod compression;
+mod error;
 mod file;
+mod iterator;
+mod lock_manager;
 pub mod lsm;
 mod mem_table;
+mod merge;
+mod range_del;
+pub mod snapshot;
 mod sstable;
+pub mod transaction;
+pub mod transaction_db;
 mod utils;
 mod version;
+mod vlog;
 pub mod write_batch;
 
+pub use cache::CacheStats;
+pub use compaction_filter::{CompactionDecision, CompactionFilter};
+pub use comparator::{BytewiseComparator, Comparator};
+pub use compression::CompressionType;
+pub use error::{Error, Result};
+pub use merge::MergeOperator;
+use snapshot::Snapshot;
+pub use sstable::TableProperties;
+pub use version::{ColumnFamily, ColumnFamilyDescriptor, DEFAULT_COLUMN_FAMILY};
+pub use vlog::{VlogEntry, VlogIterator};
+
 #[derive(Clone, Debug)]
 pub struct Options {
     pub block_size: usize,
+    /// Entries between the keys a data block keeps in full, the others keep
+    /// only what they do not share with the key before.
+    pub block_restart_interval: usize,
+    /// Compression of the blocks of tables, unless `compression_per_level`
+    /// names one for their level.
+    pub compression: CompressionType,
+    /// Compression of the tables of each level from level 0 on, the last
+    /// also for the levels below. Empty to compress all by `compression`.
+    pub compression_per_level: Vec<CompressionType>,
+    /// Bits of the bloom filter of a table per key, 0 to size the filter for
+    /// `bloom_false_positive`.
+    pub bloom_bits_per_key: usize,
+    /// Share of absent keys the bloom filter of a table lets through, unless
+    /// `bloom_bits_per_key` is set.
+    pub bloom_false_positive: f64,
     pub work_dir: String,
     pub mem_size: usize,
     pub cache_size: usize,
+    /// Number of vlog files kept open for reading separated values.
+    pub vlog_cache_size: usize,
+    /// Bytes of separated values cached, 0 to disable the value cache.
+    pub value_cache_size: usize,
     pub kv_separate_threshold: usize,
     pub allow_miss_count: usize,
     pub allow_miss_size: usize,
+    /// Share of garbage in the vlogs of a table at which background gc
+    /// rewrites it.
+    pub value_log_gc_ratio: f64,
+    /// Write the mem-tables to level 0 when the storage is closed, so the
+    /// next open has no wal to replay.
+    pub flush_on_close: bool,
+    /// Combines the operands written by `merge`, which fails without one.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Orders the keys. A storage cannot be reopened with a comparator of
+    /// another name.
+    pub comparator: Arc<dyn Comparator>,
+    /// Asked about each value compaction keeps, see [`CompactionFilter`].
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
 }
 
 impl Options {
     pub fn default_opt() -> Options {
         Options {
             block_size: 1 << 12, // 4K
+            block_restart_interval: 16,
+            compression: CompressionType::Lz4,
+            compression_per_level: Vec::new(),
+            bloom_bits_per_key: 0,
+            bloom_false_positive: 0.1,
             work_dir: "work_dir".to_string(),
             mem_size: 1 << 21,   // 2M
             cache_size: 1 << 22, // 4M
+            vlog_cache_size: 256,
+            value_cache_size: 0,
             kv_separate_threshold: 128,
             allow_miss_size: 1 << 12, // 4 K
             allow_miss_count: 100,
+            value_log_gc_ratio: 0.5,
+            flush_on_close: false,
+            merge_operator: None,
+            comparator: Arc::new(BytewiseComparator),
+            compaction_filter: None,
         }
     }
     pub fn mem_size(&mut self, mem_size: usize) -> Self {
@@ -41,11 +110,56 @@ impl Options {
         self.clone()
     }
 
+    pub fn block_restart_interval(&mut self, block_restart_interval: usize) -> Self {
+        self.block_restart_interval = block_restart_interval;
+        self.clone()
+    }
+
+    pub fn compression(&mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self.clone()
+    }
+
+    pub fn compression_per_level(&mut self, compression_per_level: Vec<CompressionType>) -> Self {
+        self.compression_per_level = compression_per_level;
+        self.clone()
+    }
+
+    pub fn bloom_bits_per_key(&mut self, bloom_bits_per_key: usize) -> Self {
+        self.bloom_bits_per_key = bloom_bits_per_key;
+        self.clone()
+    }
+
+    pub fn bloom_false_positive(&mut self, bloom_false_positive: f64) -> Self {
+        self.bloom_false_positive = bloom_false_positive;
+        self.clone()
+    }
+
+    /// The compression of the tables of `level`.
+    pub fn compression_for_level(&self, level: usize) -> CompressionType {
+        let per_level = &self.compression_per_level;
+        per_level
+            .get(level)
+            .or(per_level.last())
+            .copied()
+            .unwrap_or(self.compression)
+    }
+
     pub fn cache_size(&mut self, cache_size: usize) -> Self {
         self.cache_size = cache_size;
         self.clone()
     }
 
+    pub fn vlog_cache_size(&mut self, vlog_cache_size: usize) -> Self {
+        self.vlog_cache_size = vlog_cache_size;
+        self.clone()
+    }
+
+    pub fn value_cache_size(&mut self, value_cache_size: usize) -> Self {
+        self.value_cache_size = value_cache_size;
+        self.clone()
+    }
+
     pub fn work_dir(&mut self, work_dir: &str) -> Self {
         self.work_dir = String::from(work_dir);
         self.clone()
@@ -65,6 +179,68 @@ impl Options {
         self.allow_miss_size = allow_miss_size;
         self.clone()
     }
+
+    pub fn value_log_gc_ratio(&mut self, value_log_gc_ratio: f64) -> Self {
+        self.value_log_gc_ratio = value_log_gc_ratio;
+        self.clone()
+    }
+
+    pub fn flush_on_close(&mut self, flush_on_close: bool) -> Self {
+        self.flush_on_close = flush_on_close;
+        self.clone()
+    }
+
+    pub fn merge_operator(&mut self, merge_operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(merge_operator);
+        self.clone()
+    }
+
+    pub fn comparator(&mut self, comparator: Arc<dyn Comparator>) -> Self {
+        self.comparator = comparator;
+        self.clone()
+    }
+
+    pub fn compaction_filter(&mut self, compaction_filter: Arc<dyn CompactionFilter>) -> Self {
+        self.compaction_filter = Some(compaction_filter);
+        self.clone()
+    }
+}
+
+/// Options for a single read.
+#[derive(Clone, Default)]
+pub struct ReadOptions {
+    /// Read as of this snapshot instead of the latest state.
+    pub snapshot: Option<Snapshot>,
+}
+
+impl ReadOptions {
+    pub fn snapshot(&mut self, snapshot: &Snapshot) -> Self {
+        self.snapshot = Some(snapshot.clone());
+        self.clone()
+    }
+}
+
+/// Options for a single write.
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// Sync the wal before the write returns, so it survives a machine crash
+    /// rather than only a process crash.
+    pub sync: bool,
+    /// Skip the wal. The write is lost if the process crashes before its
+    /// mem-table is flushed.
+    pub disable_wal: bool,
+}
+
+impl WriteOptions {
+    pub fn sync(&mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self.clone()
+    }
+
+    pub fn disable_wal(&mut self, disable_wal: bool) -> Self {
+        self.disable_wal = disable_wal;
+        self.clone()
+    }
 }
 
 #[cfg(test)]
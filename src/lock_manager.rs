@@ -0,0 +1,204 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::{Error, Result};
+
+/// Exclusive key locks of pessimistic transactions, named by transaction id.
+///
+/// Keys are spread over stripes, each with its own mutex and condvar, so
+/// transactions locking unrelated keys rarely wait on each other. A lock is
+/// held until [`unlock_all`](LockManager::unlock_all) and is reentrant for
+/// its holder.
+pub(crate) struct LockManager {
+    stripes: Vec<LockStripe>,
+    // the wait-for graph: each waiting transaction to the holder of the lock
+    // it waits on. A transaction waits on one lock at a time, so one edge.
+    wait_for: Mutex<HashMap<u64, u64>>,
+    deadlock_detect: bool,
+}
+
+struct LockStripe {
+    // locked keys to their holder
+    holders: Mutex<HashMap<Vec<u8>, u64>>,
+    released: Condvar,
+}
+
+impl LockManager {
+    pub(crate) fn new(num_stripes: usize, deadlock_detect: bool) -> Self {
+        let stripes = (0..num_stripes.max(1))
+            .map(|_| LockStripe {
+                holders: Mutex::new(HashMap::new()),
+                released: Condvar::new(),
+            })
+            .collect();
+        Self {
+            stripes,
+            wait_for: Mutex::new(HashMap::new()),
+            deadlock_detect,
+        }
+    }
+
+    fn stripe(&self, key: &[u8]) -> &LockStripe {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.stripes[hasher.finish() as usize % self.stripes.len()]
+    }
+
+    /// Lock `key` for transaction `txn`, waiting at most `timeout` for its
+    /// holder to release it.
+    ///
+    /// Fails with [`Deadlock`](Error::Deadlock) instead of waiting if the
+    /// holder is, directly or not, waiting on `txn`, and with
+    /// [`TimedOut`](Error::TimedOut) if the lock is still held at the
+    /// deadline.
+    pub(crate) fn lock(&self, txn: u64, key: &[u8], timeout: Duration) -> Result<()> {
+        let stripe = self.stripe(key);
+        let deadline = Instant::now() + timeout;
+        let mut holders = stripe.holders.lock();
+        let result = loop {
+            let holder = match holders.get(key) {
+                None => {
+                    holders.insert(key.to_vec(), txn);
+                    break Ok(());
+                }
+                Some(&holder) if holder == txn => break Ok(()),
+                Some(&holder) => holder,
+            };
+            if self.deadlock_detect {
+                if let Err(err) = self.add_wait(txn, holder, key) {
+                    break Err(err);
+                }
+            }
+            if stripe
+                .released
+                .wait_until(&mut holders, deadline)
+                .timed_out()
+                && holders.get(key).is_some_and(|&h| h != txn)
+            {
+                break Err(Error::TimedOut(format!("lock on key {:?}", key)));
+            }
+        };
+        drop(holders);
+        if self.deadlock_detect {
+            self.wait_for.lock().remove(&txn);
+        }
+        result
+    }
+
+    // Record that `waiter` waits on `holder`, unless the chain of waits from
+    // `holder` leads back to `waiter`.
+    fn add_wait(&self, waiter: u64, holder: u64, key: &[u8]) -> Result<()> {
+        let mut wait_for = self.wait_for.lock();
+        let mut next = holder;
+        // the bound guards against a cycle left by stale edges
+        for _ in 0..=wait_for.len() {
+            if next == waiter {
+                wait_for.remove(&waiter);
+                return Err(Error::Deadlock(format!(
+                    "transaction {} waiting on key {:?} held by transaction {}",
+                    waiter, key, holder
+                )));
+            }
+            match wait_for.get(&next) {
+                Some(&n) => next = n,
+                None => break,
+            }
+        }
+        wait_for.insert(waiter, holder);
+        Ok(())
+    }
+
+    /// Release the locks `txn` holds on `keys`, waking their waiters.
+    pub(crate) fn unlock_all<'a>(&self, txn: u64, keys: impl IntoIterator<Item = &'a Vec<u8>>) {
+        for key in keys {
+            let stripe = self.stripe(key);
+            let mut holders = stripe.holders.lock();
+            if holders.get(key) == Some(&txn) {
+                holders.remove(key);
+                stripe.released.notify_all();
+            }
+        }
+        if self.deadlock_detect {
+            // waiters re-add their edge to whoever takes the lock next
+            self.wait_for.lock().retain(|_, holder| *holder != txn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod lock_manager_test {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use crate::Error;
+
+    use super::LockManager;
+
+    #[test]
+    fn lock_timeout_test() {
+        let locks = LockManager::new(4, true);
+        let timeout = Duration::from_millis(50);
+        locks.lock(1, b"k1", timeout).unwrap();
+        // reentrant for the holder
+        locks.lock(1, b"k1", timeout).unwrap();
+        locks.lock(2, b"k2", timeout).unwrap();
+
+        assert!(matches!(
+            locks.lock(2, b"k1", timeout),
+            Err(Error::TimedOut(_))
+        ));
+        locks.unlock_all(1, &[b"k1".to_vec()]);
+        locks.lock(2, b"k1", timeout).unwrap();
+    }
+
+    #[test]
+    fn lock_wait_test() {
+        let locks = Arc::new(LockManager::new(4, true));
+        locks.lock(1, b"k1", Duration::ZERO).unwrap();
+
+        let waiter = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || locks.lock(2, b"k1", Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        locks.unlock_all(1, &[b"k1".to_vec()]);
+        waiter.join().unwrap().unwrap();
+        assert!(matches!(
+            locks.lock(1, b"k1", Duration::ZERO),
+            Err(Error::TimedOut(_))
+        ));
+    }
+
+    #[test]
+    fn deadlock_detect_test() {
+        let locks = Arc::new(LockManager::new(4, true));
+        locks.lock(1, b"k1", Duration::ZERO).unwrap();
+        locks.lock(2, b"k2", Duration::ZERO).unwrap();
+        locks.lock(3, b"k3", Duration::ZERO).unwrap();
+
+        // 1 waits on 2, 2 waits on 3
+        let waiters: Vec<_> = [(1, b"k2"), (2, b"k3")]
+            .into_iter()
+            .map(|(txn, key)| {
+                let locks = Arc::clone(&locks);
+                thread::spawn(move || locks.lock(txn, key, Duration::from_secs(10)))
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(50));
+
+        // 3 waiting on 1 would close the cycle
+        assert!(matches!(
+            locks.lock(3, b"k1", Duration::from_secs(10)),
+            Err(Error::Deadlock(_))
+        ));
+        locks.unlock_all(3, &[b"k3".to_vec()]);
+        locks.unlock_all(2, &[b"k2".to_vec()]);
+        for waiter in waiters {
+            waiter.join().unwrap().unwrap();
+        }
+    }
+}
@@ -1,38 +1,54 @@
 use std::{
-    collections::VecDeque,
+    collections::{BTreeMap, VecDeque},
+    ops::{Bound, RangeBounds},
     path::Path,
     sync::{
+        atomic::{AtomicBool, Ordering},
         mpsc::{sync_channel, SyncSender},
         Arc,
     },
+    thread::JoinHandle,
+    time::Duration,
 };
 
-use anyhow::Ok;
-use bytes::{Buf, BufMut, Bytes};
-use parking_lot::RwLock;
-use tracing::info;
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex, RwLock};
+use tracing::{error, info, warn};
 
 use crate::{
-    compactor::{Compactor, SeekTask, Task},
+    compactor::{CompactionState, Compactor, SeekTask, Task},
     file::{path_of_file, Ext, Reader, SequentialFileImpl, WritableFileImpl, Writer},
+    iterator::InternalIterator,
     mem_table::{MemTable, MemTableIterator},
-    sstable::TableBuilder,
+    merge,
+    range_del::RangeTombstones,
+    snapshot::Snapshot,
+    sstable::{MergeIterator, TableBuilder, TableProperties},
+    transaction::Transaction,
     utils::{
-        codec::{decode_varintu32, encode_varintu32, varintu32_length},
-        Entry, OP_TYPE_PUT,
+        live_value, now_millis, Entry, OP_TYPE_DELETE, OP_TYPE_MERGE, OP_TYPE_PUT, OP_TYPE_PUT_TTL,
     },
-    version::{FileMetaData, Version, VersionEdit, VersionSet},
+    version::{ColumnFamilyData, FileMetaData, FoundEntry, Version, VersionEdit, VersionSet},
     write_batch::WriteBatch,
-    Options,
+    CacheStats, ColumnFamily, ColumnFamilyDescriptor, Error, Options, ReadOptions, Result,
+    WriteOptions,
 };
 
-type Result<T> = anyhow::Result<T, anyhow::Error>;
+pub use crate::iterator::LsmIterator;
+
+/// The mem-table of each column family, by family id.
+type MemTables = BTreeMap<u32, Arc<MemTable>>;
+// the value of each key looked up by a multi-get, and the compaction it calls
+// for
+type MultiGetResult = (Vec<Result<Option<Vec<u8>>>>, Option<Task>);
 
 struct MemInner {
-    mem: Arc<MemTable>,
-    imms: VecDeque<Arc<MemTable>>,
+    mem: MemTables,
+    // the mem-tables of all families are switched together, so the wal they
+    // share is dropped once the whole group is flushed
+    imms: VecDeque<MemTables>,
     logs: VecDeque<u64>,
-    wal: Writer,
+    wal: Arc<Writer>,
     #[allow(unused)]
     log_buf: Vec<u8>,
     #[allow(unused)]
@@ -40,41 +56,126 @@ struct MemInner {
 }
 
 impl MemInner {
-    fn new(opt: Options, next_file_id: u64) -> Self {
+    fn new(opt: Options, next_file_id: u64) -> Result<Self> {
         let logs = VecDeque::new();
         // logs.push_back(next_file_id);
-        Self {
+        Ok(Self {
             // mem_inner: Arc::new(RwLock::new(Arc::new(MemInner::new(opt.clone(), new_fid)))),
-            mem: Arc::new(MemTable::new()),
+            // filled once the families are recovered
+            mem: BTreeMap::new(),
             imms: VecDeque::new(),
             logs,
-            wal: Writer::new(WritableFileImpl::new(&path_of_file(
+            wal: Arc::new(Writer::new(WritableFileImpl::new(&path_of_file(
                 &opt.work_dir,
                 next_file_id,
                 Ext::WAL,
-            ))),
+            ))?)),
             log_buf: Vec::new(),
             miss_count: 0,
+        })
+    }
+}
+
+// upper bound of the bytes committed by one write group
+const MAX_GROUP_SIZE: usize = 1 << 20;
+
+/// Keys read or written by a transaction as of `sequence`, each with its
+/// column family. The transaction commits only if none of them was written
+/// since.
+pub(crate) struct ConflictCheck {
+    pub sequence: u64,
+    pub keys: Vec<(u32, Vec<u8>)>,
+}
+
+/// A write waiting in the writer queue of [`LsmInner::write`].
+struct PendingWrite {
+    batch: WriteBatch,
+    opt: WriteOptions,
+    // checked by this writer once it leads, it never joins another group
+    check: Option<ConflictCheck>,
+    // set by the leader that committed the write on behalf of this writer
+    result: Mutex<Option<Result<()>>>,
+}
+
+impl PendingWrite {
+    fn new(batch: WriteBatch, opt: WriteOptions, check: Option<ConflictCheck>) -> Self {
+        Self {
+            batch,
+            opt,
+            check,
+            result: Mutex::new(None),
         }
     }
 }
 
 pub struct LsmInner {
     mem_inner: Arc<RwLock<MemInner>>,
+    writers: Mutex<VecDeque<Arc<PendingWrite>>>,
+    write_cv: Condvar,
     version: Arc<VersionSet>,
     // imms: Vec<Arc<MemTable>>,
     opt: Options,
+    // the first error of a background compaction
+    bg_error: Mutex<Option<Error>>,
+    // the error of a failed wal write or sync, after which a record may be
+    // torn, so every later write fails with it
+    wal_error: Mutex<Option<Error>>,
+    closing: AtomicBool,
+    // held by every task that replaces tables of level 1 and beyond
+    compaction_lock: Mutex<()>,
+    // held while the oldest immutable mem-table is flushed
+    flush_lock: Mutex<()>,
 }
 impl LsmInner {
-    fn new(opt: Options) -> Self {
-        let version = Arc::new(VersionSet::new(opt.clone()));
+    fn new(opt: Options) -> Result<Self> {
+        let version = Arc::new(VersionSet::new(opt.clone())?);
         let next_file_id = version.new_file_number();
-        Self {
-            mem_inner: Arc::new(RwLock::new(MemInner::new(opt.clone(), next_file_id))),
+        Ok(Self {
+            mem_inner: Arc::new(RwLock::new(MemInner::new(opt.clone(), next_file_id)?)),
+            writers: Mutex::new(VecDeque::new()),
+            write_cv: Condvar::new(),
             version,
             opt,
+            bg_error: Mutex::new(None),
+            wal_error: Mutex::new(None),
+            closing: AtomicBool::new(false),
+            compaction_lock: Mutex::new(()),
+            flush_lock: Mutex::new(()),
+        })
+    }
+    pub fn options(&self) -> &Options {
+        &self.opt
+    }
+
+    pub fn create_column_family(&self, name: &str, opt: Options) -> Result<ColumnFamily> {
+        if name.is_empty() {
+            return Err(Error::InvalidArgument(
+                "empty column family name".to_string(),
+            ));
         }
+        let cf = self
+            .version
+            .create_column_family(name, self.column_family_options(opt))?;
+        self.mem_inner
+            .write()
+            .mem
+            .entry(cf.id)
+            .or_insert_with(|| self.new_mem_table());
+        Ok(cf.handle())
+    }
+
+    // the files of every family live in the directory of the storage, and its
+    // keys are ordered as those of the storage
+    fn column_family_options(&self, mut opt: Options) -> Options {
+        opt.work_dir = self.opt.work_dir.clone();
+        opt.comparator = Arc::clone(&self.opt.comparator);
+        opt
+    }
+
+    fn new_mem_table(&self) -> Arc<MemTable> {
+        Arc::new(MemTable::new(Arc::clone(&self.opt.comparator)))
     }
+
     pub fn imms_sz(&self) -> usize {
         let snap = self.mem_inner.read();
         snap.imms.len()
@@ -82,60 +183,275 @@ impl LsmInner {
     fn try_make_room(&self) -> Result<bool> {
         let mut mem_inner = self.mem_inner.write();
         // let mut snap = mem_inner.as_ref().clone();
-        if mem_inner.mem.approximate_memory_usage() > self.opt.mem_size as u64 {
-            // switch memtable
-            let imm = std::mem::replace(&mut mem_inner.mem, Arc::new(MemTable::new()));
+        let full = mem_inner.mem.iter().any(|(cf, m)| {
+            let mem_size = self
+                .version
+                .column_family(*cf)
+                .map_or(self.opt.mem_size, |cf| cf.options().mem_size);
+            m.approximate_memory_usage() > mem_size as u64
+        });
+        if full {
+            self.switch_mem_table(&mut mem_inner)?;
+            return Ok(true);
+        }
+        Ok(mem_inner.imms.len() > 3)
+    }
 
-            mem_inner.imms.push_back(imm);
+    // Turn the mem-tables into immutable ones and start a new wal for their
+    // successors.
+    fn switch_mem_table(&self, mem_inner: &mut MemInner) -> Result<()> {
+        // switch memtable
+        let fresh = mem_inner
+            .mem
+            .keys()
+            .map(|cf| (*cf, self.new_mem_table()))
+            .collect();
+        let imm = std::mem::replace(&mut mem_inner.mem, fresh);
 
-            // switch wal
-            mem_inner.wal.flush()?;
+        mem_inner.imms.push_back(imm);
 
-            let next_file_id = self.version.new_file_number();
-            mem_inner.logs.push_back(next_file_id);
-            let wal = Writer::new(WritableFileImpl::new(
-                path_of_file(&self.opt.work_dir, next_file_id, Ext::WAL).as_path(),
-            ));
+        // switch wal
+        mem_inner.wal.flush()?;
 
-            let _ = std::mem::replace(&mut mem_inner.wal, wal);
-            return Ok(true);
+        let next_file_id = self.version.new_file_number();
+        mem_inner.logs.push_back(next_file_id);
+        let wal = Writer::new(WritableFileImpl::new(
+            path_of_file(&self.opt.work_dir, next_file_id, Ext::WAL).as_path(),
+        )?);
+
+        let _ = std::mem::replace(&mut mem_inner.wal, Arc::new(wal));
+        Ok(())
+    }
+
+    /// Write the mem-table and all immutable mem-tables to level 0.
+    pub fn flush_mem_tables(&self) -> Result<()> {
+        {
+            let mut mem_inner = self.mem_inner.write();
+            if mem_inner.mem.values().any(|m| !m.is_empty()) {
+                self.switch_mem_table(&mut mem_inner)?;
+            }
         }
-        Ok(mem_inner.imms.len() > 3)
+        while self.imms_sz() > 0 {
+            self.compact_mem_table()?;
+        }
+        Ok(())
+    }
+
+    /// Keep the first error of a background task, to be returned by
+    /// [`Lsm::close`].
+    pub fn record_bg_error(&self, err: Error) {
+        self.bg_error.lock().get_or_insert(err);
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
     }
 
-    pub fn delete(&self, key: &[u8]) -> Result<Option<Task>> {
+    pub fn delete(&self, key: &[u8], opt: &WriteOptions) -> Result<Option<Task>> {
         let mut batch = WriteBatch::default();
         batch.delete(key);
-        self.write(&batch)
+        self.write(&batch, opt)
+    }
+
+    pub fn delete_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        opt: &WriteOptions,
+    ) -> Result<Option<Task>> {
+        let mut batch = WriteBatch::default();
+        batch.delete_range(start, end);
+        self.write(&batch, opt)
+    }
+
+    pub fn merge(&self, key: &[u8], operand: &[u8], opt: &WriteOptions) -> Result<Option<Task>> {
+        let mut batch = WriteBatch::default();
+        batch.merge(key, operand);
+        self.write(&batch, opt)
     }
 
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<Option<Task>> {
+    pub fn put(&self, key: &[u8], value: &[u8], opt: &WriteOptions) -> Result<Option<Task>> {
         let mut batch = WriteBatch::default();
         batch.put(key, value);
-        self.write(&batch)
+        self.write(&batch, opt)
     }
 
-    pub fn write(&self, batch: &WriteBatch) -> Result<Option<Task>> {
-        let need_compact = self.try_make_room()?;
+    /// Commit `batch` through the writer queue. The writer at the front of the
+    /// queue leads: it commits its own batch together with the batches queued
+    /// behind it, sharing one wal record and one sync, and wakes them up with
+    /// the result.
+    pub fn write(&self, batch: &WriteBatch, opt: &WriteOptions) -> Result<Option<Task>> {
+        self.write_checked(batch, opt, None)
+    }
+
+    /// Commit `batch` like [`write`](Self::write), unless a key of `check` was
+    /// written after its sequence, in which case nothing is written and a
+    /// conflict is returned. No other write lands between the check and the
+    /// commit.
+    pub(crate) fn write_checked(
+        &self,
+        batch: &WriteBatch,
+        opt: &WriteOptions,
+        check: Option<ConflictCheck>,
+    ) -> Result<Option<Task>> {
+        self.check_column_families(batch)?;
+        let w = Arc::new(PendingWrite::new(batch.clone(), opt.clone(), check));
+        let mut writers = self.writers.lock();
+        writers.push_back(Arc::clone(&w));
+        loop {
+            if let Some(result) = w.result.lock().take() {
+                // committed by a leader
+                return result.map(|_| None);
+            }
+            if Arc::ptr_eq(&w, &writers[0]) {
+                break;
+            }
+            self.write_cv.wait(&mut writers);
+        }
+
+        if let Some(check) = w.check.as_ref() {
+            // the writers behind wait for this one to lead their group
+            drop(writers);
+            let checked = self.check_conflicts(check);
+            writers = self.writers.lock();
+            if let Err(err) = checked {
+                writers.pop_front();
+                self.write_cv.notify_all();
+                return Err(err);
+            }
+        }
+        let group = Self::build_write_group(&writers);
+        drop(writers);
+        let result = self.write_group(&group);
+
+        let mut writers = self.writers.lock();
+        writers.drain(..group.len());
+        for f in group.iter().skip(1) {
+            *f.result.lock() = Some(result.as_ref().map(|_| ()).map_err(Error::clone));
+        }
+        self.write_cv.notify_all();
+        result
+    }
 
-        // write wal first
-        let mut seq = self.version.add_last_sequence(batch.count as u64);
+    // Followers join the group while the total size stays small, and only if
+    // they agree with the leader on whether to log.
+    fn build_write_group(writers: &VecDeque<Arc<PendingWrite>>) -> Vec<Arc<PendingWrite>> {
+        let leader = &writers[0];
+        let mut size = leader.batch.approximate_size();
+        // keep a small write from waiting on large ones
+        let max_size = if size <= MAX_GROUP_SIZE / 8 {
+            size + MAX_GROUP_SIZE / 8
+        } else {
+            MAX_GROUP_SIZE
+        };
+        let mut group = vec![Arc::clone(leader)];
+        for w in writers.iter().skip(1) {
+            if w.check.is_some() || w.opt.disable_wal != leader.opt.disable_wal {
+                break;
+            }
+            size += w.batch.approximate_size();
+            if size > max_size {
+                break;
+            }
+            group.push(Arc::clone(w));
+        }
+        group
+    }
 
-        self.write_batch_wal(batch, seq).unwrap();
+    // Fail on an operation of a column family the storage does not have, or
+    // a merge into a family without a merge operator.
+    fn check_column_families(&self, batch: &WriteBatch) -> Result<()> {
+        let inner = self.mem_inner.read();
+        for (cf, _, op) in batch.data.iter() {
+            if !inner.mem.contains_key(cf) {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown column family {}",
+                    cf
+                )));
+            }
+            if *op == OP_TYPE_MERGE
+                && self
+                    .version
+                    .column_family(*cf)?
+                    .options()
+                    .merge_operator
+                    .is_none()
+            {
+                return Err(Error::InvalidArgument(format!(
+                    "merge without a merge operator in column family {}",
+                    cf
+                )));
+            }
+        }
+        Ok(())
+    }
 
+    // Fail with a conflict if a key of `check` was written after its sequence.
+    fn check_conflicts(&self, check: &ConflictCheck) -> Result<()> {
+        let last_sequence = self.version.last_sequence();
+        if last_sequence <= check.sequence {
+            return Ok(());
+        }
         let inner = self.mem_inner.read();
+        check.keys.iter().try_for_each(|(cf, key)| {
+            let cf = self.version.column_family(*cf)?;
+            let current = cf.current();
+            current.refs();
+            let found = self.lookup(&inner, cf.id, &current, key, last_sequence);
+            current.derefs();
+            match found? {
+                (Some((seq, _, _)), _) if seq > check.sequence => Err(Error::Conflict(format!(
+                    "key {:?} of column family {} written at sequence {} after {}",
+                    key, cf.id, seq, check.sequence
+                ))),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn write_group(&self, group: &[Arc<PendingWrite>]) -> Result<Option<Task>> {
+        if let Some(err) = self.wal_error.lock().as_ref() {
+            return Err(err.clone());
+        }
+        let need_compact = self.try_make_room()?;
 
-        // write data
-        batch.data.iter().for_each(|(e, op)| {
-            let mut entry = e.clone();
-            entry.seq = seq;
-            if *op == OP_TYPE_PUT {
-                inner.mem.put(entry);
+        // only the leader switches the mem-tables, so they stay current
+        let (mems, wal) = {
+            let inner = self.mem_inner.read();
+            (inner.mem.clone(), Arc::clone(&inner.wal))
+        };
+
+        // entries take the sequences after the last one
+        let base_seq = self.version.last_sequence() + 1;
+        if !group[0].opt.disable_wal {
+            let logged = if group.len() == 1 {
+                wal.add_recore(&group[0].batch.encode(base_seq))
             } else {
-                inner.mem.delete(entry)
+                let mut merged = WriteBatch::default();
+                group.iter().for_each(|w| merged.append(&w.batch));
+                wal.add_recore(&merged.encode(base_seq))
+            };
+            let synced = logged.and_then(|_| match group.iter().any(|w| w.opt.sync) {
+                true => wal.sync(),
+                false => Ok(()),
+            });
+            if let Err(err) = synced {
+                let err = Error::from(err);
+                self.wal_error.lock().get_or_insert_with(|| err.clone());
+                self.record_bg_error(err.clone());
+                return Err(err);
             }
-            seq += 1;
-        });
+        }
+
+        let mut seq = base_seq;
+        for w in group.iter() {
+            for (cf, e, op) in w.batch.data.iter() {
+                mems[cf].set(Entry::new(e.key.clone(), e.value.clone(), seq), *op);
+                seq += 1;
+            }
+        }
+        // publish the group to readers once all of it is in the mem-table
+        self.version.set_last_sequence(seq - 1);
 
         let task = need_compact
             .then_some(Task::Compact)
@@ -143,106 +459,371 @@ impl LsmInner {
         Ok(task)
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, Option<Task>)> {
+    fn read_sequence(&self, opt: &ReadOptions) -> u64 {
+        match &opt.snapshot {
+            Some(snapshot) => snapshot.sequence(),
+            None => self.version.last_sequence(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.version.new_snapshot()
+    }
+
+    pub fn get(&self, key: &[u8], opt: &ReadOptions) -> Result<(Option<Vec<u8>>, Option<Task>)> {
+        self.get_cf(0, key, opt)
+    }
+
+    pub fn get_cf(
+        &self,
+        cf: u32,
+        key: &[u8],
+        opt: &ReadOptions,
+    ) -> Result<(Option<Vec<u8>>, Option<Task>)> {
+        let cf = self.version.column_family(cf)?;
+        let inner = self.mem_inner.read();
+
+        let seq = self.read_sequence(opt);
+        let current = cf.current();
+        current.refs();
+        let result = self.get_merged(&inner, &cf, &current, key, seq);
+
+        current.derefs();
+        result
+    }
+
+    /// Look `keys` up at one sequence, searching the tables once for all of
+    /// them. Each key has a result of its own, in the order of `keys`.
+    pub fn multi_get_cf(
+        &self,
+        cf: u32,
+        keys: &[&[u8]],
+        opt: &ReadOptions,
+    ) -> Result<MultiGetResult> {
+        let cf = self.version.column_family(cf)?;
         let inner = self.mem_inner.read();
 
-        let seq = self.version.last_sequence();
-        // search memtable first
-        let result = inner.mem.get(key, seq);
+        let seq = self.read_sequence(opt);
+        let current = cf.current();
+        current.refs();
+        let result = self.multi_get_merged(&inner, &cf, &current, keys, seq);
+
+        current.derefs();
+        result
+    }
 
-        if let Some(result) = result {
-            if result.is_empty() {
-                // delete case
-                return Ok((None, None));
+    fn multi_get_merged(
+        &self,
+        inner: &MemInner,
+        cf: &ColumnFamilyData,
+        current: &Version,
+        keys: &[&[u8]],
+        seq: u64,
+    ) -> Result<MultiGetResult> {
+        // sort the keys and drop the repeated ones, `slots` maps each key to
+        // its distinct one
+        let ucmp = self.opt.comparator.as_ref();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|a, b| ucmp.compare(keys[*a], keys[*b]));
+        let mut distinct: Vec<&[u8]> = vec![];
+        let mut slots = vec![0; keys.len()];
+        for i in order {
+            if distinct.last() != Some(&keys[i]) {
+                distinct.push(keys[i]);
             }
-            return Ok((Some(result.to_vec()), None));
+            slots[i] = distinct.len() - 1;
+        }
+
+        let mut entries: Vec<Option<FoundEntry>> = distinct
+            .iter()
+            .map(|key| Self::lookup_mem(inner, cf.id, key, seq))
+            .collect();
+        let missing: Vec<usize> = (0..distinct.len())
+            .filter(|i| entries[*i].is_none())
+            .collect();
+        let missing_keys: Vec<&[u8]> = missing.iter().map(|i| distinct[*i]).collect();
+        let (found, mut task) = current.multi_get(&self.opt, &missing_keys, seq)?;
+        for (i, entry) in missing.into_iter().zip(found) {
+            entries[i] = entry;
+        }
+
+        let now = now_millis();
+        let mut values = Vec::with_capacity(distinct.len());
+        for (key, entry) in distinct.iter().zip(entries) {
+            let value = match entry.map(|entry| Self::live_entry(entry, now)).transpose() {
+                // combine the operands the way get does
+                Ok(Some((_, OP_TYPE_MERGE, _))) => {
+                    match self.get_merged(inner, cf, current, key, seq) {
+                        Ok((value, t)) => {
+                            task = task.or(t);
+                            Ok(value)
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+                Ok(Some((_, OP_TYPE_PUT, value))) => Ok(Some(value.to_vec())),
+                Ok(_) => Ok(None),
+                Err(err) => Err(err),
+            };
+            values.push(value);
         }
-        // serach immutable memtable
-        for m in inner.imms.iter().rev() {
-            if let Some(result) = m.get(key, seq) {
-                return Ok((Some(result.to_vec()), None));
+        let values = slots.into_iter().map(|slot| values[slot].clone()).collect();
+        Ok((values, task))
+    }
+
+    // Walk the entries of `key` down from `seq`, gathering merge operands
+    // until a value or a deletion ends them.
+    fn get_merged(
+        &self,
+        inner: &MemInner,
+        cf: &ColumnFamilyData,
+        current: &Version,
+        key: &[u8],
+        mut seq: u64,
+    ) -> Result<(Option<Vec<u8>>, Option<Task>)> {
+        let mut operands = vec![];
+        let mut task = None;
+        let existing = loop {
+            let (entry, t) = self.lookup(inner, cf.id, current, key, seq)?;
+            task = task.or(t);
+            match entry {
+                Some((entry_seq, OP_TYPE_MERGE, operand)) => {
+                    operands.push(operand);
+                    match entry_seq.checked_sub(1) {
+                        Some(older) => seq = older,
+                        None => break None,
+                    }
+                }
+                Some((_, OP_TYPE_PUT, value)) => break Some(value),
+                _ => break None,
             }
+        };
+        if operands.is_empty() {
+            return Ok((existing.map(|v| v.to_vec()), task));
         }
-        // search sst
-        let current = self.version.current();
-        current.refs();
-        let (value, task) = current.get(self.opt.clone(), key, seq);
+        let value = merge::full_merge(
+            cf.options().merge_operator.as_deref(),
+            key,
+            existing.as_deref(),
+            &operands,
+        )?;
+        Ok((Some(value.to_vec()), task))
+    }
 
-        current.derefs();
-        Ok((value, task))
+    // The newest entry of `key` at or before `seq`, or a deletion at the
+    // sequence of the newest range tombstone covering it. An expired put
+    // reads as a deletion, a live one as a plain put.
+    fn lookup(
+        &self,
+        inner: &MemInner,
+        cf: u32,
+        current: &Version,
+        key: &[u8],
+        seq: u64,
+    ) -> Result<(Option<FoundEntry>, Option<Task>)> {
+        let (entry, task) = match Self::lookup_mem(inner, cf, key, seq) {
+            Some(entry) => (Some(entry), None),
+            // search sst
+            None => current.get(&self.opt, key, seq)?,
+        };
+        let now = now_millis();
+        Ok((entry.map(|e| Self::live_entry(e, now)).transpose()?, task))
     }
 
-    fn write_wal(&self, key: &[u8], value: &[u8], seq: u64) -> Result<()> {
-        let mut data = Vec::new();
-        data.put_u64(seq);
-        encode_varintu32(&mut data, key.len() as u32);
-        data.put(key);
-        encode_varintu32(&mut data, value.len() as u32);
-        data.put(value);
-        {
-            let mut inner = self.mem_inner.write();
-            // inner.log_buf.append(&mut data);
-            inner.miss_count += 1;
-            // if inner.miss_count >= self.opt.allow_miss_count
-            //     || inner.log_buf.len() >= self.opt.allow_miss_size
-            // {
-            //     inner.miss_count = 0;
-            // inner.wal.add_recore(&inner.log_buf)?;
-            inner.wal.add_recore(&data)?;
-            //     inner.log_buf.clear();
-            // }
-            Ok(())
+    // The newest entry of `key` in the mem-tables of family `cf`, or a
+    // deletion if a tombstone there covers it. None if the tables are to be
+    // searched.
+    fn lookup_mem(inner: &MemInner, cf: u32, key: &[u8], seq: u64) -> Option<FoundEntry> {
+        // search the mem-table first, then the immutable ones from the newest
+        let mut range_del_seq = 0;
+        for m in Self::mem_tables(inner, cf) {
+            range_del_seq = range_del_seq.max(m.range_del_seq(key, seq));
+            if let Some((entry_seq, typ, value)) = m.lookup(key, seq) {
+                if entry_seq < range_del_seq {
+                    return Some((range_del_seq, OP_TYPE_DELETE, Bytes::new()));
+                }
+                return Some((entry_seq, typ, value));
+            }
         }
+        // every entry in the tables is older than the tombstone
+        (range_del_seq > 0).then(|| (range_del_seq, OP_TYPE_DELETE, Bytes::new()))
     }
 
-    fn write_batch_wal(&self, batch: &WriteBatch, base_seq: u64) -> Result<()> {
-        let mut data = Vec::new();
-        let mut seq = base_seq;
-        batch.data.iter().for_each(|(e, _)| {
-            let mut record = vec![];
-            record.put_u64(seq);
-            encode_varintu32(&mut record, e.key.len() as u32);
-            record.put(e.key.clone());
-            encode_varintu32(&mut record, e.value.len() as u32);
-            record.put(e.value.clone());
-            data.push(Bytes::from(record));
-            seq += 1;
+    // An expiring put as read at `now`: a deletion once expired, a plain put
+    // before.
+    fn live_entry(entry: FoundEntry, now: u64) -> Result<FoundEntry> {
+        let (seq, typ, value) = entry;
+        if typ != OP_TYPE_PUT_TTL {
+            return Ok((seq, typ, value));
+        }
+        Ok(match live_value(typ, value, now)? {
+            Some(value) => (seq, OP_TYPE_PUT, value),
+            None => (seq, OP_TYPE_DELETE, Bytes::new()),
+        })
+    }
+
+    // The mem-tables of family `cf`, newest first.
+    fn mem_tables(inner: &MemInner, cf: u32) -> impl Iterator<Item = &Arc<MemTable>> {
+        let imms = inner.imms.iter().rev().filter_map(move |m| m.get(&cf));
+        inner.mem.get(&cf).into_iter().chain(imms)
+    }
+
+    pub fn new_iterator(
+        &self,
+        lower: Bound<Vec<u8>>,
+        upper: Bound<Vec<u8>>,
+        opt: &ReadOptions,
+    ) -> Result<LsmIterator> {
+        self.new_iterator_cf(0, lower, upper, opt)
+    }
+
+    /// The properties of the tables of column family `cf`, by file number.
+    pub fn table_properties_cf(&self, cf: u32) -> Result<BTreeMap<u64, TableProperties>> {
+        let cf = self.version.column_family(cf)?;
+        let current = cf.current();
+        current.refs();
+        let result = current.table_properties(&self.opt);
+        current.derefs();
+        result
+    }
+
+    pub fn new_iterator_cf(
+        &self,
+        cf: u32,
+        lower: Bound<Vec<u8>>,
+        upper: Bound<Vec<u8>>,
+        opt: &ReadOptions,
+    ) -> Result<LsmIterator> {
+        let cf = self.version.column_family(cf)?;
+        let mut iters: Vec<Box<dyn InternalIterator>> = vec![];
+        let (current, seq, mut range_dels);
+        {
+            let inner = self.mem_inner.read();
+            seq = self.read_sequence(opt);
+            range_dels = RangeTombstones::new(Arc::clone(&self.opt.comparator), seq);
+            for m in Self::mem_tables(&inner, cf.id) {
+                iters.push(Box::new(MemTableIterator::new(m)));
+                range_dels.extend(&m.range_tombstones());
+            }
+            current = cf.current();
+            current.refs();
+        }
+        let num_mems = iters.len();
+        let tables = current.new_table_iterators(&self.opt).and_then(|tables| {
+            range_dels.extend(&current.range_tombstones(&self.opt)?);
+            Ok(tables)
         });
+        match tables {
+            Ok(mut tables) => iters.append(&mut tables),
+            Err(err) => {
+                current.derefs();
+                return Err(err);
+            }
+        }
 
-        let inner = self.mem_inner.write();
-        inner.wal.add_recore_batch(&data)
+        let ucmp = Arc::clone(&self.opt.comparator);
+        let mut iter = LsmIterator::new(
+            MergeIterator::new(iters, Arc::clone(&ucmp)),
+            ucmp,
+            current,
+            num_mems,
+            seq,
+            range_dels,
+            cf.options().merge_operator.clone(),
+            lower,
+            upper,
+        );
+        iter.seek_to_first();
+        Ok(iter)
     }
 
-    pub fn compact_mem_table(&self) {
+    pub fn compact_mem_table(&self) -> Result<()> {
+        let _lock = self.flush_lock.lock();
         // write to disk
         // remove files
-        let (imm, log_number);
-        let base;
+        let (imms, log_number);
         {
             let inner = self.mem_inner.read();
             if inner.imms.is_empty() {
-                return;
+                return Ok(());
             }
-            base = self.version.current();
-            imm = inner.imms[0].clone();
+            imms = inner.imms[0].clone();
             log_number = inner.logs[0];
         }
-        base.refs();
-        let iter = MemTableIterator::new(&imm);
-        self.write_level0_table(base, iter, log_number);
+        // the mem-tables stay queued if any fails to flush, to be flushed
+        // again by the families that had yet to
+        for cf in self.version.column_families() {
+            if cf.log_number() >= log_number {
+                continue;
+            }
+            let mut edit = match imms.get(&cf.id).filter(|m| !m.is_empty()) {
+                Some(imm) => self.write_level0_table(&cf, imm)?,
+                // nothing to add, the wal still has to be retired for it
+                None => {
+                    let mut edit = VersionEdit::new();
+                    edit.column_family(cf.id);
+                    edit
+                }
+            };
+            // each edit retires the wal for its family, and for all of them
+            // once none is left behind: after a crash before, the wal is
+            // replayed into the families that had yet to flush it
+            let oldest = self
+                .version
+                .column_families()
+                .iter()
+                .filter(|other| other.id != cf.id)
+                .map(|other| other.log_number())
+                .fold(log_number, u64::min);
+            edit.cf_log_number(log_number);
+            edit.log_number(oldest);
+            self.version.log_and_apply(edit)?;
+        }
+        // delete wal file
+        let wal_path = path_of_file(&self.opt.work_dir, log_number, Ext::WAL);
+        std::fs::remove_file(wal_path.as_path())?;
         {
             let mut inner = self.mem_inner.write();
             inner.logs.pop_front();
             inner.imms.pop_front();
         }
+        Ok(())
+    }
+
+    /// Run a compaction in every column family that needs one. Returns a gc
+    /// task if they left garbage in vlogs worth collecting.
+    pub fn major_compaction(&self) -> Result<Option<Task>> {
+        let _lock = self.compaction_lock.lock();
+        let mut discarded = false;
+        for cf in self.version.column_families() {
+            discarded |= self.compact_column_family(&cf)?;
+        }
+        self.gc_task(discarded)
+    }
+
+    // A gc task if a compaction `discarded` values and some table now has
+    // vlogs holding at least `value_log_gc_ratio` garbage.
+    fn gc_task(&self, discarded: bool) -> Result<Option<Task>> {
+        if !discarded {
+            return Ok(None);
+        }
+        let ratio = self.opt.value_log_gc_ratio;
+        for cf in self.version.column_families() {
+            if self.version.pick_gc(&cf, ratio)?.is_some() {
+                return Ok(Some(Task::GC));
+            }
+        }
+        Ok(None)
     }
 
-    pub fn major_compaction(&self) -> Result<()> {
-        let current = self.version.current();
+    // Returns whether the compaction recorded garbage in vlogs.
+    fn compact_column_family(&self, cf: &ColumnFamilyData) -> Result<bool> {
+        let current = cf.current();
         current.refs();
         let mut file_meta = FileMetaData::new(0);
-        if let Some(c) = self.version.do_compaction(&mut file_meta)? {
+        if let Some(c) = self.version.do_compaction(cf, &mut file_meta)? {
             let mut edit = VersionEdit::new();
+            edit.column_family(cf.id);
             c.base
                 .iter()
                 .for_each(|f| edit.delete_file(c.base_level as u32, f.clone()));
@@ -251,11 +832,12 @@ impl LsmInner {
                 .for_each(|f| edit.delete_file(c.target_level as u32, f.clone()));
 
             edit.add_file(c.target_level as u32, file_meta.clone());
+            Self::record_vlog_changes(&mut edit, &c, &file_meta);
 
             let inner = self.mem_inner.read();
             edit.log_number(inner.logs[0] - 1);
             current.derefs();
-            self.version.log_and_apply(edit).unwrap();
+            self.version.log_and_apply(edit)?;
 
             // delete files
             self.version.remove_ssts()?;
@@ -273,19 +855,27 @@ impl LsmInner {
                 c.target_level,
                 format!("{:05}.sst", file_meta.number)
             );
+            Ok(!c.discards.is_empty())
         } else {
             current.derefs();
+            Ok(false)
         }
-
-        Ok(())
     }
 
-    pub fn seek_compaction(&self, seek_task: &SeekTask) -> Result<()> {
-        let current = self.version.current();
+    /// Run the compaction of `seek_task`, returning a gc task as
+    /// [`major_compaction`](Self::major_compaction) does.
+    pub fn seek_compaction(&self, seek_task: &SeekTask) -> Result<Option<Task>> {
+        let _lock = self.compaction_lock.lock();
+        let cf = self.version.column_family(seek_task.cf)?;
+        let current = cf.current();
         current.refs();
         let mut file_meta = FileMetaData::new(0);
-        if let Some(c) = self.version.do_seek_compaction(&mut file_meta, seek_task)? {
+        if let Some(c) = self
+            .version
+            .do_seek_compaction(&cf, &mut file_meta, seek_task)?
+        {
             let mut edit = VersionEdit::new();
+            edit.column_family(cf.id);
             c.base
                 .iter()
                 .for_each(|f| edit.delete_file(c.base_level as u32, f.clone()));
@@ -294,11 +884,12 @@ impl LsmInner {
                 .for_each(|f| edit.delete_file(c.target_level as u32, f.clone()));
 
             edit.add_file(c.target_level as u32, file_meta.clone());
+            Self::record_vlog_changes(&mut edit, &c, &file_meta);
 
             let inner = self.mem_inner.read();
             edit.log_number(inner.logs[0] - 1);
             current.derefs();
-            self.version.log_and_apply(edit).unwrap();
+            self.version.log_and_apply(edit)?;
 
             // delete files
             self.version.remove_ssts()?;
@@ -316,59 +907,134 @@ impl LsmInner {
                 c.target_level,
                 format!("{:05}.sst", file_meta.number),
             );
+            self.gc_task(!c.discards.is_empty())
         } else {
             current.derefs();
+            Ok(None)
         }
-
-        Ok(())
     }
 
-    fn write_level0_table<T>(&self, version: Arc<Version>, iter: T, log_number: u64)
-    where
-        T: Iterator<Item = Entry>,
-    {
-        {
-            // let inner = self.mem_inner.read();
-            let mut edit = VersionEdit::new();
-            let fid = self.version.new_file_number();
-            let mut file_meta = FileMetaData::new(fid);
-            // imm  to sst
-
-            TableBuilder::build_table(
-                path_of_file(&self.opt.work_dir, fid, Ext::SST).as_path(),
-                self.opt.clone(),
-                iter,
-                &mut file_meta,
-            )
-            .unwrap();
-
-            // pick level to push
-            let level = version.pick_level_for_mem_table_output(
-                file_meta.smallest().user_key(),
-                file_meta.largest().user_key(),
-            );
-
-            edit.add_file(level, file_meta);
-            edit.log_number(log_number);
-            version.derefs();
-
-            self.version.log_and_apply(edit).unwrap();
-            // delete wal file
-            let wal_path = path_of_file(&self.opt.work_dir, log_number, Ext::WAL);
-            std::fs::remove_file(wal_path.as_path()).unwrap();
+    // Record the garbage a compaction left in the vlogs its output keeps, and
+    // the vlogs it stopped referencing.
+    fn record_vlog_changes(edit: &mut VersionEdit, c: &CompactionState, output: &FileMetaData) {
+        c.discards
+            .iter()
+            .for_each(|(fid, bytes)| edit.discard_vlog(*fid, *bytes));
+        c.base
+            .iter()
+            .chain(c.target.iter())
+            .flat_map(|f| f.vlogs.iter())
+            .filter(|fid| !output.vlogs.contains(fid))
+            .for_each(|fid| edit.delete_vlog(*fid));
+    }
 
-            info!("Minor compact {:05}.sst to level {:?}", fid, level);
+    /// Rewrite the table whose vlogs hold the most garbage, in the first
+    /// column family with one at least `ratio` garbage, and delete the vlogs
+    /// it referenced. Returns whether a table was rewritten.
+    pub fn value_log_gc(&self, ratio: f64) -> Result<bool> {
+        let _lock = self.compaction_lock.lock();
+        let mut picked = None;
+        for cf in self.version.column_families() {
+            if let Some((level, target)) = self.version.pick_gc(&cf, ratio)? {
+                picked = Some((cf, level, target));
+                break;
+            }
         }
+        let Some((cf, level, target)) = picked else {
+            return Ok(false);
+        };
+        let mut file_meta = FileMetaData::new(0);
+        let gc = self.version.do_gc(&cf, &mut file_meta, level, target)?;
+
+        let mut edit = VersionEdit::new();
+        edit.column_family(cf.id);
+        edit.delete_file(gc.level as u32, gc.rewrite_file.clone());
+        edit.add_file(gc.level as u32, gc.new_file.clone());
+        gc.rewrite_file
+            .vlogs
+            .iter()
+            .for_each(|fid| edit.delete_vlog(*fid));
+        let log_number = self.mem_inner.read().logs[0] - 1;
+        edit.log_number(log_number);
+        self.version.log_and_apply(edit)?;
+
+        // delete files
+        self.version.remove_ssts()?;
+        info!(
+            "GC {:05}.sst with vlogs {:?} to {:05}.sst -> level {}",
+            gc.rewrite_file.number, gc.rewrite_file.vlogs, gc.new_file.number, gc.level
+        );
+        Ok(true)
+    }
+
+    // Build a level 0 table of family `cf` out of `iter`, returning the edit
+    // adding it.
+    fn write_level0_table(&self, cf: &ColumnFamilyData, imm: &MemTable) -> Result<VersionEdit> {
+        let version = cf.current();
+        version.refs();
+        let mut edit = VersionEdit::new();
+        edit.column_family(cf.id);
+        let fid = self.version.new_file_number();
+        let mut file_meta = FileMetaData::new(fid);
+
+        // pick level to push, the table is compressed as those of the level
+        let level = imm.user_key_range().map_or(0, |(smallest, largest)| {
+            version.pick_level_for_mem_table_output(&smallest, &largest)
+        });
+        version.derefs();
+
+        // imm  to sst
+        TableBuilder::build_table(
+            path_of_file(&self.opt.work_dir, fid, Ext::SST).as_path(),
+            cf.options().as_ref().clone(),
+            level as usize,
+            MemTableIterator::new(imm),
+            &imm.range_tombstones(),
+            &mut file_meta,
+        )?;
+
+        edit.add_file(level, file_meta);
+        info!(
+            "Minor compact {:05}.sst of column family {} to level {:?}",
+            fid, cf.id, level
+        );
+        Ok(edit)
     }
-    fn recover(&self) -> Result<()> {
+    // Recover the storage, with the options of `column_families` for the
+    // families it has and creating those it does not.
+    fn recover(&self, column_families: &[ColumnFamilyDescriptor]) -> Result<()> {
         // recover from manifest
-        self.version.recover()?;
+        let cf_opts = column_families
+            .iter()
+            .map(|cf| {
+                (
+                    cf.name.clone(),
+                    self.column_family_options(cf.options.clone()),
+                )
+            })
+            .collect();
+        self.version.recover(&cf_opts)?;
         self.recover_mem()?;
+        for cf in column_families {
+            self.create_column_family(&cf.name, cf.options.clone())?;
+        }
 
         self.version.remove_ssts()?;
         Ok(())
     }
 
+    // Read the next whole record of a wal. A torn record at the tail, left by
+    // a crash in the middle of a write, ends the log and is dropped as a whole.
+    fn read_wal_record(f: &mut Reader) -> Result<Option<Vec<u8>>> {
+        match f.read_record() {
+            Err(err @ Error::Corruption { .. }) => {
+                warn!("drop corrupted tail of wal: {}", err);
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
     fn recover_mem(&self) -> Result<()> {
         let mut seq = 0_u64;
         let mut next_file_id = self.version.new_file_number();
@@ -376,72 +1042,92 @@ impl LsmInner {
         let mut data_count = 0;
         {
             let mut inner = self.mem_inner.write();
+            inner.mem = self
+                .version
+                .column_families()
+                .iter()
+                .map(|cf| (cf.id, self.new_mem_table()))
+                .collect();
 
-            let log_number = self.version.smallest_log_number();
-            let dir = std::fs::read_dir(Path::new(&self.opt.work_dir))?;
-            for dir_entry in dir {
+            let log_number = self.version.log_number();
+            let cf_log_numbers: BTreeMap<_, _> = self
+                .version
+                .column_families()
+                .iter()
+                .map(|cf| (cf.id, cf.log_number()))
+                .collect();
+            // replayed oldest first, so the newest is retired last
+            let mut wals = vec![];
+            for dir_entry in std::fs::read_dir(Path::new(&self.opt.work_dir))? {
                 if let Some(file_name) = dir_entry?.file_name().to_str() {
-                    if let Some((name, ext)) = file_name.split_once('.') {
-                        let fid = name.parse::<u64>()?;
-                        if ext == "wal" {
-                            if fid > log_number {
-                                if fid > next_file_id {
-                                    next_file_id = fid;
-                                }
-                                let mut f = Reader::new(Box::new(SequentialFileImpl::new(
-                                    path_of_file(&self.opt.work_dir, fid, Ext::WAL).as_path(),
-                                )));
-
-                                let mut end = false;
-                                while !end {
-                                    let record = f.read_record();
-                                    match record {
-                                        core::result::Result::Ok(record) => {
-                                            seq = seq.max((&record[..8]).get_u64());
-                                            let data = &record[8..];
-                                            let key_sz = decode_varintu32(data).unwrap();
-                                            let var_key_sz = varintu32_length(key_sz) as usize;
-                                            let key =
-                                                &data[var_key_sz..var_key_sz + key_sz as usize];
-                                            let value = &data[var_key_sz + key_sz as usize..];
-                                            let val_sz = decode_varintu32(value).unwrap();
-                                            let var_val_sz = varintu32_length(val_sz) as usize;
-                                            let value = &value[var_val_sz..];
-                                            inner.mem.set(
-                                                Entry::new(
-                                                    Bytes::from(key.to_vec()),
-                                                    Bytes::from(value.to_vec()),
-                                                    seq,
-                                                ),
-                                                OP_TYPE_PUT,
-                                            );
-                                            data_count += 1;
-                                        }
-                                        Err(err) => match err.kind() {
-                                            std::io::ErrorKind::UnexpectedEof => end = true,
-                                            err => panic!("{:?}", err),
-                                        },
-                                    };
-                                }
-                                remove_logs.push(fid);
-                            } else {
-                                let path = path_of_file(&self.opt.work_dir, fid, Ext::WAL);
-                                std::fs::remove_file(path.as_path())?;
-                            }
+                    if let Some((name, "wal")) = file_name.split_once('.') {
+                        // not a file of ours
+                        let Ok(fid) = name.parse::<u64>() else {
+                            continue;
+                        };
+                        wals.push(fid);
+                    }
+                }
+            }
+            wals.sort_unstable();
+            for fid in wals {
+                if fid <= log_number {
+                    let path = path_of_file(&self.opt.work_dir, fid, Ext::WAL);
+                    std::fs::remove_file(path.as_path())?;
+                    continue;
+                }
+                next_file_id = next_file_id.max(fid);
+                let path = path_of_file(&self.opt.work_dir, fid, Ext::WAL);
+                let mut f = Reader::new(Box::new(SequentialFileImpl::new(path.as_path())?));
+
+                let mut offset = f.offset();
+                while let Some(record) = Self::read_wal_record(&mut f)? {
+                    let (base_seq, batch) = WriteBatch::decode(&record)
+                        .map_err(|err| Error::corruption(f.name(), offset, err))?;
+                    let mut entry_seq = base_seq;
+                    for (cf, e, op) in batch.data {
+                        let (Some(mem), Some(cf_log_number)) =
+                            (inner.mem.get(&cf), cf_log_numbers.get(&cf))
+                        else {
+                            let reason = format!("unknown column family {}", cf);
+                            return Err(Error::corruption(f.name(), offset, reason));
+                        };
+                        // already in the tables of a family that flushed the
+                        // wal before a crash
+                        if fid > *cf_log_number {
+                            mem.set(Entry::new(e.key, e.value, entry_seq), op);
+                            data_count += 1;
                         }
+                        entry_seq += 1;
                     }
+                    if batch.count != 0 {
+                        seq = seq.max(entry_seq - 1);
+                    }
+                    offset = f.offset();
                 }
+                remove_logs.push(fid);
             }
             self.version.set_file_number(next_file_id + 5);
             if data_count != 0 {
-                let imm = std::mem::replace(&mut inner.mem, Arc::new(MemTable::new()));
+                let fresh = inner
+                    .mem
+                    .keys()
+                    .map(|cf| (*cf, self.new_mem_table()))
+                    .collect();
+                let imm = std::mem::replace(&mut inner.mem, fresh);
                 inner.imms.push_back(imm);
                 inner.logs.push_back(remove_logs.pop().unwrap());
             }
         }
 
+        // the flushed table is logged with the last sequence, bring it up to
+        // the replayed entries first
+        let vseq = self.version.last_sequence();
+        if seq > vseq {
+            self.version.add_last_sequence(seq - vseq);
+        }
         if data_count != 0 {
-            self.compact_mem_table();
+            self.compact_mem_table()?;
         }
         // remove wal files
         for fid in remove_logs {
@@ -454,11 +1140,8 @@ impl LsmInner {
         inner.logs.push_back(next_file_id);
         let wal = Writer::new(WritableFileImpl::new(
             path_of_file(&self.opt.work_dir, next_file_id, Ext::WAL).as_path(),
-        ));
-        let _ = std::mem::replace(&mut inner.wal, wal);
-
-        let vseq = self.version.last_sequence();
-        self.version.add_last_sequence(seq.abs_diff(vseq));
+        )?);
+        let _ = std::mem::replace(&mut inner.wal, Arc::new(wal));
 
         Ok(())
     }
@@ -469,90 +1152,425 @@ pub struct Lsm {
     // mem_inner: Arc<RwLock<Arc<MemInner>>>,
     inner: Arc<LsmInner>,
     bg_tx: Option<SyncSender<Task>>,
+    bg_handle: Option<JoinHandle<()>>,
 }
 
 impl Lsm {
-    pub fn open(opt: Options) -> Self {
+    pub fn open(opt: Options) -> Result<Self> {
+        Self::open_cf(opt, vec![])
+    }
+
+    /// Open the storage with the column families `column_families`, creating
+    /// those it does not have yet.
+    ///
+    /// Every column family the storage has, but the default one, must be
+    /// given, as its options are not persisted. The default family has `opt`
+    /// unless it is given too.
+    pub fn open_cf(opt: Options, column_families: Vec<ColumnFamilyDescriptor>) -> Result<Self> {
         let path = Path::new(&opt.work_dir);
         if !path.exists() {
-            std::fs::create_dir_all(path).expect("create work direction fail!");
+            std::fs::create_dir_all(path)?;
         }
 
         let mut lsm = Self {
-            inner: Arc::new(LsmInner::new(opt.clone())),
+            inner: Arc::new(LsmInner::new(opt.clone())?),
             bg_tx: None,
+            bg_handle: None,
+        };
+        lsm.inner.recover(&column_families)?;
+        lsm.run_bg_task()?;
+        Ok(lsm)
+    }
+
+    /// Close the storage, returning the first error hit by background
+    /// compaction, if any.
+    ///
+    /// The compactor finishes its in-flight task and stops, the wal is synced
+    /// and, with `Options::flush_on_close`, the mem-tables are written to
+    /// level 0. Dropping an `Lsm` closes it the same way but can only log the
+    /// error.
+    pub fn close(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        let Some(handle) = self.bg_handle.take() else {
+            // already closed
+            return Ok(());
         };
-        lsm.inner.recover().unwrap();
-        lsm.bg_tx = lsm.run_bg_task().into();
-        lsm
+        self.inner.closing.store(true, Ordering::SeqCst);
+        // disconnect the channel to wake the compactor up
+        self.bg_tx = None;
+        if handle.join().is_err() {
+            let err = std::io::Error::other("background thread panicked");
+            self.inner.record_bg_error(err.into());
+        }
+
+        let wal = Arc::clone(&self.inner.mem_inner.read().wal);
+        wal.flush()?;
+        wal.sync()?;
+        if self.inner.opt.flush_on_close {
+            self.inner.flush_mem_tables()?;
+        }
+
+        match self.inner.bg_error.lock().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
     }
 
     pub fn write_batch(&self, batch: &WriteBatch) -> Result<()> {
-        let task = self.inner.write(batch)?;
+        self.write_batch_opt(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_opt(&self, batch: &WriteBatch, opt: &WriteOptions) -> Result<()> {
+        let task = self.inner.write(batch, opt)?;
         self.handle_task(task);
         Ok(())
     }
 
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        let task = self.inner.delete(key)?;
+    /// Create the column family `name` with `opt`, or hand `opt` to it if it
+    /// exists already.
+    ///
+    /// Options are not persisted: the storage is opened again with
+    /// [`Lsm::open_cf`] and the options of every family. The `work_dir` and
+    /// cache sizes of `opt` are ignored, those of the storage apply.
+    pub fn create_column_family(&self, name: &str, opt: Options) -> Result<ColumnFamily> {
+        self.inner.create_column_family(name, opt)
+    }
+
+    /// The column family named `name`, if the storage has it.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamily> {
+        self.inner
+            .version
+            .column_families()
+            .iter()
+            .find(|cf| cf.name == name)
+            .map(|cf| cf.handle())
+    }
+
+    /// Begin an optimistic transaction, reading as of now.
+    pub fn begin_transaction(&self) -> Transaction<'_> {
+        Transaction::new(self, self.snapshot())
+    }
+
+    pub(crate) fn write_checked(
+        &self,
+        batch: &WriteBatch,
+        opt: &WriteOptions,
+        check: ConflictCheck,
+    ) -> Result<()> {
+        let task = self.inner.write_checked(batch, opt, Some(check))?;
         self.handle_task(task);
         Ok(())
     }
 
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        let task = self.inner.put(key, value)?;
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.delete_opt(key, &WriteOptions::default())
+    }
+
+    pub fn delete_opt(&self, key: &[u8], opt: &WriteOptions) -> Result<()> {
+        let task = self.inner.delete(key, opt)?;
         self.handle_task(task);
         Ok(())
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let (value, task) = self.inner.get(key)?;
-        self.handle_task(task);
-        Ok(value)
+    /// Delete every key in `[start, end)` with a single range tombstone.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.delete_range_opt(start, end, &WriteOptions::default())
     }
 
-    fn handle_task(&self, task: Option<Task>) {
-        if let Some(tx) = self.bg_tx.as_ref() {
-            match task {
-                None => {}
-                Some(task) => match task {
-                    Task::Compact => {
-                        let _ = tx.try_send(Task::Compact);
-                    }
-                    Task::Seek(task) => {
-                        let _ = tx.try_send(Task::Seek(task));
-                    }
-                    Task::Major => {
-                        let _ = tx.try_send(Task::Major);
-                    }
-                },
-            }
+    pub fn delete_range_opt(&self, start: &[u8], end: &[u8], opt: &WriteOptions) -> Result<()> {
+        let ucmp = &self.inner.options().comparator;
+        if ucmp.compare(start, end) == std::cmp::Ordering::Greater {
+            return Err(Error::InvalidArgument(
+                "range start is past its end".to_string(),
+            ));
         }
+        let task = self.inner.delete_range(start, end, opt)?;
+        self.handle_task(task);
+        Ok(())
     }
 
-    fn run_bg_task(&self) -> SyncSender<Task> {
-        let (tx, rx) = sync_channel(1000);
+    /// Merge `operand` into the value of `key` with the merge operator of the
+    /// options, without reading the value first.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_opt(key, operand, &WriteOptions::default())
+    }
+
+    pub fn merge_opt(&self, key: &[u8], operand: &[u8], opt: &WriteOptions) -> Result<()> {
+        let task = self.inner.merge(key, operand, opt)?;
+        self.handle_task(task);
+        Ok(())
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_opt(key, value, &WriteOptions::default())
+    }
+
+    /// Put `value` for `ttl` from now, after which `key` reads as absent.
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.put_with_ttl_opt(key, value, ttl, &WriteOptions::default())
+    }
+
+    pub fn put_with_ttl_opt(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+        opt: &WriteOptions,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put_with_ttl(key, value, ttl);
+        self.write_batch_opt(&batch, opt)
+    }
+
+    pub fn put_cf(&self, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put_cf(cf, key, value);
+        self.write_batch(&batch)
+    }
+
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(cf, key);
+        self.write_batch(&batch)
+    }
+
+    /// [`merge`](Self::merge) into family `cf`, with its merge operator.
+    pub fn merge_cf(&self, cf: &ColumnFamily, key: &[u8], operand: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.merge_cf(cf, key, operand);
+        self.write_batch(&batch)
+    }
+
+    pub fn put_opt(&self, key: &[u8], value: &[u8], opt: &WriteOptions) -> Result<()> {
+        let task = self.inner.put(key, value, opt)?;
+        self.handle_task(task);
+        Ok(())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_opt(key, &ReadOptions::default())
+    }
+
+    pub fn get_opt(&self, key: &[u8], opt: &ReadOptions) -> Result<Option<Vec<u8>>> {
+        let (value, task) = self.inner.get(key, opt)?;
+        self.handle_task(task);
+        Ok(value)
+    }
+
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_cf_opt(cf, key, &ReadOptions::default())
+    }
+
+    pub fn get_cf_opt(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        opt: &ReadOptions,
+    ) -> Result<Option<Vec<u8>>> {
+        let (value, task) = self.inner.get_cf(cf.id(), key, opt)?;
+        self.handle_task(task);
+        Ok(value)
+    }
+
+    /// Look `keys` up at one sequence. The results are in the order of
+    /// `keys`, each failing on its own.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        self.multi_get_opt(keys, &ReadOptions::default())
+    }
+
+    pub fn multi_get_opt(&self, keys: &[&[u8]], opt: &ReadOptions) -> Vec<Result<Option<Vec<u8>>>> {
+        self.multi_get_inner(0, keys, opt)
+    }
+
+    pub fn multi_get_cf(&self, cf: &ColumnFamily, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        self.multi_get_cf_opt(cf, keys, &ReadOptions::default())
+    }
+
+    pub fn multi_get_cf_opt(
+        &self,
+        cf: &ColumnFamily,
+        keys: &[&[u8]],
+        opt: &ReadOptions,
+    ) -> Vec<Result<Option<Vec<u8>>>> {
+        self.multi_get_inner(cf.id(), keys, opt)
+    }
+
+    fn multi_get_inner(
+        &self,
+        cf: u32,
+        keys: &[&[u8]],
+        opt: &ReadOptions,
+    ) -> Vec<Result<Option<Vec<u8>>>> {
+        match self.inner.multi_get_cf(cf, keys, opt) {
+            Ok((values, task)) => {
+                self.handle_task(task);
+                values
+            }
+            // a failure before the keys were told apart fails them all
+            Err(err) => keys.iter().map(|_| Err(err.clone())).collect(),
+        }
+    }
+
+    /// Take a consistent read view of the current state. It is released once
+    /// dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.snapshot()
+    }
+
+    /// Collect the vlogs of the table with the most garbage in them, if at
+    /// least `ratio` of them is garbage. Returns whether any space was
+    /// reclaimed; call it again until it returns false to collect more.
+    pub fn run_value_log_gc(&self, ratio: f64) -> Result<bool> {
+        if !(ratio > 0.0 && ratio <= 1.0) {
+            return Err(Error::InvalidArgument(format!(
+                "gc ratio {} not in (0, 1]",
+                ratio
+            )));
+        }
+        self.inner.value_log_gc(ratio)
+    }
+
+    /// The properties of every table, by file number. Tables built before
+    /// tables kept properties are left out.
+    pub fn table_properties(&self) -> Result<BTreeMap<u64, TableProperties>> {
+        self.inner.table_properties_cf(0)
+    }
+
+    pub fn table_properties_cf(&self, cf: &ColumnFamily) -> Result<BTreeMap<u64, TableProperties>> {
+        self.inner.table_properties_cf(cf.id())
+    }
+
+    /// Hit and miss counts and usage of the data block cache.
+    pub fn block_cache_stats(&self) -> CacheStats {
+        self.inner.version.block_cache_stats()
+    }
+
+    /// Hit and miss counts and usage of the separated value cache, all zero
+    /// unless `Options::value_cache_size` enables it.
+    pub fn value_cache_stats(&self) -> CacheStats {
+        self.inner.version.value_cache_stats()
+    }
+
+    /// Iterate all keys in order, positioned at the first key.
+    pub fn iter(&self) -> Result<LsmIterator> {
+        self.iter_opt(&ReadOptions::default())
+    }
+
+    pub fn iter_opt(&self, opt: &ReadOptions) -> Result<LsmIterator> {
+        self.inner
+            .new_iterator(Bound::Unbounded, Bound::Unbounded, opt)
+    }
+
+    /// Iterate all keys of column family `cf` in order.
+    pub fn iter_cf(&self, cf: &ColumnFamily) -> Result<LsmIterator> {
+        self.iter_cf_opt(cf, &ReadOptions::default())
+    }
+
+    pub fn iter_cf_opt(&self, cf: &ColumnFamily, opt: &ReadOptions) -> Result<LsmIterator> {
+        self.inner
+            .new_iterator_cf(cf.id(), Bound::Unbounded, Bound::Unbounded, opt)
+    }
+
+    /// Iterate the keys within `range`, positioned at the first of them.
+    pub fn range<K, R>(&self, range: R) -> Result<LsmIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.range_opt(range, &ReadOptions::default())
+    }
+
+    pub fn range_opt<K, R>(&self, range: R, opt: &ReadOptions) -> Result<LsmIterator>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let to_owned = |bound: Bound<&K>| match bound {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.inner.new_iterator(
+            to_owned(range.start_bound()),
+            to_owned(range.end_bound()),
+            opt,
+        )
+    }
+
+    fn handle_task(&self, task: Option<Task>) {
+        if let Some(tx) = self.bg_tx.as_ref() {
+            match task {
+                None => {}
+                Some(task) => match task {
+                    Task::Compact => {
+                        let _ = tx.try_send(Task::Compact);
+                    }
+                    Task::Seek(task) => {
+                        let _ = tx.try_send(Task::Seek(task));
+                    }
+                    Task::Major => {
+                        let _ = tx.try_send(Task::Major);
+                    }
+                    Task::GC => {
+                        let _ = tx.try_send(Task::GC);
+                    }
+                },
+            }
+        }
+    }
+
+    fn run_bg_task(&mut self) -> Result<()> {
+        let (tx, rx) = sync_channel(1000);
         let db = self.inner.clone();
-        std::thread::Builder::new()
+        let handle = std::thread::Builder::new()
             .name("bg".to_owned())
             .spawn(move || {
                 Compactor::new(rx, db).run_compactor();
-            })
-            .unwrap();
-        tx
+            })?;
+        self.bg_tx = Some(tx);
+        self.bg_handle = Some(handle);
+        Ok(())
+    }
+}
+
+impl Drop for Lsm {
+    fn drop(&mut self) {
+        if let Err(err) = self.shutdown() {
+            error!("close lsm: {}", err);
+        }
     }
 }
 
 #[cfg(test)]
 mod lsm_test {
-    use std::sync::Arc;
-
-    use crate::Options;
-
-    use super::Lsm;
+    use std::{
+        collections::BTreeMap,
+        ops::Bound,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use parking_lot::Mutex;
+
+    use crate::{
+        compactor::{Compactor, SeekTask, Task},
+        file::{path_of_file, Ext, Writable, Writer},
+        utils::parse_internal_key,
+        write_batch::WriteBatch,
+        ColumnFamilyDescriptor, CompactionDecision, CompactionFilter, Error, MergeOperator,
+        Options, ReadOptions, WriteOptions,
+    };
+
+    use super::{Lsm, LsmInner};
 
     fn crud(opt: Options) {
-        let lsm = Arc::new(Lsm::open(opt));
+        let lsm = Arc::new(Lsm::open(opt).unwrap());
 
         let mut handles = vec![];
         for _ in 0..10 {
@@ -619,11 +1637,8 @@ mod lsm_test {
         .join()
         .unwrap();
 
-        // wait to release resource
-        std::thread::sleep(std::time::Duration::from_secs(1));
-
         let opt = Options::default_opt().work_dir("work_dir/recovery");
-        let lsm = Arc::new(Lsm::open(opt));
+        let lsm = Arc::new(Lsm::open(opt).unwrap());
         //
         for i in 0..2000 {
             let n = i as u32;
@@ -632,4 +1647,1022 @@ mod lsm_test {
             assert_eq!(res.clone().unwrap(), n.to_be_bytes());
         }
     }
+
+    #[test]
+    fn lsm_recover_delete_test() {
+        let opt = Options::default_opt().work_dir("work_dir/recover_delete");
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        {
+            let lsm = Lsm::open(opt.clone()).unwrap();
+            for i in 0..100_u32 {
+                lsm.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+            }
+            let mut batch = WriteBatch::default();
+            for i in (0..100_u32).step_by(2) {
+                batch.delete(&i.to_be_bytes());
+            }
+            lsm.write_batch(&batch).unwrap();
+        }
+
+        let check = |lsm: &Lsm| {
+            for i in 0..100_u32 {
+                let res = lsm.get(&i.to_be_bytes()).unwrap();
+                if i % 2 == 0 {
+                    assert_eq!(res, None);
+                } else {
+                    assert_eq!(res.unwrap(), i.to_be_bytes());
+                }
+            }
+        };
+        {
+            let lsm = Lsm::open(opt.clone()).unwrap();
+            check(&lsm);
+
+            let mut batch = WriteBatch::default();
+            for i in 0..100_u32 {
+                batch.put(&i.to_be_bytes(), b"torn");
+            }
+            lsm.write_batch(&batch).unwrap();
+        }
+
+        // cut the last batch short as if the process crashed while writing it
+        let wal = std::fs::read_dir(&opt.work_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "wal"))
+            .max()
+            .unwrap();
+        let f = std::fs::OpenOptions::new().write(true).open(&wal).unwrap();
+        f.set_len(f.metadata().unwrap().len() - 1).unwrap();
+
+        let lsm = Lsm::open(opt).unwrap();
+        check(&lsm);
+    }
+
+    #[test]
+    fn lsm_write_options_test() {
+        let opt = Options::default_opt().work_dir("work_dir/write_options");
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        {
+            let lsm = Arc::new(Lsm::open(opt.clone()).unwrap());
+            let mut handles = vec![];
+            for t in 0..8_u32 {
+                let lsm = Arc::clone(&lsm);
+                handles.push(std::thread::spawn(move || {
+                    let write_opt = WriteOptions::default().sync(t % 2 == 0);
+                    for i in (t * 100)..(t + 1) * 100 {
+                        lsm.put_opt(&i.to_be_bytes(), &i.to_be_bytes(), &write_opt)
+                            .unwrap();
+                    }
+                }));
+            }
+            handles.into_iter().for_each(|h| h.join().unwrap());
+
+            let write_opt = WriteOptions::default().disable_wal(true);
+            lsm.put_opt(b"unlogged", b"value", &write_opt).unwrap();
+            lsm.delete_opt(&0_u32.to_be_bytes(), &write_opt).unwrap();
+            assert_eq!(lsm.get(b"unlogged").unwrap().unwrap(), b"value");
+            assert_eq!(lsm.get(&0_u32.to_be_bytes()).unwrap(), None);
+        }
+
+        // writes that skipped the wal do not survive a restart
+        let lsm = Lsm::open(opt).unwrap();
+        for i in 0..800_u32 {
+            let res = lsm.get(&i.to_be_bytes()).unwrap();
+            assert_eq!(res.unwrap(), i.to_be_bytes());
+        }
+        assert_eq!(lsm.get(b"unlogged").unwrap(), None);
+    }
+
+    // a wal file failing its `fail_at`-th append, and taking the others
+    struct FailingFile {
+        appends: usize,
+        fail_at: usize,
+    }
+
+    impl Writable for FailingFile {
+        fn append(&mut self, _data: &[u8]) -> std::result::Result<(), std::io::Error> {
+            self.appends += 1;
+            match self.appends == self.fail_at {
+                true => Err(std::io::Error::other("disk full")),
+                false => Ok(()),
+            }
+        }
+
+        fn flush(&mut self) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn sync(&mut self) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn size(&self) -> std::result::Result<u64, std::io::Error> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn lsm_wal_error_test() {
+        let opt = Options::default_opt().work_dir("work_dir/wal_error");
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        let lsm = Lsm::open(opt).unwrap();
+        lsm.inner.mem_inner.write().wal = Arc::new(Writer::new(FailingFile {
+            appends: 0,
+            fail_at: 2,
+        }));
+        lsm.put(b"k1", b"v1").unwrap();
+        assert!(matches!(lsm.put(b"k2", b"v2"), Err(Error::Io(_))));
+
+        // the wal may end in a torn record, so no later write is acknowledged
+        // though the file takes it
+        assert!(matches!(lsm.put(b"k3", b"v3"), Err(Error::Io(_))));
+        let write_opt = WriteOptions::default().disable_wal(true);
+        assert!(lsm.put_opt(b"k4", b"v4", &write_opt).is_err());
+        assert_eq!(lsm.get(b"k1").unwrap().unwrap(), b"v1");
+        assert_eq!(lsm.get(b"k3").unwrap(), None);
+        assert!(lsm.close().is_err());
+    }
+
+    #[test]
+    fn lsm_close_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/close")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        let lsm = Lsm::open(opt.clone()).unwrap();
+        for i in 0..1000_u32 {
+            lsm.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        lsm.close().unwrap();
+
+        let lsm = Lsm::open(opt.clone().flush_on_close(true)).unwrap();
+        for i in 0..1000_u32 {
+            let res = lsm.get(&i.to_be_bytes()).unwrap();
+            assert_eq!(res.unwrap(), i.to_be_bytes());
+        }
+        lsm.put(b"last", b"value").unwrap();
+        lsm.close().unwrap();
+
+        // everything is in tables, the wals left are empty
+        let wal_sizes: Vec<_> = std::fs::read_dir(&opt.work_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "wal"))
+            .map(|p| std::fs::metadata(p).unwrap().len())
+            .collect();
+        assert!(wal_sizes.iter().all(|sz| *sz == 0));
+
+        let lsm = Lsm::open(opt).unwrap();
+        assert_eq!(lsm.get(b"last").unwrap().unwrap(), b"value");
+        assert_eq!(
+            lsm.get(&999_u32.to_be_bytes()).unwrap().unwrap(),
+            999_u32.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn lsm_value_log_gc_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/value_log_gc")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+        // no background compactor, so the tables stay where they are put
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+
+        let write_opt = WriteOptions::default();
+        for round in 0..4_u8 {
+            for i in 0..300_u32 {
+                inner
+                    .put(&i.to_be_bytes(), &[round; 16], &write_opt)
+                    .unwrap();
+            }
+            inner.flush_mem_tables().unwrap();
+        }
+        // compacting level 0 keeps the vlogs of its inputs, garbage included
+        inner.major_compaction().unwrap();
+        let cf = inner.version.column_family(0).unwrap();
+        let (level, target) = inner.version.pick_gc(&cf, 0.5).unwrap().unwrap();
+        assert!(level > 0 && target.vlogs.len() > 1);
+        let discard_of = |inner: &LsmInner| -> Vec<u64> {
+            let current = inner.version.column_family(0).unwrap().current();
+            target
+                .vlogs
+                .iter()
+                .map(|fid| current.vlog_discard(*fid))
+                .collect()
+        };
+        let discards = discard_of(&inner);
+        let (total, discard) = target.vlogs.iter().zip(discards.iter()).fold(
+            (0, 0),
+            |(total, discard), (fid, bytes)| {
+                let path = path_of_file(&opt.work_dir, *fid, Ext::VLOG);
+                (
+                    total + std::fs::metadata(path).unwrap().len(),
+                    discard + bytes,
+                )
+            },
+        );
+        // most values of each key were overwritten, the newest are live
+        assert!(discard * 2 >= total && discard < total);
+
+        // the discard stats survive a reopen
+        drop(inner);
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+        assert_eq!(discard_of(&inner), discards);
+
+        assert!(inner.value_log_gc(0.5).unwrap());
+        for fid in target.vlogs.iter() {
+            assert!(!path_of_file(&opt.work_dir, *fid, Ext::VLOG).exists());
+        }
+        assert!(discard_of(&inner).iter().all(|bytes| *bytes == 0));
+        let read_opt = ReadOptions::default();
+        for i in 0..300_u32 {
+            let (value, _) = inner.get(&i.to_be_bytes(), &read_opt).unwrap();
+            assert_eq!(value.unwrap(), [3; 16]);
+        }
+        // the rewritten table holds no garbage
+        let cf = inner.version.column_family(0).unwrap();
+        assert!(inner.version.pick_gc(&cf, 0.01).unwrap().is_none());
+    }
+
+    #[test]
+    fn lsm_value_log_gc_under_writes_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/value_log_gc_under_writes")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+        let inner = Arc::new(LsmInner::new(opt.clone()).unwrap());
+        inner.recover(&[]).unwrap();
+
+        // tables whose values are mostly overwritten, the vlogs of the last
+        // round holding the live ones
+        let vlogs = || -> std::collections::HashSet<_> {
+            std::fs::read_dir(&opt.work_dir)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .filter(|p| p.extension().is_some_and(|ext| ext == "vlog"))
+                .collect()
+        };
+        let write_opt = WriteOptions::default();
+        let mut live = Default::default();
+        for round in 0..4_u8 {
+            let before = vlogs();
+            for i in 0..300_u32 {
+                inner
+                    .put(&i.to_be_bytes(), &[round; 16], &write_opt)
+                    .unwrap();
+            }
+            inner.flush_mem_tables().unwrap();
+            live = &vlogs() - &before;
+        }
+        assert!(!live.is_empty());
+
+        let (tx, rx) = std::sync::mpsc::sync_channel(1000);
+        let compactor = {
+            let inner = Arc::clone(&inner);
+            std::thread::spawn(move || Compactor::new(rx, inner).run_compactor())
+        };
+        // a task within every wait of the compactor, so it never idles
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (inner, stop) = (Arc::clone(&inner), Arc::clone(&stop));
+            std::thread::spawn(move || {
+                let mut i = 1000_u32;
+                while !stop.load(Ordering::SeqCst) {
+                    let task = inner.put(&i.to_be_bytes(), &[0; 16], &write_opt).unwrap();
+                    let _ = tx.try_send(task.unwrap_or(Task::Compact));
+                    i += 1;
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            })
+        };
+
+        // compaction leaves the vlogs of its output mostly garbage, and only gc
+        // deletes a vlog with live values, long before the idle timer would
+        let start = std::time::Instant::now();
+        while live.iter().all(|p| p.exists()) {
+            assert!(start.elapsed() < Duration::from_secs(20), "gc did not run");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!writer.is_finished());
+        stop.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+        compactor.join().unwrap();
+
+        let read_opt = ReadOptions::default();
+        for i in 0..300_u32 {
+            let (value, _) = inner.get(&i.to_be_bytes(), &read_opt).unwrap();
+            assert_eq!(value.unwrap(), [3; 16]);
+        }
+    }
+
+    #[test]
+    fn lsm_delete_range_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/delete_range")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+
+        let write_opt = WriteOptions::default();
+        for round in 0..2_u8 {
+            for i in 0..300_u32 {
+                inner
+                    .put(&i.to_be_bytes(), &[round; 16], &write_opt)
+                    .unwrap();
+            }
+            inner.flush_mem_tables().unwrap();
+        }
+        let snapshot = inner.snapshot();
+        inner
+            .delete_range(&100_u32.to_be_bytes(), &200_u32.to_be_bytes(), &write_opt)
+            .unwrap();
+        inner
+            .put(&150_u32.to_be_bytes(), &[2; 16], &write_opt)
+            .unwrap();
+
+        let expected = |i: u32| match i {
+            150 => Some(vec![2; 16]),
+            100..=199 => None,
+            _ => Some(vec![1; 16]),
+        };
+        let check = |inner: &LsmInner| {
+            let read_opt = ReadOptions::default();
+            for i in 0..300_u32 {
+                let (value, _) = inner.get(&i.to_be_bytes(), &read_opt).unwrap();
+                assert_eq!(value, expected(i), "key {}", i);
+            }
+            let keys: Vec<u32> = (0..300).filter(|i| expected(*i).is_some()).collect();
+            let mut iter = inner
+                .new_iterator(Bound::Unbounded, Bound::Unbounded, &read_opt)
+                .unwrap();
+            for i in keys.iter() {
+                assert!(iter.valid());
+                assert_eq!(iter.key(), i.to_be_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            for i in keys.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(iter.key(), i.to_be_bytes());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+        };
+        // the tombstone in the mem-table hides the keys in the tables
+        check(&inner);
+        let read_opt = ReadOptions::default().snapshot(&snapshot);
+        let (value, _) = inner.get(&120_u32.to_be_bytes(), &read_opt).unwrap();
+        assert_eq!(value.unwrap(), [1; 16]);
+        drop(read_opt);
+        drop(snapshot);
+
+        // the tombstone is recovered from the wal into a level 0 table
+        drop(inner);
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+        check(&inner);
+        let in_level0 = |inner: &LsmInner| {
+            inner.version.column_family(0).unwrap().current().files()[0]
+                .iter()
+                .any(|f| f.smallest.user_key() == 100_u32.to_be_bytes())
+        };
+        assert!(in_level0(&inner));
+
+        // compaction drops the covered entries but keeps the tombstone, pile
+        // up level 0 tables to get it going
+        for _ in 0..3 {
+            for i in (0..100_u32).chain(200..300) {
+                inner.put(&i.to_be_bytes(), &[1; 16], &write_opt).unwrap();
+            }
+            inner.flush_mem_tables().unwrap();
+        }
+        inner.major_compaction().unwrap();
+        assert!(!in_level0(&inner));
+        check(&inner);
+        drop(inner);
+        let inner = LsmInner::new(opt).unwrap();
+        inner.recover(&[]).unwrap();
+        check(&inner);
+    }
+
+    #[test]
+    fn lsm_flush_crash_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/flush_crash")
+            .merge_operator(Arc::new(Counter));
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+        let users = inner.create_column_family("users", opt.clone()).unwrap();
+
+        let write_opt = WriteOptions::default();
+        for _ in 0..2 {
+            inner
+                .merge(b"hits", &1_u64.to_le_bytes(), &write_opt)
+                .unwrap();
+        }
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&users, b"name", b"v");
+        inner.write(&batch, &write_opt).unwrap();
+
+        // the flush takes a number for the next wal, then one for the table of
+        // each family: the table of users cannot be created, so the crash
+        // comes after the edit of the default family
+        let next = inner.version.new_file_number();
+        let blocked = path_of_file(&opt.work_dir, next + 3, Ext::SST);
+        std::fs::create_dir(&blocked).unwrap();
+        assert!(inner.flush_mem_tables().is_err());
+        // the wal is retired for the storage as far as the family left behind
+        let log_numbers = |inner: &LsmInner| -> Vec<u64> {
+            let cfs = inner.version.column_families();
+            cfs.iter().map(|cf| cf.log_number()).collect()
+        };
+        let flushed = log_numbers(&inner);
+        assert!(flushed[0] > flushed[1]);
+        assert_eq!(inner.version.log_number(), flushed[1]);
+        drop(inner);
+        std::fs::remove_dir(&blocked).unwrap();
+
+        // the wal is replayed into users only
+        let descriptors = [ColumnFamilyDescriptor::new("users", opt.clone())];
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&descriptors).unwrap();
+        let tables = |inner: &LsmInner, cf: u32| -> usize {
+            let cf = inner.version.column_family(cf).unwrap();
+            cf.current().files().iter().map(|f| f.len()).sum()
+        };
+        assert_eq!((tables(&inner, 0), tables(&inner, users.id())), (1, 1));
+        let read_opt = ReadOptions::default();
+        let (value, _) = inner.get(b"hits", &read_opt).unwrap();
+        assert_eq!(value.unwrap(), 2_u64.to_le_bytes());
+        let (value, _) = inner.get_cf(users.id(), b"name", &read_opt).unwrap();
+        assert_eq!(value.unwrap(), b"v");
+
+        // and then retired for good
+        drop(inner);
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&descriptors).unwrap();
+        assert_eq!((tables(&inner, 0), tables(&inner, users.id())), (1, 1));
+        let recovered = log_numbers(&inner);
+        assert_eq!(recovered[0], recovered[1]);
+        assert_eq!(inner.version.log_number(), recovered[1]);
+
+        // a flush retried after a failure skips the families done
+        inner
+            .merge(b"hits", &1_u64.to_le_bytes(), &write_opt)
+            .unwrap();
+        inner.write(&batch, &write_opt).unwrap();
+        let next = inner.version.new_file_number();
+        let blocked = path_of_file(&opt.work_dir, next + 3, Ext::SST);
+        std::fs::create_dir(&blocked).unwrap();
+        assert!(inner.flush_mem_tables().is_err());
+        std::fs::remove_dir(&blocked).unwrap();
+        inner.flush_mem_tables().unwrap();
+        assert_eq!((tables(&inner, 0), tables(&inner, users.id())), (2, 2));
+        let (value, _) = inner.get(b"hits", &read_opt).unwrap();
+        assert_eq!(value.unwrap(), 3_u64.to_le_bytes());
+    }
+
+    // adds up little endian u64 operands
+    struct Counter;
+
+    impl MergeOperator for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn full_merge(
+            &self,
+            _key: &[u8],
+            existing: Option<&[u8]>,
+            operands: &[&[u8]],
+        ) -> Option<Vec<u8>> {
+            let count = |v: &[u8]| v.try_into().ok().map(u64::from_le_bytes);
+            let mut sum = existing.map_or(Some(0), count)?;
+            for operand in operands {
+                sum += count(operand)?;
+            }
+            Some(sum.to_le_bytes().to_vec())
+        }
+
+        fn partial_merge(&self, key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+            self.full_merge(key, Some(left), &[right])
+        }
+    }
+
+    #[test]
+    fn lsm_merge_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/merge")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4)
+            .merge_operator(Arc::new(Counter));
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+
+        let write_opt = WriteOptions::default();
+        let key = |i: u64| format!("key{:03}", i).into_bytes();
+        // every third key has no value below its operands
+        for i in (0..100_u64).filter(|i| i % 3 != 0) {
+            inner.put(&key(i), &i.to_le_bytes(), &write_opt).unwrap();
+        }
+        inner.flush_mem_tables().unwrap();
+        let snapshot = inner.snapshot();
+        // operands spread over mem-tables and tables
+        for round in 0..6 {
+            for i in 0..100_u64 {
+                inner
+                    .merge(&key(i), &1_u64.to_le_bytes(), &write_opt)
+                    .unwrap();
+            }
+            if round % 2 == 0 {
+                inner.flush_mem_tables().unwrap();
+            }
+        }
+        inner.delete(&key(50), &write_opt).unwrap();
+        inner
+            .merge(&key(50), &7_u64.to_le_bytes(), &write_opt)
+            .unwrap();
+
+        let expected = |i: u64| match i {
+            50 => 7,
+            i if i % 3 == 0 => 6,
+            i => i + 6,
+        };
+        let check = |inner: &LsmInner| {
+            let read_opt = ReadOptions::default();
+            for i in 0..100_u64 {
+                let (value, _) = inner.get(&key(i), &read_opt).unwrap();
+                assert_eq!(value.unwrap(), expected(i).to_le_bytes(), "key {}", i);
+            }
+            let mut iter = inner
+                .new_iterator(Bound::Unbounded, Bound::Unbounded, &read_opt)
+                .unwrap();
+            for i in 0..100_u64 {
+                assert!(iter.valid());
+                assert_eq!(
+                    (iter.key(), iter.value()),
+                    (&key(i)[..], &expected(i).to_le_bytes()[..])
+                );
+                iter.next();
+            }
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            for i in (0..100_u64).rev() {
+                assert!(iter.valid());
+                assert_eq!(
+                    (iter.key(), iter.value()),
+                    (&key(i)[..], &expected(i).to_le_bytes()[..])
+                );
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            iter.status().unwrap();
+        };
+        check(&inner);
+        let read_opt = ReadOptions::default().snapshot(&snapshot);
+        let (value, _) = inner.get(&key(1), &read_opt).unwrap();
+        assert_eq!(value.unwrap(), 1_u64.to_le_bytes());
+        let (value, _) = inner.get(&key(3), &read_opt).unwrap();
+        assert_eq!(value, None);
+        drop(read_opt);
+        drop(snapshot);
+
+        // compaction combines the operands, with the value below them if any
+        inner.flush_mem_tables().unwrap();
+        for _ in 0..4 {
+            inner.major_compaction().unwrap();
+        }
+        check(&inner);
+        drop(inner);
+        let inner = LsmInner::new(opt).unwrap();
+        inner.recover(&[]).unwrap();
+        check(&inner);
+    }
+
+    #[test]
+    fn lsm_merge_without_operator_test() {
+        let opt = Options::default_opt().work_dir("work_dir/merge_without_operator");
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        let lsm = Lsm::open(opt).unwrap();
+        assert!(matches!(
+            lsm.merge(b"key", b"operand"),
+            Err(Error::InvalidArgument(_))
+        ));
+        // nor is an operand written in a batch, which writes nothing of it
+        let mut batch = WriteBatch::default();
+        batch.put(b"other", b"value");
+        batch.merge(b"key", b"operand");
+        assert!(matches!(
+            lsm.write_batch(&batch),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert_eq!(lsm.get(b"key").unwrap(), None);
+        assert_eq!(lsm.get(b"other").unwrap(), None);
+    }
+
+    fn build_iter_data(lsm: &Lsm) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut expected = BTreeMap::new();
+        for i in 0..1000_u32 {
+            lsm.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+            expected.insert(i.to_be_bytes().to_vec(), i.to_be_bytes().to_vec());
+        }
+        for i in (0..1000_u32).step_by(3) {
+            lsm.delete(&i.to_be_bytes()).unwrap();
+            expected.remove(i.to_be_bytes().as_slice());
+        }
+        for i in (0..1000_u32).step_by(5) {
+            lsm.put(&i.to_be_bytes(), &(i + 1).to_be_bytes()).unwrap();
+            expected.insert(i.to_be_bytes().to_vec(), (i + 1).to_be_bytes().to_vec());
+        }
+        expected
+    }
+
+    #[test]
+    fn lsm_iter_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/iter")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        let lsm = Lsm::open(opt).unwrap();
+        let expected = build_iter_data(&lsm);
+
+        let mut iter = lsm.iter().unwrap();
+        for (k, v) in expected.iter() {
+            assert!(iter.valid());
+            assert_eq!(iter.key(), &k[..]);
+            assert_eq!(iter.value(), &v[..]);
+            iter.next();
+        }
+        assert!(!iter.valid());
+
+        iter.seek_to_last();
+        for (k, v) in expected.iter().rev() {
+            assert!(iter.valid());
+            assert_eq!(iter.key(), &k[..]);
+            assert_eq!(iter.value(), &v[..]);
+            iter.prev();
+        }
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn lsm_range_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/range")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        let lsm = Lsm::open(opt).unwrap();
+        let expected = build_iter_data(&lsm);
+        let (start, end) = (100_u32.to_be_bytes(), 200_u32.to_be_bytes());
+        let in_range: Vec<_> = expected
+            .range(start.to_vec()..end.to_vec())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let mut iter = lsm.range(start..end).unwrap();
+        let mut found = vec![];
+        while iter.valid() {
+            found.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next();
+        }
+        assert_eq!(found, in_range);
+
+        // 153 is deleted, seek lands on its neighbours
+        iter.seek(&153_u32.to_be_bytes());
+        assert_eq!(iter.key(), 154_u32.to_be_bytes());
+        iter.seek_for_prev(&153_u32.to_be_bytes());
+        assert_eq!(iter.key(), 152_u32.to_be_bytes());
+        iter.prev();
+        assert_eq!(iter.key(), 151_u32.to_be_bytes());
+        iter.next();
+        iter.next();
+        assert_eq!(iter.key(), 154_u32.to_be_bytes());
+
+        // out of range targets are clamped to the bounds
+        iter.seek(&0_u32.to_be_bytes());
+        assert_eq!(iter.key(), 100_u32.to_be_bytes());
+        iter.seek_for_prev(&500_u32.to_be_bytes());
+        assert_eq!(iter.key(), 199_u32.to_be_bytes());
+        iter.next();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn lsm_snapshot_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/snapshot")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        let lsm = Lsm::open(opt).unwrap();
+        let expected = build_iter_data(&lsm);
+        let snapshot = lsm.snapshot();
+        let read_opt = ReadOptions::default().snapshot(&snapshot);
+
+        // overwrite and delete everything, enough to flush and compact
+        for round in 1..4_u32 {
+            for i in 0..1000_u32 {
+                lsm.put(&i.to_be_bytes(), &(i * 10 + round).to_be_bytes())
+                    .unwrap();
+            }
+        }
+        for i in (0..1000_u32).step_by(2) {
+            lsm.delete(&i.to_be_bytes()).unwrap();
+        }
+
+        for i in 0..1000_u32 {
+            let k = i.to_be_bytes();
+            let v = lsm.get_opt(&k, &read_opt).unwrap();
+            assert_eq!(v.as_deref(), expected.get(k.as_slice()).map(|v| &v[..]));
+
+            let v = lsm.get(&k).unwrap();
+            if i % 2 == 0 {
+                assert_eq!(v, None);
+            } else {
+                assert_eq!(v.unwrap(), (i * 10 + 3).to_be_bytes());
+            }
+        }
+
+        let mut iter = lsm.iter_opt(&read_opt).unwrap();
+        for (k, v) in expected.iter() {
+            assert!(iter.valid());
+            assert_eq!(iter.key(), &k[..]);
+            assert_eq!(iter.value(), &v[..]);
+            iter.next();
+        }
+        assert!(!iter.valid());
+        drop(iter);
+
+        let iter = lsm.range_opt(..100_u32.to_be_bytes(), &read_opt).unwrap();
+        assert_eq!(iter.key(), 0_u32.to_be_bytes());
+
+        drop(snapshot);
+        let iter = lsm.range_opt(..100_u32.to_be_bytes(), &read_opt).unwrap();
+        assert_eq!(iter.key(), 0_u32.to_be_bytes());
+        drop(read_opt);
+        assert_eq!(
+            lsm.inner.version.smallest_sequence(),
+            lsm.inner.version.last_sequence()
+        );
+    }
+
+    #[test]
+    fn lsm_ttl_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/ttl")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+
+        let write_opt = WriteOptions::default();
+        // older values the expired ones must not uncover
+        for i in 0..300_u32 {
+            inner.put(&i.to_be_bytes(), &[0; 16], &write_opt).unwrap();
+        }
+        inner.flush_mem_tables().unwrap();
+        // every even key expires, the last ones while in the mem-table
+        for i in 0..300_u32 {
+            let ttl = match i % 2 {
+                0 => Duration::from_millis(200),
+                _ => Duration::from_secs(3600),
+            };
+            let mut batch = WriteBatch::default();
+            batch.put_with_ttl(&i.to_be_bytes(), &[1; 16], ttl);
+            inner.write(&batch, &write_opt).unwrap();
+            if i == 200 {
+                inner.flush_mem_tables().unwrap();
+            }
+        }
+        let (value, _) = inner
+            .get(&0_u32.to_be_bytes(), &ReadOptions::default())
+            .unwrap();
+        assert_eq!(value.unwrap(), [1; 16]);
+        std::thread::sleep(Duration::from_millis(300));
+
+        let check = |inner: &LsmInner| {
+            let read_opt = ReadOptions::default();
+            for i in 0..300_u32 {
+                let (value, _) = inner.get(&i.to_be_bytes(), &read_opt).unwrap();
+                assert_eq!(value, (i % 2 == 1).then(|| vec![1; 16]), "key {}", i);
+            }
+            let keys: Vec<u32> = (0..300).filter(|i| i % 2 == 1).collect();
+            let mut iter = inner
+                .new_iterator(Bound::Unbounded, Bound::Unbounded, &read_opt)
+                .unwrap();
+            for i in keys.iter() {
+                assert!(iter.valid());
+                assert_eq!(
+                    (iter.key(), iter.value()),
+                    (&i.to_be_bytes()[..], &[1; 16][..])
+                );
+                iter.next();
+            }
+            assert!(!iter.valid());
+            iter.seek_to_last();
+            for i in keys.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(
+                    (iter.key(), iter.value()),
+                    (&i.to_be_bytes()[..], &[1; 16][..])
+                );
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            iter.status().unwrap();
+        };
+        check(&inner);
+
+        inner.flush_mem_tables().unwrap();
+        for _ in 0..4 {
+            inner.major_compaction().unwrap();
+        }
+        check(&inner);
+        // pushing level 1 into the bottom level drops the expired entries
+        // with the older values below them
+        let current = inner.version.column_family(0).unwrap().current();
+        assert!(!current.level_files(1).is_empty());
+        for f in current.level_files(1) {
+            let task = SeekTask {
+                cf: 0,
+                level: 1,
+                fid: f.number,
+            };
+            inner.seek_compaction(&task).unwrap();
+        }
+        let current = inner.version.column_family(0).unwrap().current();
+        assert!(current.level_files(1).is_empty());
+        for mut iter in current.new_table_iterators(&opt).unwrap() {
+            iter.seek_to_first();
+            while iter.valid() {
+                let (user_key, _, _) = parse_internal_key(iter.key());
+                assert_eq!(user_key[3] % 2, 1);
+                iter.next();
+            }
+        }
+        check(&inner);
+        drop(inner);
+        let inner = LsmInner::new(opt).unwrap();
+        inner.recover(&[]).unwrap();
+        check(&inner);
+    }
+
+    // removes the keys of tenant 1 and rewrites those of tenant 2
+    #[derive(Default)]
+    struct TenantFilter {
+        levels: Mutex<Vec<usize>>,
+    }
+
+    impl CompactionFilter for TenantFilter {
+        fn name(&self) -> &str {
+            "tenant"
+        }
+
+        fn filter(&self, level: usize, key: &[u8], _value: &[u8]) -> CompactionDecision {
+            self.levels.lock().push(level);
+            match &key[..3] {
+                b"t1/" => CompactionDecision::Remove,
+                b"t2/" => CompactionDecision::ChangeValue(b"moved".to_vec()),
+                _ => CompactionDecision::Keep,
+            }
+        }
+    }
+
+    #[test]
+    fn lsm_compaction_filter_test() {
+        let filter = Arc::new(TenantFilter::default());
+        let opt = Options::default_opt()
+            .work_dir("work_dir/compaction_filter")
+            .mem_size(1 << 12)
+            .kv_separate_threshold(4)
+            .compaction_filter(filter.clone());
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap()
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+        let inner = LsmInner::new(opt.clone()).unwrap();
+        inner.recover(&[]).unwrap();
+
+        let write_opt = WriteOptions::default();
+        let key = |tenant: u32, i: u32| format!("t{}/{:03}", tenant, i).into_bytes();
+        let put_round = |inner: &LsmInner, round: u8| {
+            for tenant in 1..=3 {
+                for i in 0..100 {
+                    inner
+                        .put(&key(tenant, i), &[round; 16], &write_opt)
+                        .unwrap();
+                }
+            }
+            inner.flush_mem_tables().unwrap();
+        };
+        // the first round stays for the snapshot, the filter sees the second
+        put_round(&inner, 0);
+        let snapshot = inner.snapshot();
+        put_round(&inner, 1);
+
+        let expected = |tenant: u32| match tenant {
+            1 => None,
+            2 => Some(b"moved".to_vec()),
+            _ => Some(vec![1; 16]),
+        };
+        let check = |inner: &LsmInner| {
+            let read_opt = ReadOptions::default();
+            for tenant in 1..=3 {
+                for i in 0..100 {
+                    let (value, _) = inner.get(&key(tenant, i), &read_opt).unwrap();
+                    assert_eq!(value, expected(tenant), "tenant {} key {}", tenant, i);
+                }
+            }
+            let mut iter = inner
+                .new_iterator(Bound::Unbounded, Bound::Unbounded, &read_opt)
+                .unwrap();
+            for tenant in 2..=3 {
+                for i in 0..100 {
+                    assert!(iter.valid());
+                    assert_eq!(iter.key(), key(tenant, i));
+                    assert_eq!(Some(iter.value().to_vec()), expected(tenant));
+                    iter.next();
+                }
+            }
+            assert!(!iter.valid());
+            iter.status().unwrap();
+        };
+
+        // push everything into the bottom level
+        let current = inner.version.column_family(0).unwrap().current();
+        assert!(!current.level_files(1).is_empty());
+        for f in current.level_files(1) {
+            let task = SeekTask {
+                cf: 0,
+                level: 1,
+                fid: f.number,
+            };
+            inner.seek_compaction(&task).unwrap();
+        }
+        let levels = filter.levels.lock().clone();
+        assert_eq!(levels.len(), 300);
+        assert!(levels.iter().all(|level| *level == 2));
+        check(&inner);
+        let read_opt = ReadOptions::default().snapshot(&snapshot);
+        for tenant in 1..=3 {
+            let (value, _) = inner.get(&key(tenant, 7), &read_opt).unwrap();
+            assert_eq!(value.unwrap(), [0; 16]);
+        }
+        drop(read_opt);
+        drop(snapshot);
+
+        drop(inner);
+        let inner = LsmInner::new(opt).unwrap();
+        inner.recover(&[]).unwrap();
+        check(&inner);
+    }
 }
@@ -1,47 +1,52 @@
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
+use std::{
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use bytes::{Buf, BufMut, Bytes};
 use crossbeam_skiplist::SkipMap;
-
-use crate::utils::{
-    codec::{decode_varintu32, encode_varintu32, varintu32_length},
-    Entry, OP_TYPE_DELETE, OP_TYPE_PUT,
+use parking_lot::RwLock;
+
+use crate::{
+    comparator::{BytewiseComparator, Comparator},
+    iterator::InternalIterator,
+    range_del::RangeTombstone,
+    utils::{
+        codec::{decode_varintu32, encode_varintu32, varintu32_length},
+        Entry, OP_TYPE_DELETE, OP_TYPE_PUT, OP_TYPE_RANGE_DELETE,
+    },
 };
 
 type Table = SkipMap<Key, Bytes>;
-type TableIterator<'a> = crossbeam_skiplist::map::Iter<'a, Key, Bytes>;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// A key of the skiplist, ordered as an internal key by the comparator of
+/// its mem-table.
+#[derive(Debug, Clone)]
 pub struct Key {
     key: Bytes,
+    cmp: Arc<dyn Comparator>,
 }
 
 impl From<Vec<u8>> for Key {
     fn from(key: Vec<u8>) -> Self {
-        Self {
-            key: Bytes::from(key),
-        }
+        Self::new(Bytes::from(key), Arc::new(BytewiseComparator))
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
     }
 }
 
+impl Eq for Key {}
+
 impl PartialOrd for Key {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let k1 = self.user_key();
-        let k2 = other.user_key();
-        match k1.partial_cmp(k2) {
-            Some(ord) => match ord {
-                std::cmp::Ordering::Equal => {
-                    let seq1 = self.seq();
-                    let seq2 = other.seq();
-                    seq2.partial_cmp(&seq1)
-                }
-                other => Some(other),
-            },
-            None => None,
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -49,7 +54,7 @@ impl Ord for Key {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         let k1 = self.user_key();
         let k2 = other.user_key();
-        match k1.cmp(k2) {
+        match self.cmp.compare(k1, k2) {
             std::cmp::Ordering::Equal => {
                 let seq1 = self.seq();
                 let seq2 = other.seq();
@@ -61,8 +66,8 @@ impl Ord for Key {
 }
 
 impl Key {
-    pub fn new(key: Bytes) -> Self {
-        Self { key }
+    pub fn new(key: Bytes, cmp: Arc<dyn Comparator>) -> Self {
+        Self { key, cmp }
     }
 
     pub fn user_key(&self) -> &[u8] {
@@ -84,6 +89,7 @@ impl Key {
         // &self.key[var_sz..]
     }
 
+    #[allow(dead_code)]
     pub fn tag(key: &[u8]) -> &[u8] {
         let sz = decode_varintu32(key).unwrap();
         let var_sz = varintu32_length(sz) as usize;
@@ -91,6 +97,10 @@ impl Key {
         &key[var_sz + sz as usize..]
     }
 
+    pub fn value_type(&self) -> u8 {
+        self.key[self.key.len() - 1]
+    }
+
     pub fn seq(&self) -> u64 {
         let key = &self.key;
         let len = key.len();
@@ -108,16 +118,21 @@ impl Key {
 /// A basic mem-table based on crossbeam-skiplist
 pub struct MemTable {
     table: Arc<Table>,
-    refs: AtomicU64,
+    // kept apart from the point entries, which they may cover
+    range_dels: RwLock<Vec<RangeTombstone>>,
+    refs: Arc<AtomicU64>,
     size: AtomicU64,
+    cmp: Arc<dyn Comparator>,
 }
 
 impl MemTable {
-    pub fn new() -> Self {
+    pub fn new(cmp: Arc<dyn Comparator>) -> Self {
         Self {
             table: Arc::new(Table::new()),
-            refs: AtomicU64::new(1),
+            range_dels: RwLock::new(Vec::new()),
+            refs: Arc::new(AtomicU64::new(1)),
             size: AtomicU64::new(1),
+            cmp,
         }
     }
 
@@ -125,6 +140,7 @@ impl MemTable {
         self.refs.fetch_add(1, Ordering::SeqCst);
     }
 
+    #[allow(dead_code)]
     pub fn decr_refs(&self) {
         self.refs.fetch_sub(1, Ordering::SeqCst);
         if self.refs.load(Ordering::SeqCst) == 0 {
@@ -132,11 +148,20 @@ impl MemTable {
         }
     }
 
+    #[allow(dead_code)]
     pub fn get(&self, key: &[u8], seq: u64) -> Option<Bytes> {
+        self.lookup(key, seq)
+            .filter(|(_, typ, _)| *typ != OP_TYPE_DELETE)
+            .map(|(_, _, value)| value)
+    }
+
+    /// The newest entry of `key` at or before `seq`, with its sequence and
+    /// operation type. The value of a deletion is empty.
+    pub fn lookup(&self, key: &[u8], seq: u64) -> Option<(u64, u8, Bytes)> {
         let entry = Entry::new(Bytes::from(key.to_vec()), Bytes::new(), seq);
-        let internal_key = MemTable::build_internal_key(&entry, OP_TYPE_PUT);
+        let internal_key = self.build_internal_key(&entry, OP_TYPE_PUT);
         let e = Entry::new(entry.key, Bytes::new(), 0);
-        let right = MemTable::build_internal_key(&e, OP_TYPE_PUT);
+        let right = self.build_internal_key(&e, OP_TYPE_PUT);
         let key = self
             .table
             .range((
@@ -145,19 +170,71 @@ impl MemTable {
             ))
             .next();
         key.map(|e| {
+            let (seq, typ) = (e.key().seq(), e.key().value_type());
             let value = e.value();
             if value.is_empty() {
-                return Bytes::from("");
+                return (seq, typ, Bytes::from(""));
             }
             let value_sz = decode_varintu32(value).unwrap();
-            Bytes::from(value[varintu32_length(value_sz) as usize..].to_vec())
+            let value = Bytes::from(value[varintu32_length(value_sz) as usize..].to_vec());
+            (seq, typ, value)
         })
     }
 
+    /// The sequence of the newest tombstone at or before `seq` covering `key`,
+    /// 0 if there is none.
+    pub fn range_del_seq(&self, key: &[u8], seq: u64) -> u64 {
+        self.range_dels
+            .read()
+            .iter()
+            .filter(|t| t.seq <= seq && t.contains(self.cmp.as_ref(), key))
+            .map(|t| t.seq)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_dels.read().clone()
+    }
+
+    /// The smallest and the largest user key of its entries and range
+    /// tombstones, none if it is empty.
+    pub fn user_key_range(&self) -> Option<(Bytes, Bytes)> {
+        let ucmp = self.cmp.as_ref();
+        let entries = self
+            .table
+            .front()
+            .zip(self.table.back())
+            .map(|(first, last)| {
+                (
+                    Bytes::copy_from_slice(first.key().user_key()),
+                    Bytes::copy_from_slice(last.key().user_key()),
+                )
+            });
+        let tombstones = self.range_dels.read();
+        tombstones
+            .iter()
+            .map(|t| (t.start.clone(), t.end.clone()))
+            .chain(entries)
+            .reduce(|(s1, l1), (s2, l2)| {
+                let smallest = match ucmp.compare(&s1, &s2) {
+                    std::cmp::Ordering::Greater => s2,
+                    _ => s1,
+                };
+                let largest = match ucmp.compare(&l1, &l2) {
+                    std::cmp::Ordering::Less => l2,
+                    _ => l1,
+                };
+                (smallest, largest)
+            })
+    }
+
+    #[allow(dead_code)]
     pub fn put(&self, entry: Entry) {
         self.set(entry, OP_TYPE_PUT);
     }
 
+    #[allow(dead_code)]
     pub fn delete(&self, entry: Entry) {
         self.set(entry, OP_TYPE_DELETE);
     }
@@ -166,8 +243,17 @@ impl MemTable {
     // |  key_size | key | tag |   | value_size | value |
     // +-----------------------+   +--------------------+
     pub fn set(&self, entry: Entry, typ: u8) {
-        let internal_key = MemTable::build_internal_key(&entry, typ);
-        let value = if typ == OP_TYPE_PUT {
+        if typ == OP_TYPE_RANGE_DELETE {
+            self.size.fetch_add(
+                (entry.key.len() + entry.value.len() + 8) as u64,
+                Ordering::SeqCst,
+            );
+            let tombstone = RangeTombstone::new(entry.key, entry.value, entry.seq);
+            self.range_dels.write().push(tombstone);
+            return;
+        }
+        let internal_key = self.build_internal_key(&entry, typ);
+        let value = if typ != OP_TYPE_DELETE {
             MemTable::build_value(&entry)
         } else {
             Bytes::from("")
@@ -177,10 +263,16 @@ impl MemTable {
         self.table.insert(internal_key, value);
     }
 
+    #[allow(dead_code)]
     pub fn colse(&self) {
         self.decr_refs()
     }
 
+    /// Whether nothing, not even a range tombstone, was written to it.
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty() && self.range_dels.read().is_empty()
+    }
+
     pub fn approximate_memory_usage(&self) -> u64 {
         self.size.load(Ordering::SeqCst)
     }
@@ -188,7 +280,7 @@ impl MemTable {
     // +-----------------------+
     // |  key_size | key | tag |
     // +-----------------------+
-    fn build_internal_key(entry: &Entry, typ: u8) -> Key {
+    fn build_internal_key(&self, entry: &Entry, typ: u8) -> Key {
         let key = entry.key();
         let seq = entry.seq();
         let key_sz = key.len() as u32;
@@ -200,7 +292,7 @@ impl MemTable {
         internal_key.put(key);
         internal_key.put_u64((seq << 8) | typ as u64);
 
-        Key::new(Bytes::from(internal_key))
+        Key::new(Bytes::from(internal_key), Arc::clone(&self.cmp))
     }
 
     // +-----------------+
@@ -222,69 +314,140 @@ impl MemTable {
 
 impl Default for MemTable {
     fn default() -> Self {
-        Self::new()
+        Self::new(Arc::new(BytewiseComparator))
     }
 }
 
-pub struct MemTableIterator<'a> {
-    mem: &'a MemTable,
-    table_iter: TableIterator<'a>,
+/// Iterates a mem-table in internal key order.
+///
+/// The iterator is positioned at the first entry when created. Besides the
+/// forward `Iterator` it is an [`InternalIterator`], each step looks the
+/// neighbour up in the skiplist so no borrow of the table is held.
+pub struct MemTableIterator {
+    table: Arc<Table>,
+    refs: Arc<AtomicU64>,
+    cmp: Arc<dyn Comparator>,
+    current: Option<Key>,
     key: Bytes,
     value: Bytes,
 }
 
-impl<'a> Iterator for MemTableIterator<'a> {
+impl Iterator for MemTableIterator {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let item_op = self.table_iter.next();
-        match item_op {
+        let seq = self.current.as_ref()?.seq();
+        let entry = Entry::new(self.key.clone(), self.value.clone(), seq);
+        InternalIterator::next(self);
+        Some(entry)
+    }
+}
+
+impl MemTableIterator {
+    pub fn new(mem: &MemTable) -> Self {
+        mem.incr_refs();
+        let mut iter = Self {
+            table: mem.table.clone(),
+            refs: mem.refs.clone(),
+            cmp: mem.cmp.clone(),
+            current: None,
+            key: Bytes::new(),
+            value: Bytes::new(),
+        };
+        iter.seek_to_first();
+        iter
+    }
+
+    fn set_current(&mut self, entry: Option<crossbeam_skiplist::map::Entry<Key, Bytes>>) {
+        match entry {
             Some(item) => {
                 let value = item.value();
                 self.key = item.key().internal_key();
-
-                if !value.is_empty() {
+                // a deletion carries no value
+                self.value = if value.is_empty() {
+                    Bytes::new()
+                } else {
                     let value_sz = decode_varintu32(value).unwrap();
-                    self.value = value.slice(varintu32_length(value_sz) as usize..);
-                }
-                // self.value = value[varintu32_length(value_sz) as usize..].to_vec();
-                Some(Entry::new(
-                    self.key.clone(),
-                    self.value.clone(),
-                    item.key().seq(),
-                ))
-                // crossbeam_skiplist::map::Entry;
-                // item_op.map(|e| e)
-                // Some(crossbeam_skiplist::map::Entry::from(j))
+                    value.slice(varintu32_length(value_sz) as usize..)
+                };
+                self.current = Some(item.key().clone());
             }
             None => {
-                self.mem.decr_refs();
-                None
+                self.current = None;
+                self.key = Bytes::new();
+                self.value = Bytes::new();
             }
         }
     }
+
+    // internal key -> +-----------------------+
+    //                 |  key_size | key | tag |
+    //                 +-----------------------+
+    fn lookup_key(&self, internal_key: &[u8]) -> Key {
+        let user_key_sz = internal_key.len() - 8;
+        let mut key = vec![];
+        encode_varintu32(&mut key, user_key_sz as u32);
+        key.put_slice(internal_key);
+        Key::new(Bytes::from(key), Arc::clone(&self.cmp))
+    }
 }
 
-impl<'a> MemTableIterator<'a> {
-    pub fn new(mem: &'a MemTable) -> Self {
-        mem.incr_refs();
-        Self {
-            mem,
-            table_iter: mem.table.iter(),
-            key: Bytes::new(),
-            value: Bytes::new(),
+impl InternalIterator for MemTableIterator {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn seek_to_first(&mut self) {
+        let table = self.table.clone();
+        self.set_current(table.front());
+    }
+
+    fn seek_to_last(&mut self) {
+        let table = self.table.clone();
+        self.set_current(table.back());
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        let table = self.table.clone();
+        let key = self.lookup_key(target);
+        self.set_current(table.lower_bound(Bound::Included(&key)));
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        let table = self.table.clone();
+        let key = self.lookup_key(target);
+        self.set_current(table.upper_bound(Bound::Included(&key)));
+    }
+
+    fn next(&mut self) {
+        if let Some(current) = self.current.take() {
+            let table = self.table.clone();
+            self.set_current(table.lower_bound(Bound::Excluded(&current)));
         }
     }
 
-    pub fn key(&self) -> &[u8] {
+    fn prev(&mut self) {
+        if let Some(current) = self.current.take() {
+            let table = self.table.clone();
+            self.set_current(table.upper_bound(Bound::Excluded(&current)));
+        }
+    }
+
+    fn key(&self) -> &[u8] {
         &self.key
     }
 
-    pub fn value(&self) -> &[u8] {
+    fn value(&self) -> &[u8] {
         &self.value
     }
 }
 
+impl Drop for MemTableIterator {
+    fn drop(&mut self) {
+        self.refs.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 #[cfg(test)]
 mod mem_tests {
     use std::sync::atomic::Ordering;
@@ -302,7 +465,7 @@ mod mem_tests {
     }
 
     fn build_base_table() -> MemTable {
-        let memtable = MemTable::new();
+        let memtable = MemTable::default();
         let e = Entry::new(Bytes::from(vec![3]), Bytes::from(vec![30]), 0);
         memtable.put(e);
         let e = Entry::new(Bytes::from(vec![1]), Bytes::from(vec![11]), 1);
@@ -362,7 +525,7 @@ mod mem_tests {
 
     #[test]
     fn mem_iter_test() {
-        let memtable = MemTable::new();
+        let memtable = MemTable::default();
         assert_eq!(memtable.refs.load(Ordering::SeqCst), 1);
         for i in 0..100 {
             let e = Entry::new(Bytes::from(vec![i]), Bytes::from(vec![i]), i as u64);
@@ -391,4 +554,27 @@ mod mem_tests {
         assert_eq!(memtable.refs.load(Ordering::SeqCst), 1);
         // memtable.colse();
     }
+
+    #[test]
+    fn mem_seek_test() {
+        let memtable = build_base_table();
+        let mut iter = MemTableIterator::new(&memtable);
+
+        // newest version of a user key comes first
+        iter.seek(&crate::utils::build_internal_key(&[1], 5, OP_TYPE_PUT));
+        assert!(iter.valid());
+        assert_eq!(iter.value(), &[13]);
+        InternalIterator::next(&mut iter);
+        assert_eq!(iter.value(), &[12]);
+
+        iter.seek_for_prev(&crate::utils::build_internal_key(&[2], 0, OP_TYPE_PUT));
+        assert_eq!(iter.value(), &[11]);
+        iter.prev();
+        assert_eq!(iter.value(), &[12]);
+
+        iter.seek_to_last();
+        assert_eq!(iter.value(), &[254, 233, 234]);
+        InternalIterator::next(&mut iter);
+        assert!(!iter.valid());
+    }
 }
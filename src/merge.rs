@@ -0,0 +1,131 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::{Error, Result};
+
+/// Combines the merge operands of a key with the value they were written on.
+///
+/// Operands written by [`Lsm::merge`](crate::lsm::Lsm::merge) are stored as
+/// they are and combined lazily, when the key is read or compacted, so a
+/// read-modify-write needs no read.
+pub trait MergeOperator: Send + Sync {
+    /// Names the operator in errors.
+    fn name(&self) -> &str;
+
+    /// Apply `operands`, oldest first, to the `existing` value of `key`, none
+    /// if the key has no value. Returning none fails the read.
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>>;
+
+    /// Combine two operands of `key`, `left` being the older, into one with
+    /// the same effect. Returning none keeps both.
+    ///
+    /// Compaction uses it to shorten a chain of operands whose value is not
+    /// in its inputs.
+    fn partial_merge(&self, _key: &[u8], _left: &[u8], _right: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Apply `operands`, newest first as a read meets them, to `existing` with
+/// the merge operator `op`.
+pub(crate) fn full_merge(
+    op: Option<&dyn MergeOperator>,
+    key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &[Bytes],
+) -> Result<Bytes> {
+    let Some(op) = op else {
+        return Err(Error::InvalidArgument(
+            "merge operand read without a merge operator".to_string(),
+        ));
+    };
+    let operands: Vec<&[u8]> = operands.iter().rev().map(|o| &o[..]).collect();
+    op.full_merge(key, existing, &operands)
+        .map(Bytes::from)
+        .ok_or_else(|| Error::MergeFailed(format!("{} on key {:?}", op.name(), key)))
+}
+
+/// Combine the `operands` of `key`, newest first with their internal keys,
+/// into as few as the operator allows. Each combined operand keeps the key of
+/// the newest operand it replaces.
+pub(crate) fn partial_merge(
+    op: &dyn MergeOperator,
+    key: &[u8],
+    operands: Vec<(Bytes, Bytes)>,
+) -> Vec<(Bytes, Bytes)> {
+    // fold from the oldest, so `left` is always the older operand
+    let mut merged: Vec<(Bytes, Bytes)> = Vec::with_capacity(operands.len());
+    for (ikey, operand) in operands.into_iter().rev() {
+        let combined = merged
+            .last()
+            .and_then(|(_, left)| op.partial_merge(key, left, &operand));
+        match combined {
+            Some(combined) => *merged.last_mut().unwrap() = (ikey, Bytes::from(combined)),
+            None => merged.push((ikey, operand)),
+        }
+    }
+    merged.reverse();
+    merged
+}
+
+#[cfg(test)]
+mod merge_test {
+    use bytes::Bytes;
+
+    use super::{partial_merge, MergeOperator};
+
+    // sums little endian u64 operands, and only combines even ones
+    struct EvenSum;
+
+    impl MergeOperator for EvenSum {
+        fn name(&self) -> &str {
+            "even-sum"
+        }
+
+        fn full_merge(
+            &self,
+            _key: &[u8],
+            existing: Option<&[u8]>,
+            operands: &[&[u8]],
+        ) -> Option<Vec<u8>> {
+            let base = existing.map_or(0, |v| u64::from_le_bytes(v.try_into().unwrap()));
+            let sum = operands.iter().fold(base, |sum, o| {
+                sum + u64::from_le_bytes((*o).try_into().unwrap())
+            });
+            Some(sum.to_le_bytes().to_vec())
+        }
+
+        fn partial_merge(&self, key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+            if right[0] % 2 == 1 {
+                return None;
+            }
+            self.full_merge(key, Some(left), &[right])
+        }
+    }
+
+    #[test]
+    fn partial_merge_test() {
+        let operand = |seq: u8, n: u64| {
+            (
+                Bytes::from(vec![seq]),
+                Bytes::from(n.to_le_bytes().to_vec()),
+            )
+        };
+        // newest first: 3 cannot be folded onto 1, the newer ones fold onto 3
+        let operands = vec![operand(5, 4), operand(4, 2), operand(3, 3), operand(2, 1)];
+        let merged = partial_merge(&EvenSum, b"key", operands);
+        assert_eq!(merged, vec![operand(5, 9), operand(2, 1)]);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use bytes::Bytes;
+
+use crate::{
+    utils::{build_internal_key, parse_internal_key, MAX_SEQUENCE, OP_TYPE_RANGE_DELETE},
+    Comparator,
+};
+
+/// Deletes every key in `[start, end)` written before `seq`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub seq: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, seq: u64) -> Self {
+        Self { start, end, seq }
+    }
+
+    /// Whether `key` is in the range, as `ucmp` orders keys.
+    pub fn contains(&self, ucmp: &dyn Comparator, key: &[u8]) -> bool {
+        ucmp.compare(&self.start, key) != Ordering::Greater
+            && ucmp.compare(key, &self.end) == Ordering::Less
+    }
+
+    /// Whether the entry of `key` at `seq` is deleted by this tombstone.
+    pub fn covers(&self, ucmp: &dyn Comparator, key: &[u8], seq: u64) -> bool {
+        seq < self.seq && self.contains(ucmp, key)
+    }
+
+    // A table keeps a tombstone as an entry of its range deletion block:
+    // +------------------------------------+   +-----+
+    // |  start | seq << 8 | RANGE_DELETE   |   | end |
+    // +------------------------------------+   +-----+
+    pub fn internal_key(&self) -> Vec<u8> {
+        build_internal_key(&self.start, self.seq, OP_TYPE_RANGE_DELETE)
+    }
+
+    pub fn decode(internal_key: &[u8], end: &[u8]) -> Self {
+        let (start, seq, _) = parse_internal_key(internal_key);
+        Self::new(
+            Bytes::copy_from_slice(start),
+            Bytes::copy_from_slice(end),
+            seq,
+        )
+    }
+
+    /// The internal key bounding a table from above for this tombstone. It
+    /// sorts before every entry of `end`, which the tombstone does not cover.
+    pub fn largest_key(&self) -> Vec<u8> {
+        build_internal_key(&self.end, MAX_SEQUENCE, OP_TYPE_RANGE_DELETE)
+    }
+}
+
+/// The range tombstones visible at a read sequence, sorted by start.
+pub struct RangeTombstones {
+    ucmp: Arc<dyn Comparator>,
+    sequence: u64,
+    tombstones: Vec<RangeTombstone>,
+}
+
+impl RangeTombstones {
+    pub fn new(ucmp: Arc<dyn Comparator>, sequence: u64) -> Self {
+        Self {
+            ucmp,
+            sequence,
+            tombstones: Vec::new(),
+        }
+    }
+
+    pub fn extend<'a>(&mut self, tombstones: impl IntoIterator<Item = &'a RangeTombstone>) {
+        let sequence = self.sequence;
+        self.tombstones.extend(
+            tombstones
+                .into_iter()
+                .filter(|t| t.seq <= sequence)
+                .cloned(),
+        );
+        let ucmp = &self.ucmp;
+        self.tombstones
+            .sort_by(|t1, t2| ucmp.compare(&t1.start, &t2.start));
+    }
+
+    /// Whether the entry of `key` at `seq` is deleted by a visible tombstone.
+    pub fn covers(&self, key: &[u8], seq: u64) -> bool {
+        let ucmp = self.ucmp.as_ref();
+        self.tombstones
+            .iter()
+            .take_while(|t| ucmp.compare(&t.start, key) != Ordering::Greater)
+            .any(|t| t.covers(ucmp, key, seq))
+    }
+
+    /// Whether a visible tombstone deletes every entry of a table spanning
+    /// user keys `[smallest, largest]` whose newest entry is at `largest_seq`.
+    pub fn covers_range(&self, smallest: &[u8], largest: &[u8], largest_seq: u64) -> bool {
+        let ucmp = self.ucmp.as_ref();
+        self.tombstones
+            .iter()
+            .take_while(|t| ucmp.compare(&t.start, smallest) != Ordering::Greater)
+            .any(|t| largest_seq < t.seq && ucmp.compare(largest, &t.end) == Ordering::Less)
+    }
+}
+
+#[cfg(test)]
+mod range_del_test {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use crate::BytewiseComparator;
+
+    use super::{RangeTombstone, RangeTombstones};
+
+    fn tombstone(start: &str, end: &str, seq: u64) -> RangeTombstone {
+        RangeTombstone::new(
+            Bytes::from(start.to_owned()),
+            Bytes::from(end.to_owned()),
+            seq,
+        )
+    }
+
+    #[test]
+    fn range_tombstones_test() {
+        let t = tombstone("b", "d", 10);
+        let ucmp = &BytewiseComparator;
+        assert!(t.covers(ucmp, b"b", 9) && t.covers(ucmp, b"c", 1));
+        assert!(!t.covers(ucmp, b"d", 9) && !t.covers(ucmp, b"a", 9));
+        assert!(!t.covers(ucmp, b"c", 10));
+        assert_eq!(RangeTombstone::decode(&t.internal_key(), b"d"), t);
+
+        // tombstones newer than the read sequence are ignored
+        let mut tombstones = RangeTombstones::new(Arc::new(BytewiseComparator), 20);
+        tombstones.extend(&[tombstone("f", "h", 30), t, tombstone("a", "c", 5)]);
+        assert!(tombstones.covers(b"a", 4) && tombstones.covers(b"c", 9));
+        assert!(!tombstones.covers(b"c", 10) && !tombstones.covers(b"g", 1));
+
+        assert!(tombstones.covers_range(b"b", b"c", 9));
+        assert!(!tombstones.covers_range(b"b", b"d", 9));
+        assert!(!tombstones.covers_range(b"b", b"c", 10));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use parking_lot::Mutex;
+
+/// Sequence numbers of the live snapshots, with the number of handles
+/// holding each of them.
+#[derive(Default)]
+pub(crate) struct SnapshotList {
+    seqs: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    /// Register a snapshot at the sequence returned by `sequence`, which is
+    /// read under the list lock so that it is ordered against `oldest_or`.
+    pub fn acquire(self: &Arc<Self>, sequence: impl FnOnce() -> u64) -> Snapshot {
+        let mut seqs = self.seqs.lock();
+        let seq = sequence();
+        *seqs.entry(seq).or_insert(0) += 1;
+        Snapshot {
+            inner: Arc::new(SnapshotInner {
+                seq,
+                list: Arc::clone(self),
+            }),
+        }
+    }
+
+    fn release(&self, seq: u64) {
+        let mut seqs = self.seqs.lock();
+        if let Some(cnt) = seqs.get_mut(&seq) {
+            *cnt -= 1;
+            if *cnt == 0 {
+                seqs.remove(&seq);
+            }
+        }
+    }
+
+    /// The sequence of the oldest live snapshot, or `sequence` if there is
+    /// none.
+    pub fn oldest_or(&self, sequence: impl FnOnce() -> u64) -> u64 {
+        let seqs = self.seqs.lock();
+        match seqs.keys().next() {
+            Some(seq) => *seq,
+            None => sequence(),
+        }
+    }
+
+    /// The sequence of the newest live snapshot, if any.
+    pub fn newest(&self) -> Option<u64> {
+        self.seqs.lock().keys().next_back().copied()
+    }
+}
+
+struct SnapshotInner {
+    seq: u64,
+    list: Arc<SnapshotList>,
+}
+
+impl Drop for SnapshotInner {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
+
+/// A consistent read view of an [`Lsm`](crate::lsm::Lsm).
+///
+/// Reads through a snapshot see exactly the writes made before it was taken.
+/// Compaction keeps every version a live snapshot can see; the snapshot is
+/// released once its last clone is dropped.
+#[derive(Clone)]
+pub struct Snapshot {
+    inner: Arc<SnapshotInner>,
+}
+
+impl Snapshot {
+    pub fn sequence(&self) -> u64 {
+        self.inner.seq
+    }
+}
+
+#[cfg(test)]
+mod snapshot_test {
+    use std::sync::Arc;
+
+    use super::SnapshotList;
+
+    #[test]
+    fn snapshot_list_test() {
+        let list = Arc::new(SnapshotList::default());
+        assert_eq!(list.oldest_or(|| 9), 9);
+
+        let s1 = list.acquire(|| 5);
+        let s2 = list.acquire(|| 3);
+        let s3 = s2.clone();
+        let s4 = list.acquire(|| 3);
+        assert_eq!(list.oldest_or(|| 9), 3);
+
+        drop(s2);
+        drop(s4);
+        assert_eq!(list.oldest_or(|| 9), 3);
+        drop(s3);
+        assert_eq!(list.oldest_or(|| 9), 5);
+        assert_eq!(s1.sequence(), 5);
+        drop(s1);
+        assert_eq!(list.oldest_or(|| 9), 9);
+    }
+}
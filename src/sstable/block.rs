@@ -1,25 +1,35 @@
-use std::sync::Arc;
+use std::{cmp::Ordering, ops::Range, sync::Arc};
 
-use bytes::{Buf, BufMut, Bytes};
+use bytes::{Buf, Bytes};
 
-use crate::utils::{
-    codec::{decode_varintu32, varintu32_length, verify_checksum},
-    Entry,
+use crate::{
+    iterator::InternalIterator,
+    utils::{
+        codec::{encode_varintu64, take_bytes, take_varintu32, take_varintu64, verify_checksum},
+        compare_internal_key, Entry,
+    },
+    BytewiseComparator, Comparator, CompressionType,
 };
 
-use super::{Result, TableError};
+use super::TableError;
 
 pub const SIZEOF_U32: usize = std::mem::size_of::<u32>();
 pub const SIZEOF_U64: usize = std::mem::size_of::<u64>();
-pub const BLOCK_TRAILER_SIZE_: usize = 8;
+/// Compression type and checksum following a block since format version 4.
+pub const BLOCK_TRAILER_SIZE: usize = 1 + SIZEOF_U64;
 
+/// Locates a block in its table file. Since format version 3 it is encoded
+/// as two varints, of at most [`MAX_ENCODED_LENGTH`](Self::MAX_ENCODED_LENGTH)
+/// bytes; before, as two u32.
 #[derive(Clone, Debug)]
 pub struct BlockHandler {
-    offset: u32,
-    block_size: u32,
+    offset: u64,
+    block_size: u64,
 }
 
 impl BlockHandler {
+    pub const MAX_ENCODED_LENGTH: usize = 20;
+
     pub fn new() -> Self {
         Self {
             offset: 0,
@@ -27,94 +37,181 @@ impl BlockHandler {
         }
     }
 
-    pub fn offset(&self) -> u32 {
+    pub fn offset(&self) -> u64 {
         self.offset
     }
 
-    pub fn block_size(&self) -> u32 {
+    pub fn block_size(&self) -> u64 {
         self.block_size
     }
 
-    pub fn decode(data: Bytes) -> Result<Self> {
+    pub fn decode(data: &[u8]) -> Result<Self, TableError> {
+        let mut buf = data;
+        let offset = take_varintu64(&mut buf).map_err(|_| TableError::DecodeBlockHandlerError)?;
+        let block_size =
+            take_varintu64(&mut buf).map_err(|_| TableError::DecodeBlockHandlerError)?;
+        Ok(Self { offset, block_size })
+    }
+
+    /// Decode a handle of a table before format version 3.
+    pub fn decode_fixed32(data: &[u8]) -> Result<Self, TableError> {
         if data.len() < 8 {
             return Err(TableError::DecodeBlockHandlerError);
         }
-        let offset = (&data[..4]).get_u32();
-        let block_size = (&data[4..]).get_u32();
+        let offset = (&data[..4]).get_u32() as u64;
+        let block_size = (&data[4..]).get_u32() as u64;
         Ok(Self { offset, block_size })
     }
 
-    // pub fn offset(&self) -> u32 {
-    //     self.offset
-    // }
-    pub fn set_offset(&mut self, offset: u32) {
+    pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
-    // pub fn block_size(&self) -> u32 {
-    //     self.block_size
-    // }
-    pub fn set_block_size(&mut self, block_size: u32) {
+
+    pub fn set_block_size(&mut self, block_size: u64) {
         self.block_size = block_size;
     }
 
     pub fn to_vec(&self) -> Vec<u8> {
         let mut buf = Vec::new();
-        buf.put_u32(self.offset);
-        buf.put_u32(self.block_size);
+        encode_varintu64(&mut buf, self.offset);
+        encode_varintu64(&mut buf, self.block_size);
         buf
     }
 }
 
+/// How a block is laid out and stored, fixed by the format version of its
+/// table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// Format version 0: each key in full, with the offset of every entry.
+    Legacy,
+    /// Format versions 1 to 3: each key as the suffix it does not share with
+    /// the previous key, in full at the restart points.
+    PrefixCompressed,
+    /// As `PrefixCompressed`, compressed by the type in the trailer after it.
+    /// Before, blocks were lz4-compressed with their checksum inside.
+    Trailer,
+}
+
+impl BlockFormat {
+    pub fn of_version(format_version: u32) -> Self {
+        match format_version {
+            0 => BlockFormat::Legacy,
+            1..=3 => BlockFormat::PrefixCompressed,
+            _ => BlockFormat::Trailer,
+        }
+    }
+
+    /// Bytes of a block not counted in the size of its handle.
+    pub fn trailer_size(&self) -> usize {
+        match self {
+            BlockFormat::Legacy | BlockFormat::PrefixCompressed => SIZEOF_U64,
+            BlockFormat::Trailer => BLOCK_TRAILER_SIZE,
+        }
+    }
+
+    // decompress a block as stored, checking its checksum
+    fn contents(&self, data: &[u8]) -> Result<Vec<u8>, TableError> {
+        let corrupted = |reason: String| TableError::DecodeBlockError(reason);
+        if *self != BlockFormat::Trailer {
+            let mut data = lz4_flex::decompress_size_prepended(data)
+                .map_err(|err| corrupted(err.to_string()))?;
+            let Some(len) = data.len().checked_sub(SIZEOF_U64) else {
+                return Err(corrupted("block too short".to_string()));
+            };
+            let checksum = (&data[len..]).get_u64();
+            verify_checksum(&data[..len], checksum).map_err(|err| corrupted(err.to_string()))?;
+            data.truncate(len);
+            return Ok(data);
+        }
+
+        let Some(len) = data.len().checked_sub(BLOCK_TRAILER_SIZE) else {
+            return Err(corrupted("block too short".to_string()));
+        };
+        let checksum = (&data[len + 1..]).get_u64();
+        verify_checksum(&data[..len + 1], checksum).map_err(|err| corrupted(err.to_string()))?;
+        CompressionType::try_from(data[len])
+            .and_then(|compression| compression.decompress(&data[..len]))
+            .map_err(corrupted)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Block {
     data: Bytes,
-    entry_offsets: Vec<u32>,
+    // offsets of the entries a seek may start decoding from
+    restarts: Vec<u32>,
+    format: BlockFormat,
 }
 
 impl Block {
-    pub fn decode(data: &[u8]) -> Self {
-        let data = lz4_flex::decompress_size_prepended(data).unwrap();
-        let len = data.len();
-        let checksum = (&data[len - SIZEOF_U64..]).get_u64();
-        verify_checksum(&data[..len - SIZEOF_U64], checksum).unwrap();
-
-        let offset_end = data.len() - SIZEOF_U64 - SIZEOF_U32;
-        let num_offset = (&data[offset_end..]).get_u32();
-        let data_end = offset_end - num_offset as usize * SIZEOF_U32;
-        Self {
-            data: Bytes::from(data[..data_end].to_vec()),
-            entry_offsets: data[data_end..offset_end]
-                .chunks(SIZEOF_U32)
-                .map(|mut x| x.get_u32())
-                .collect(),
+    pub fn decode(data: &[u8], format: BlockFormat) -> Result<Self, TableError> {
+        let corrupted = |reason: &str| TableError::DecodeBlockError(reason.to_string());
+        let data = format.contents(data)?;
+        let Some(offset_end) = data.len().checked_sub(SIZEOF_U32) else {
+            return Err(corrupted("block too short"));
+        };
+        let num_offset = (&data[offset_end..]).get_u32() as usize;
+        let data_end = num_offset
+            .checked_mul(SIZEOF_U32)
+            .and_then(|size| offset_end.checked_sub(size))
+            .ok_or_else(|| corrupted("bad entry offsets"))?;
+        let mut restarts: Vec<u32> = data[data_end..offset_end]
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
+            .collect();
+        if format == BlockFormat::Legacy {
+            // the last offset marks the end of data
+            restarts.pop();
         }
-    }
-
-    pub fn read_entry_at(&self, offset: usize) -> Option<Entry> {
-        if offset >= self.data.len() {
-            return None;
+        if restarts.iter().any(|off| *off as usize > data_end) {
+            return Err(corrupted("entry offset out of range"));
         }
-        let e = Self::decode_entry(&self.data[offset..]);
-        Some(e)
+        Ok(Self {
+            data: Bytes::from(data[..data_end].to_vec()),
+            restarts,
+            format,
+        })
     }
 
-    fn decode_entry(data: &[u8]) -> Entry {
-        // decode key
-        let key_sz = decode_varintu32(data).unwrap();
-        let varint_key_sz = varintu32_length(key_sz) as usize;
-        let key = data[varint_key_sz..varint_key_sz + key_sz as usize].to_vec();
-
-        // decode value
-        let value_data = &data[varint_key_sz + key_sz as usize..];
-        let value_sz = decode_varintu32(value_data).unwrap();
-        let varint_value_sz = varintu32_length(key_sz) as usize;
-        let value = value_data[varint_value_sz..varint_value_sz + value_sz as usize].to_vec();
-        Entry::new(Bytes::from(key), Bytes::from(value), 0)
+    /// Memory held by the decoded block.
+    pub fn size(&self) -> usize {
+        self.data.len() + self.restarts.len() * SIZEOF_U32
     }
 
-    // pub fn append(&mut self, data: &[u8]) {
-    //     self.data.put(data);
-    // }
+    // Decode the entry at `offset` over `key`, which holds the key of the
+    // entry before. Returns the range of its value and the offset of the next
+    // entry, none if the entry is malformed.
+    fn decode_entry(&self, offset: usize, key: &mut Vec<u8>) -> Option<(Range<usize>, usize)> {
+        let mut buf = self.data.get(offset..)?;
+        let (shared, unshared) = match self.format {
+            BlockFormat::Legacy => (0, take_varintu32(&mut buf).ok()? as usize),
+            BlockFormat::PrefixCompressed | BlockFormat::Trailer => {
+                let shared = take_varintu32(&mut buf).ok()? as usize;
+                (shared, take_varintu32(&mut buf).ok()? as usize)
+            }
+        };
+        let (key_delta, value_len) = match self.format {
+            BlockFormat::Legacy => {
+                let key_delta = take_bytes(&mut buf, unshared).ok()?;
+                (key_delta, take_varintu32(&mut buf).ok()? as usize)
+            }
+            BlockFormat::PrefixCompressed | BlockFormat::Trailer => {
+                let value_len = take_varintu32(&mut buf).ok()? as usize;
+                (take_bytes(&mut buf, unshared).ok()?, value_len)
+            }
+        };
+        if shared > key.len() || buf.len() < value_len {
+            return None;
+        }
+        key.truncate(shared);
+        key.extend_from_slice(key_delta);
+        let value_start = self.data.len() - buf.len();
+        Some((
+            value_start..value_start + value_len,
+            value_start + value_len,
+        ))
+    }
 }
 
 impl IntoIterator for Block {
@@ -122,77 +219,172 @@ impl IntoIterator for Block {
 
     type IntoIter = BlockIterator;
 
+    // walked in order only, no key is compared
     fn into_iter(self) -> Self::IntoIter {
-        BlockIterator::new(Arc::new(self))
+        BlockIterator::new(Arc::new(self), Arc::new(BytewiseComparator))
     }
 }
 
+/// Iterates the entries of a block, positioned at the first entry when created.
+/// Seeks order the internal keys of the block by the user key comparator
+/// `ucmp`, searching the restart points before decoding entries one by one.
 pub struct BlockIterator {
     block: Arc<Block>,
-    idx: usize,
+    ucmp: Arc<dyn Comparator>,
+    // offset of the current entry, the end of data once invalid
+    current: usize,
+    // offset of the entry after the current one
+    next: usize,
+    // the last restart point at or before the current entry
+    restart_index: usize,
+    key: Vec<u8>,
+    value: Range<usize>,
 }
 
 impl BlockIterator {
-    pub fn new(block: Arc<Block>) -> Self {
-        Self { block, idx: 0 }
+    pub fn new(block: Arc<Block>, ucmp: Arc<dyn Comparator>) -> Self {
+        let end = block.data.len();
+        let mut iter = Self {
+            block,
+            ucmp,
+            current: end,
+            next: end,
+            restart_index: 0,
+            key: Vec::new(),
+            value: 0..0,
+        };
+        iter.seek_to_first();
+        iter
     }
 
-    fn seek_to(&self, idx: usize) -> Option<Entry> {
-        if idx >= self.block.entry_offsets.len() {
-            return None;
+    fn invalidate(&mut self) {
+        self.current = self.block.data.len();
+        self.next = self.current;
+        self.key.clear();
+        self.value = 0..0;
+    }
+
+    // position before the entry at restart point `index`
+    fn seek_to_restart(&mut self, index: usize) {
+        self.restart_index = index;
+        self.key.clear();
+        self.next = self.block.restarts[index] as usize;
+    }
+
+    // move to the entry at `next`, returning whether there is one
+    fn parse_next(&mut self) -> bool {
+        self.current = self.next;
+        if self.current >= self.block.data.len() {
+            self.invalidate();
+            return false;
+        }
+        match self.block.decode_entry(self.current, &mut self.key) {
+            Some((value, next)) => {
+                self.value = value;
+                self.next = next;
+                let restarts = &self.block.restarts;
+                while self.restart_index + 1 < restarts.len()
+                    && (restarts[self.restart_index + 1] as usize) <= self.current
+                {
+                    self.restart_index += 1;
+                }
+                true
+            }
+            None => {
+                self.invalidate();
+                false
+            }
         }
-        let offset = self.block.entry_offsets[idx];
-        self.block.read_entry_at(offset as usize)
     }
 
-    pub fn seek(&mut self, key: &[u8]) -> Option<Entry> {
-        // self.block.
-        let (mut low, mut high) = (0, self.block.entry_offsets.len() - 1);
-        // let target_key = Key::new(key.to_vec());
-        while low < high {
-            let mid = ((high - low) >> 1) + low;
-            let offset = self.block.entry_offsets[mid];
-            let entry = self.block.read_entry_at(offset as usize).unwrap();
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        compare_internal_key(self.ucmp.as_ref(), a, b)
+    }
+}
+
+impl InternalIterator for BlockIterator {
+    fn valid(&self) -> bool {
+        self.current < self.block.data.len()
+    }
+
+    fn seek_to_first(&mut self) {
+        if self.block.restarts.is_empty() {
+            self.invalidate();
+            return;
+        }
+        self.seek_to_restart(0);
+        self.parse_next();
+    }
+
+    fn seek_to_last(&mut self) {
+        if self.block.restarts.is_empty() {
+            self.invalidate();
+            return;
+        }
+        self.seek_to_restart(self.block.restarts.len() - 1);
+        while self.parse_next() && self.next < self.block.data.len() {}
+    }
 
-            if BlockIterator::greater_or_equal(&entry.key, key) {
-                high = mid;
+    fn seek(&mut self, target: &[u8]) {
+        if self.block.restarts.is_empty() {
+            self.invalidate();
+            return;
+        }
+        // the last restart point whose key is before the target
+        let (mut low, mut high) = (0, self.block.restarts.len() - 1);
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            let mut key = vec![];
+            let offset = self.block.restarts[mid] as usize;
+            if self.block.decode_entry(offset, &mut key).is_none() {
+                self.invalidate();
+                return;
+            }
+            if self.compare(&key, target) == Ordering::Less {
+                low = mid;
             } else {
-                low = mid + 1;
+                high = mid - 1;
             }
         }
+        self.seek_to_restart(low);
+        while self.parse_next() {
+            if self.compare(&self.key, target) != Ordering::Less {
+                return;
+            }
+        }
+    }
 
-        self.idx = low;
-        self.seek_to(low)
-    }
-
-    // fn less_or_equal(key: &[u8], target: &[u8]) -> bool {
-    //     let user_key1 = &key[..key.len() - 8];
-    //     let user_key2 = &target[..target.len() - 8];
-    //     // user_key1.
-    //     match user_key1.cmp(user_key2) {
-    //         std::cmp::Ordering::Less => true,
-    //         std::cmp::Ordering::Greater => false,
-    //         std::cmp::Ordering::Equal => {
-    //             let seq1 = (&key[key.len() - 8..]).get_u64() >> 8;
-    //             let seq2 = (&target[target.len() - 8..]).get_u64() >> 8;
-    //             seq1 <= seq2
-    //         }
-    //     }
-    // }
-
-    fn greater_or_equal(key: &[u8], target: &[u8]) -> bool {
-        let user_key1 = &key[..key.len() - 8];
-        let user_key2 = &target[..target.len() - 8];
-        // user_key1.
-        match user_key1.cmp(user_key2) {
-            std::cmp::Ordering::Less => false,
-            std::cmp::Ordering::Greater => true,
-            std::cmp::Ordering::Equal => {
-                let seq1 = (&key[key.len() - 8..]).get_u64() >> 8;
-                let seq2 = (&target[target.len() - 8..]).get_u64() >> 8;
-                seq1 <= seq2
+    fn next(&mut self) {
+        if self.valid() {
+            self.parse_next();
+        }
+    }
+
+    fn prev(&mut self) {
+        if !self.valid() {
+            // back from past the end onto the last entry
+            self.seek_to_last();
+            return;
+        }
+        // scan forward from the restart point before the current entry
+        let original = self.current;
+        while self.block.restarts[self.restart_index] as usize >= original {
+            if self.restart_index == 0 {
+                self.invalidate();
+                return;
             }
+            self.restart_index -= 1;
         }
+        self.seek_to_restart(self.restart_index);
+        while self.parse_next() && self.next < original {}
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.block.data[self.value.clone()]
     }
 }
 
@@ -200,9 +392,16 @@ impl Iterator for BlockIterator {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let res = self.seek_to(self.idx);
-        self.idx += 1;
-        res
+        if !self.valid() {
+            return None;
+        }
+        let entry = Entry::new(
+            Bytes::copy_from_slice(&self.key),
+            self.block.data.slice(self.value.clone()),
+            0,
+        );
+        self.parse_next();
+        Some(entry)
     }
 }
 
@@ -210,22 +409,22 @@ impl Iterator for BlockIterator {
 mod block_test {
     use std::{io::Read, sync::Arc};
 
-    use bytes::{Buf, Bytes};
+    use bytes::Bytes;
 
     use crate::{
         file::{path_of_file, Ext},
         mem_table::{MemTable, MemTableIterator},
-        sstable::table_builder::TableBuilder,
-        utils::Entry,
+        sstable::{block_builder::BlockBuilder, table_builder::TableBuilder},
+        utils::{build_internal_key, Entry, OP_TYPE_PUT},
         version::FileMetaData,
-        Options,
+        BytewiseComparator, CompressionType, Options,
     };
 
-    use super::{Block, BlockIterator};
+    use super::{Block, BlockFormat, BlockHandler, BlockIterator, BLOCK_TRAILER_SIZE};
 
     #[test]
     fn block_test() {
-        let mem = MemTable::new();
+        let mem = MemTable::default();
         for i in 0..300 {
             let e = Entry::new(
                 Bytes::from((i as u32).to_be_bytes().to_vec()),
@@ -248,7 +447,9 @@ mod block_test {
         TableBuilder::build_table(
             path.as_path(),
             opt,
+            0,
             MemTableIterator::new(&mem),
+            &[],
             &mut file_meta,
         )
         .unwrap();
@@ -258,18 +459,16 @@ mod block_test {
         let mut buf = Vec::new();
         file.read_to_end(&mut buf).unwrap();
 
-        let len = buf.len();
-        (&buf[len - 4..]).get_u32(); // index block size
-        let _index_offset: u32 = (&buf[len - 8..]).get_u32(); // index block offset
-        (&buf[len - 12..]).get_u32(); // filter block size
-        let filter_offset: u32 = (&buf[len - 16..]).get_u32(); // filter block offset
-                                                               // let checksum = buf[len-8..]
+        // the handles of the footer are padded to 20 bytes, the filter handle
+        // second
+        let footer = &buf[buf.len() - 93..];
+        let filter_offset = BlockHandler::decode(&footer[20..]).unwrap().offset();
 
-        let block = Block::decode(&buf[..filter_offset as usize]);
+        let block = Block::decode(&buf[..filter_offset as usize], BlockFormat::Trailer).unwrap();
         // let block = Block::decode(&buf[..index_offset as usize]);
-        let iter = BlockIterator::new(Arc::new(block));
+        let iter = BlockIterator::new(Arc::new(block), Arc::new(BytewiseComparator));
         let mut count = 0;
-        iter.enumerate().for_each(|(_, ele)| {
+        iter.for_each(|ele| {
             let e = mem_iter.next().unwrap();
             count += 1;
             assert_eq!(ele.key, e.key);
@@ -277,4 +476,112 @@ mod block_test {
         });
         assert_eq!(count, 300);
     }
+
+    #[test]
+    fn block_restart_test() {
+        use crate::iterator::InternalIterator;
+
+        let key = |i: u32| build_internal_key(format!("key{:04}", i).as_bytes(), 7, OP_TYPE_PUT);
+        for restart_interval in [1, 3, 16] {
+            let mut builder = BlockBuilder::new(restart_interval);
+            // the even keys only
+            for i in 0..100 {
+                builder.add(&key(i * 2), &i.to_be_bytes());
+            }
+            let block =
+                Block::decode(builder.finish(CompressionType::Lz4), BlockFormat::Trailer).unwrap();
+            let mut iter = BlockIterator::new(Arc::new(block), Arc::new(BytewiseComparator));
+            for i in 0..200 {
+                iter.seek(&key(i));
+                if i >= 199 {
+                    assert!(!iter.valid());
+                    continue;
+                }
+                assert_eq!(iter.key(), key(i.div_ceil(2) * 2));
+                assert_eq!(iter.value(), (i.div_ceil(2)).to_be_bytes());
+            }
+
+            // from the last entry backward
+            iter.seek_to_last();
+            for i in (0..100).rev() {
+                assert_eq!(iter.key(), key(i * 2));
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            iter.prev();
+            assert_eq!(iter.key(), key(198));
+
+            iter.seek(&key(51));
+            iter.prev();
+            assert_eq!(iter.key(), key(50));
+            InternalIterator::next(&mut iter);
+            InternalIterator::next(&mut iter);
+            assert_eq!(iter.key(), key(54));
+        }
+    }
+
+    #[test]
+    fn block_empty_test() {
+        use crate::iterator::InternalIterator;
+
+        let mut builder = BlockBuilder::new(16);
+        let block =
+            Block::decode(builder.finish(CompressionType::Lz4), BlockFormat::Trailer).unwrap();
+        let mut iter = BlockIterator::new(Arc::new(block), Arc::new(BytewiseComparator));
+        assert!(!iter.valid());
+        iter.seek(b"key");
+        assert!(!iter.valid());
+        iter.prev();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn block_compression_test() {
+        use crate::iterator::InternalIterator;
+
+        let key = |i: u32| build_internal_key(format!("key{:04}", i).as_bytes(), 7, OP_TYPE_PUT);
+        // repeating values shrink, those of a random walk do not
+        let mut state = 17_u64;
+        let mut random = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as u8
+        };
+        let noise: Vec<Vec<u8>> = (0..100)
+            .map(|_| (0..256).map(|_| random()).collect())
+            .collect();
+        for compression in [
+            CompressionType::None,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+            CompressionType::Snappy,
+        ] {
+            for (compressible, stored) in [(true, compression), (false, CompressionType::None)] {
+                let value = |i: u32| match compressible {
+                    true => vec![b'v'; 64],
+                    false => noise[i as usize].clone(),
+                };
+                let mut builder = BlockBuilder::new(16);
+                for i in 0..100 {
+                    builder.add(&key(i), &value(i));
+                }
+                let data = builder.finish(compression).to_vec();
+                assert_eq!(data[data.len() - BLOCK_TRAILER_SIZE], stored as u8);
+
+                let block = Block::decode(&data, BlockFormat::Trailer).unwrap();
+                let mut iter = BlockIterator::new(Arc::new(block), Arc::new(BytewiseComparator));
+                for i in 0..100 {
+                    assert_eq!((iter.key(), iter.value()), (&key(i)[..], &value(i)[..]));
+                    InternalIterator::next(&mut iter);
+                }
+                assert!(!iter.valid());
+
+                // a flipped bit of the compressed data fails the checksum
+                let mut corrupted = data.clone();
+                corrupted[3] ^= 0x10;
+                assert!(Block::decode(&corrupted, BlockFormat::Trailer).is_err());
+            }
+        }
+    }
 }
@@ -1,61 +1,109 @@
 use bytes::BufMut;
 
-use crate::utils::{
-    codec::{calculate_checksum, encode_varintu32},
-    convert::u32vec_to_bytes,
+use crate::{
+    utils::{
+        codec::{calculate_checksum, encode_varintu32},
+        convert::u32vec_to_bytes,
+    },
+    CompressionType,
 };
 
 use super::block::SIZEOF_U32;
 
 /// BlockBuilder write data to Blockm
 ///
-/// +--------------------- --------------------------+
-/// |  data | entryOffsets | entryOff len | checksum |
-/// +------------------------------------------------+
+/// +------------------------------------------------------------+
+/// |  data | restarts | num restarts | compression | checksum |
+/// +------------------------------------------------------------+
 ///
+/// All before the compression type is compressed, unless compressing does
+/// not save an eighth of it. The checksum covers the block as stored.
+///
+/// An entry keeps only the part of its key it does not share with the key
+/// before it, as `shared | unshared | value len | key delta | value`. Every
+/// `restart_interval` entries the key is kept in full, at a restart point a
+/// seek can start decoding from.
 #[derive(Clone)]
 pub struct BlockBuilder {
     data: Vec<u8>,
-    entry_offsets: Vec<u32>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    // entries since the last restart point
+    counter: usize,
+    last_key: Vec<u8>,
 }
 
 impl BlockBuilder {
-    pub fn new() -> Self {
+    pub fn new(restart_interval: usize) -> Self {
         Self {
             data: Vec::new(),
-            entry_offsets: vec![0],
+            restarts: vec![0],
+            restart_interval: restart_interval.max(1),
+            counter: 0,
+            last_key: Vec::new(),
         }
     }
 
     pub fn add(&mut self, key: &[u8], value: &[u8]) {
-        // encode key
-        encode_varintu32(&mut self.data, key.len() as u32);
-        self.data.put(key);
+        let mut shared = 0;
+        if self.counter < self.restart_interval {
+            shared = self
+                .last_key
+                .iter()
+                .zip(key)
+                .take_while(|(a, b)| a == b)
+                .count();
+        } else {
+            self.restarts.push(self.data.len() as u32);
+            self.counter = 0;
+        }
 
-        // encode value
+        encode_varintu32(&mut self.data, shared as u32);
+        encode_varintu32(&mut self.data, (key.len() - shared) as u32);
         encode_varintu32(&mut self.data, value.len() as u32);
+        self.data.put(&key[shared..]);
         self.data.put(value);
 
-        self.entry_offsets.push(self.data.len() as u32);
-        // self.offset += entry_data.len() as u32;
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        self.counter += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
     }
 
     pub fn estimated_size(&self) -> usize {
-        self.data.len() + self.entry_offsets.len() * SIZEOF_U32
+        self.data.len() + self.restarts.len() * SIZEOF_U32
     }
 
     pub fn reset(&mut self) {
         self.data.clear();
-        self.entry_offsets.clear();
-        self.entry_offsets.push(0);
+        self.restarts.clear();
+        self.restarts.push(0);
+        self.counter = 0;
+        self.last_key.clear();
     }
 
-    pub fn finish(&mut self) -> &[u8] {
-        self.data.put(&u32vec_to_bytes(&self.entry_offsets)[..]);
-        self.data.put_u32(self.entry_offsets.len() as u32);
+    pub fn finish(&mut self, compression: CompressionType) -> &[u8] {
+        if self.data.is_empty() {
+            // nothing to restart from
+            self.restarts.clear();
+        }
+        self.data.put(&u32vec_to_bytes(&self.restarts)[..]);
+        self.data.put_u32(self.restarts.len() as u32);
+
+        let raw_size = self.data.len();
+        match compression.compress(&self.data) {
+            Ok(compressed) if compressed.len() < raw_size - raw_size / 8 => {
+                self.data = compressed;
+                self.data.put_u8(compression as u8);
+            }
+            // kept raw
+            _ => self.data.put_u8(CompressionType::None as u8),
+        }
         let checksum = calculate_checksum(&self.data);
         self.data.put_u64(checksum);
-        self.data = lz4_flex::compress_prepend_size(&self.data);
         &self.data
     }
 }
@@ -1,48 +1,179 @@
-use crate::utils::Entry;
+use std::{cmp::Ordering, sync::Arc};
 
-use super::table::TableIterator;
+use bytes::Bytes;
 
+use crate::{
+    iterator::{Direction, InternalIterator},
+    utils::{compare_internal_key, Entry},
+    Comparator, Result,
+};
+
+/// Merges several iterators sorted by the user key comparator `ucmp` into
+/// one. Children are expected to be positioned at their first entry when
+/// handed over.
 pub struct MergeIterator {
-    iters: Vec<TableIterator>,
-    idx: usize,
-    current: Option<Entry>,
+    iters: Vec<Box<dyn InternalIterator>>,
+    ucmp: Arc<dyn Comparator>,
+    current: Option<usize>,
+    direction: Direction,
 }
 
 impl MergeIterator {
-    pub fn new(iters: Vec<TableIterator>) -> Self {
-        Self {
+    pub fn new(iters: Vec<Box<dyn InternalIterator>>, ucmp: Arc<dyn Comparator>) -> Self {
+        let mut it = Self {
             iters,
-            idx: 0,
+            ucmp,
             current: None,
+            direction: Direction::Forward,
+        };
+        it.find_smallest();
+        it
+    }
+
+    /// The index of the child the iterator is currently positioned on.
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    fn find_smallest(&mut self) {
+        let ucmp = self.ucmp.as_ref();
+        let mut smallest: Option<usize> = None;
+        for (i, it) in self.iters.iter().enumerate() {
+            if !it.valid() {
+                continue;
+            }
+            match smallest {
+                Some(s)
+                    if compare_internal_key(ucmp, it.key(), self.iters[s].key())
+                        != Ordering::Less => {}
+                _ => smallest = Some(i),
+            }
+        }
+        self.current = smallest;
+    }
+
+    fn find_largest(&mut self) {
+        let ucmp = self.ucmp.as_ref();
+        let mut largest: Option<usize> = None;
+        for (i, it) in self.iters.iter().enumerate().rev() {
+            if !it.valid() {
+                continue;
+            }
+            match largest {
+                Some(l)
+                    if compare_internal_key(ucmp, it.key(), self.iters[l].key())
+                        != Ordering::Greater => {}
+                _ => largest = Some(i),
+            }
         }
+        self.current = largest;
     }
 }
 
-impl Iterator for MergeIterator {
-    type Item = Entry;
+impl InternalIterator for MergeIterator {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut smallest = None;
-        let mut idx = self.idx;
+    fn seek_to_first(&mut self) {
+        self.iters.iter_mut().for_each(|it| it.seek_to_first());
+        self.find_smallest();
+        self.direction = Direction::Forward;
+    }
 
-        for (i, it) in self.iters.iter_mut().enumerate() {
-            if self.current.is_none() {
-                // first iter
-                it.next();
-            }
-            let internal_key = it.key();
-            if internal_key.is_none() {
-                continue;
+    fn seek_to_last(&mut self) {
+        self.iters.iter_mut().for_each(|it| it.seek_to_last());
+        self.find_largest();
+        self.direction = Direction::Reverse;
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.iters.iter_mut().for_each(|it| it.seek(target));
+        self.find_smallest();
+        self.direction = Direction::Forward;
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.iters
+            .iter_mut()
+            .for_each(|it| it.seek_for_prev(target));
+        self.find_largest();
+        self.direction = Direction::Reverse;
+    }
+
+    fn next(&mut self) {
+        let Some(current) = self.current else {
+            return;
+        };
+        if self.direction != Direction::Forward {
+            // make sure all children are positioned after key()
+            let key = self.key().to_vec();
+            for (i, it) in self.iters.iter_mut().enumerate() {
+                if i == current {
+                    continue;
+                }
+                it.seek(&key);
+                if it.valid() && it.key() == &key[..] {
+                    it.next();
+                }
             }
-            if smallest.is_none() || internal_key.clone()? < smallest.clone()? {
-                smallest = internal_key;
-                idx = i;
+            self.direction = Direction::Forward;
+        }
+        self.iters[current].next();
+        self.find_smallest();
+    }
+
+    fn prev(&mut self) {
+        let Some(current) = self.current else {
+            return;
+        };
+        if self.direction != Direction::Reverse {
+            // make sure all children are positioned before key()
+            let key = self.key().to_vec();
+            for (i, it) in self.iters.iter_mut().enumerate() {
+                if i == current {
+                    continue;
+                }
+                it.seek(&key);
+                if it.valid() {
+                    it.prev();
+                } else {
+                    it.seek_to_last();
+                }
             }
+            self.direction = Direction::Reverse;
+        }
+        self.iters[current].prev();
+        self.find_largest();
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iters[self.current.unwrap()].key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iters[self.current.unwrap()].value()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.iters.iter().try_for_each(|it| it.status())
+    }
+}
+
+impl Iterator for MergeIterator {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.valid() {
+            return None;
         }
-        self.current = self.iters[idx].item();
-        self.iters[idx].next();
-        self.idx = idx;
-        self.current.clone()
+        let entry = Entry::new(
+            Bytes::copy_from_slice(self.key()),
+            Bytes::copy_from_slice(self.value()),
+            0,
+        );
+        InternalIterator::next(self);
+        Some(entry)
     }
 }
 
@@ -54,6 +185,7 @@ mod merge_test {
 
     use crate::{
         file::{path_of_file, Ext, RandomAccessFileImpl},
+        iterator::InternalIterator,
         mem_table::{MemTable, MemTableIterator},
         sstable::{
             table::{Table, TableIterator},
@@ -61,7 +193,7 @@ mod merge_test {
         },
         utils::Entry,
         version::{FileMetaData, InternalKey},
-        Options,
+        BytewiseComparator, Options,
     };
 
     use super::MergeIterator;
@@ -77,7 +209,7 @@ mod merge_test {
         // create table
         for i in 0..3 {
             let path = path_of_file(&opt.clone().work_dir, i, Ext::SST);
-            let mem = MemTable::new();
+            let mem = MemTable::default();
             for j in 0..50 {
                 let e = Entry::new(
                     Bytes::from((j as u32).to_be_bytes().to_vec()),
@@ -90,20 +222,25 @@ mod merge_test {
             TableBuilder::build_table(
                 path.as_path(),
                 opt.clone(),
+                0,
                 MemTableIterator::new(&mem),
+                &[],
                 &mut file_meta,
             )
             .unwrap();
         }
 
         // merge
-        let mut merge_iter = vec![];
+        let mut merge_iter: Vec<Box<dyn InternalIterator>> = vec![];
         for i in 0..3 {
             let path = path_of_file(&opt.clone().work_dir, i, Ext::SST);
-            let t = Table::new(Box::new(RandomAccessFileImpl::open(path.as_path()))).unwrap();
-            merge_iter.push(TableIterator::new(Arc::new(t)).unwrap());
+            let t = Table::new(Box::new(
+                RandomAccessFileImpl::open(path.as_path()).unwrap(),
+            ))
+            .unwrap();
+            merge_iter.push(Box::new(TableIterator::new(Arc::new(t)).unwrap()));
         }
-        let iter = MergeIterator::new(merge_iter);
+        let iter = MergeIterator::new(merge_iter, Arc::new(BytewiseComparator));
         let (mut i, mut j) = (0, 0);
         for e in iter {
             let key = InternalKey::new(e.key);
@@ -3,21 +3,22 @@ mod block_builder;
 mod merge;
 mod table;
 mod table_builder;
+mod table_properties;
 
-pub use block::Block;
 pub use merge::*;
 pub use table::*;
 pub use table_builder::*;
+pub use table_properties::TableProperties;
 
-pub type Result<T> = anyhow::Result<T, TableError>;
-
-/// The error type of catalog operations.
+/// The error type of table decoding. It is reported as a
+/// [`Corruption`](crate::Error::Corruption) of the table file.
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 pub enum TableError {
     #[error("fail to decode block handler")]
     DecodeBlockHandlerError,
-    #[error("fail to decode block")]
-    DecodeBlockError,
-    #[error("fail to decode table")]
-    DecodeTableError,
+    #[error("fail to decode block: {0}")]
+    DecodeBlockError(String),
+    #[error("fail to decode table: {0}")]
+    DecodeTableError(String),
 }
@@ -1,37 +1,235 @@
 use std::sync::Arc;
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes};
 
 use crate::{
-    file::{path_of_file, RandomAccess, RandomAccessFileImpl, RandomReader},
+    cache::Cache,
+    file::RandomAccess,
+    iterator::InternalIterator,
+    range_del::RangeTombstone,
     utils::{bloom::BloomFilter, Entry, FilterPolicy},
     version::InternalKey,
-    Options,
+    vlog::VlogCache,
+    BytewiseComparator, Comparator, Error, Result,
 };
 
 use super::{
-    block::{Block, BlockHandler, BlockIterator, BLOCK_TRAILER_SIZE_},
-    Result,
+    block::{Block, BlockFormat, BlockHandler, BlockIterator, SIZEOF_U32, SIZEOF_U64},
+    table_properties::TableProperties,
+    TableError,
 };
 
-struct Footer {
-    filter_handler: BlockHandler,
-    index_handler: BlockHandler,
+/// Ends the footer of every table since format version 1.
+pub const TABLE_MAGIC_NUMBER: u64 = 0x636b_765f_7461_626c;
+/// Format version of the tables built: 1 adds the range tombstone block and
+/// compresses the shared prefix of keys in blocks, 2 adds the properties block
+/// and the checksum type, 3 encodes block handles as varints of 64 bits, 4
+/// records the compression of each block, 5 names the filter policy and its
+/// parameters in the filter block.
+/// Tables of version 0 have no version in the footer.
+pub const TABLE_FORMAT_VERSION: u32 = 5;
+// the filter and index handles, all the footer of a table of version 0 has
+const LEGACY_FOOTER_SIZE: u64 = 16;
+const FOOTER_V1_SIZE: u64 = 3 * 8 + 12;
+const FOOTER_V2_SIZE: u64 = FOOTER_V1_SIZE + 9;
+const FOOTER_SIZE: u64 = 4 * BlockHandler::MAX_ENCODED_LENGTH as u64 + 13;
+
+// +----------+-------------+------------+--------+--------+
+// | name len | policy name | params len | params | filter |
+// | u8       |             | u8         |        |        |
+// +----------+-------------+------------+--------+--------+
+//
+// The filter block since format version 5, tables before keep the filter of
+// a bloom filter alone.
+pub(super) fn encode_filter_block(policy: &BloomFilter, filter: &[u8]) -> Vec<u8> {
+    let (name, params) = (policy.name(), policy.params());
+    let mut buf = Vec::with_capacity(2 + name.len() + params.len() + filter.len());
+    buf.put_u8(name.len() as u8);
+    buf.put_slice(name.as_bytes());
+    buf.put_u8(params.len() as u8);
+    buf.put_slice(&params);
+    buf.put_slice(filter);
+    buf
+}
+
+// the policy name, parameters and filter of the filter block `data`
+fn decode_filter_block(mut data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    fn take<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = *data.first()? as usize;
+        let field = data.get(1..1 + len)?;
+        *data = &data[1 + len..];
+        Some(field)
+    }
+    let name = take(&mut data)?;
+    let params = take(&mut data)?;
+    Some((name, params, data))
+}
+
+// the filter policy of `name` built with `params`, none if the name is
+// unknown
+fn load_filter_policy(
+    name: &[u8],
+    params: &[u8],
+) -> std::result::Result<Option<Box<dyn FilterPolicy>>, TableError> {
+    let bad = || TableError::DecodeBlockError("bad filter parameters".to_string());
+    match name {
+        n if n == BloomFilter::NAME.as_bytes() => Ok(Some(Box::new(
+            BloomFilter::from_params(params).ok_or_else(bad)?,
+        ))),
+        _ => Ok(None),
+    }
+}
+
+/// Data blocks of all tables, keyed by file number and block offset and
+/// charged by their decoded size.
+pub type BlockCache = Cache<(u64, u64), Block>;
+
+/// Checksum of the blocks of a table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumType {
+    Crc32 = 1,
+}
+
+impl TryFrom<u8> for ChecksumType {
+    type Error = TableError;
+
+    fn try_from(v: u8) -> std::result::Result<Self, Self::Error> {
+        match v {
+            1 => Ok(ChecksumType::Crc32),
+            _ => Err(TableError::DecodeTableError(format!(
+                "unknown checksum type {}",
+                v
+            ))),
+        }
+    }
+}
+
+// +-----------+--------+-------+------------+---------------+----------------+-------+
+// | range del | filter | index | properties | checksum type | format version | magic |
+// | handle    | handle | handle| handle     | u8            | u32            | u64   |
+// +-----------+--------+-------+------------+---------------+----------------+-------+
+//
+// Each handle is padded to its largest encoded length. Tables of version 2
+// have handles of two u32, those of version 1 end with the format version and
+// magic right after the index handle. Tables of version 0 predate range
+// tombstones: their footer is the filter handle and the index handle alone.
+pub(super) struct Footer {
+    pub range_del_handler: Option<BlockHandler>,
+    pub filter_handler: BlockHandler,
+    pub index_handler: BlockHandler,
+    pub properties_handler: Option<BlockHandler>,
+    pub checksum_type: ChecksumType,
+    pub format_version: u32,
 }
 
 impl Footer {
-    fn decode(data: &[u8]) -> Self {
-        let mut filter_handler = BlockHandler::new();
-        filter_handler.set_offset((&data[0..4]).get_u32());
-        filter_handler.set_block_size((&data[4..8]).get_u32());
-
-        let mut index_handler = BlockHandler::new();
-        index_handler.set_offset((&data[8..12]).get_u32());
-        index_handler.set_block_size((&data[12..]).get_u32());
-        Self {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        let handlers = [
+            self.range_del_handler.as_ref(),
+            Some(&self.filter_handler),
+            Some(&self.index_handler),
+            self.properties_handler.as_ref(),
+        ];
+        for handler in handlers {
+            let mut handler = handler.map(BlockHandler::to_vec).unwrap_or_default();
+            handler.resize(BlockHandler::MAX_ENCODED_LENGTH, 0);
+            buf.put_slice(&handler);
+        }
+        buf.put_u8(self.checksum_type as u8);
+        buf.put_u32(self.format_version);
+        buf.put_u64(TABLE_MAGIC_NUMBER);
+        buf
+    }
+
+    // read the footer at the end of `file`, of a legacy table if it has no
+    // magic number
+    fn read(file: &dyn RandomAccess) -> Result<Self> {
+        let file_sz = file.size()?;
+        let corrupted = |err: TableError| Error::corruption(file.name(), file_sz, err);
+        let too_short = || corrupted(TableError::DecodeTableError("file too short".to_string()));
+        if file_sz < LEGACY_FOOTER_SIZE {
+            return Err(too_short());
+        }
+        let mut tail = [0_u8; SIZEOF_U32 + SIZEOF_U64];
+        file.read(&mut tail, file_sz - (SIZEOF_U32 + SIZEOF_U64) as u64)?;
+        let format_version = (&tail[..SIZEOF_U32]).get_u32();
+        let magic = (&tail[SIZEOF_U32..]).get_u64();
+        let footer_sz = match (magic, format_version) {
+            (TABLE_MAGIC_NUMBER, 1) => FOOTER_V1_SIZE,
+            (TABLE_MAGIC_NUMBER, 2) => FOOTER_V2_SIZE,
+            (TABLE_MAGIC_NUMBER, 3..=TABLE_FORMAT_VERSION) => FOOTER_SIZE,
+            (TABLE_MAGIC_NUMBER, v) => {
+                let reason = format!("unsupported format version {}", v);
+                return Err(corrupted(TableError::DecodeTableError(reason)));
+            }
+            _ => LEGACY_FOOTER_SIZE,
+        };
+        if file_sz < footer_sz {
+            return Err(too_short());
+        }
+
+        let mut data = vec![0_u8; footer_sz as usize];
+        file.read(&mut data, file_sz - footer_sz)?;
+        let format_version = match footer_sz {
+            LEGACY_FOOTER_SIZE => 0,
+            _ => format_version,
+        };
+        let format = BlockFormat::of_version(format_version);
+        let handler = |i: usize| {
+            let handler = match format_version {
+                0..=2 => BlockHandler::decode_fixed32(&data[i * 8..]),
+                _ => BlockHandler::decode(&data[i * BlockHandler::MAX_ENCODED_LENGTH..]),
+            };
+            handler.map_err(corrupted)
+        };
+        let (properties_handler, checksum_type) = match format_version {
+            0 | 1 => (None, ChecksumType::Crc32),
+            _ => {
+                let checksum_type = data[footer_sz as usize - 13];
+                let checksum_type = ChecksumType::try_from(checksum_type).map_err(corrupted)?;
+                (Some(handler(3)?), checksum_type)
+            }
+        };
+        let (range_del_handler, filter_handler, index_handler) = match format_version {
+            0 => (None, handler(0)?, handler(1)?),
+            _ => (Some(handler(0)?), handler(1)?, handler(2)?),
+        };
+        let footer = Self {
+            range_del_handler,
             filter_handler,
             index_handler,
+            properties_handler,
+            checksum_type,
+            format_version,
+        };
+
+        // a handle past the footer tells of a truncated or foreign file
+        let blocks = [
+            footer.range_del_handler.as_ref(),
+            Some(&footer.index_handler),
+            footer.properties_handler.as_ref(),
+        ];
+        let end = blocks
+            .iter()
+            .flatten()
+            .map(|h| {
+                h.offset()
+                    .saturating_add(h.block_size())
+                    .saturating_add(format.trailer_size() as u64)
+            })
+            .fold(
+                footer
+                    .filter_handler
+                    .offset()
+                    .saturating_add(footer.filter_handler.block_size()),
+                u64::max,
+            );
+        if end > file_sz - footer_sz {
+            let reason = "block handle past the footer".to_string();
+            return Err(corrupted(TableError::DecodeTableError(reason)));
         }
+        Ok(footer)
     }
 }
 
@@ -40,171 +238,430 @@ pub struct Table {
     // file_opt: Options,
     file: Box<dyn RandomAccess>,
     index_block: Block,
+    format_version: u32,
+    format: BlockFormat,
+    properties: Option<TableProperties>,
     #[allow(dead_code)]
     smallest: InternalKey,
     #[allow(dead_code)]
     largest: InternalKey,
     file_sz: u64,
-    bloom: BloomFilter,
+    // none if the table was built with a policy unknown here, letting every
+    // key through
+    filter_policy: Option<Box<dyn FilterPolicy>>,
     filter_data: Vec<u8>,
+    range_dels: Vec<RangeTombstone>,
+    fid: u64,
+    block_cache: Option<Arc<BlockCache>>,
+    ucmp: Arc<dyn Comparator>,
 }
 
 unsafe impl Send for Table {}
 unsafe impl Sync for Table {}
 
 impl Table {
-    pub fn new(file: Box<dyn RandomAccess>) -> anyhow::Result<Self, anyhow::Error> {
+    pub fn new(file: Box<dyn RandomAccess>) -> Result<Self> {
         // read footer
-        let mut footer = vec![0_u8; 16];
-        let sz = file.size().unwrap();
-        file.read(&mut footer, sz - 16).unwrap();
-        let footer = Footer::decode(&footer);
+        let file_sz = file.size()?;
+        let footer = Footer::read(file.as_ref())?;
+        let format = BlockFormat::of_version(footer.format_version);
 
         // read index
+        let index_offset = footer.index_handler.offset();
         let mut index_data =
-            vec![0_u8; footer.index_handler.block_size() as usize + BLOCK_TRAILER_SIZE_];
-        file.read(&mut index_data, footer.index_handler.offset() as u64)
-            .unwrap();
-        let index_block = Block::decode(&index_data);
-        let file_sz = file.size()?;
+            vec![0_u8; footer.index_handler.block_size() as usize + format.trailer_size()];
+        file.read(&mut index_data, index_offset)?;
+        let index_block = Block::decode(&index_data, format)
+            .map_err(|err| Error::corruption(file.name(), index_offset, err))?;
 
         // read filter
+        let filter_offset = footer.filter_handler.offset();
         let mut filter_data = vec![0_u8; footer.filter_handler.block_size() as usize];
-        file.read(&mut filter_data, footer.filter_handler.offset() as u64)
-            .unwrap();
+        file.read(&mut filter_data, filter_offset)?;
+        let filter_policy: Option<Box<dyn FilterPolicy>> = match footer.format_version {
+            // a bloom filter keeps its k, whatever it was built with
+            0..=4 => Some(Box::new(BloomFilter::new(BloomFilter::bits_per_key(
+                1999, 0.1,
+            )))),
+            _ => {
+                let corrupted = |err| Error::corruption(file.name(), filter_offset, err);
+                let (name, params, filter) =
+                    decode_filter_block(&filter_data).ok_or_else(|| {
+                        corrupted(TableError::DecodeBlockError("bad filter block".to_string()))
+                    })?;
+                let policy = load_filter_policy(name, params).map_err(corrupted)?;
+                filter_data = filter.to_vec();
+                policy
+            }
+        };
+
+        // read range tombstones, kept since format version 1
+        let range_dels = match footer.range_del_handler {
+            Some(handler) => {
+                let offset = handler.offset();
+                let mut data = vec![0_u8; handler.block_size() as usize + format.trailer_size()];
+                file.read(&mut data, offset)?;
+                Block::decode(&data, format)
+                    .map_err(|err| Error::corruption(file.name(), offset, err))?
+                    .into_iter()
+                    .map(|e| match e.key.len() {
+                        8.. => Ok(RangeTombstone::decode(&e.key, &e.value)),
+                        _ => Err(Error::corruption(
+                            file.name(),
+                            offset,
+                            "bad range tombstone",
+                        )),
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+            None => vec![],
+        };
+
+        // read properties, kept since format version 2
+        let properties = match footer.properties_handler {
+            Some(handler) => {
+                let offset = handler.offset();
+                let mut data = vec![0_u8; handler.block_size() as usize + format.trailer_size()];
+                file.read(&mut data, offset)?;
+                let props = TableProperties::decode(&data, format)
+                    .map_err(|err| Error::corruption(file.name(), offset, err))?;
+                Some(props)
+            }
+            None => None,
+        };
 
         Ok(Self {
             // file_opt,
             file,
             index_block,
+            format_version: footer.format_version,
+            format,
+            properties,
             smallest: InternalKey::new(Bytes::new()),
             largest: InternalKey::new(Bytes::new()),
             file_sz,
-            bloom: BloomFilter::new(BloomFilter::bits_per_key(1999, 0.1)),
+            filter_policy,
             filter_data,
+            range_dels,
+            fid: 0,
+            block_cache: None,
+            ucmp: Arc::new(BytewiseComparator),
         })
     }
 
+    /// Look data blocks up in `block_cache` before reading them from the file,
+    /// and keep the blocks read there. `fid` is the number of the table file.
+    pub fn with_block_cache(mut self, fid: u64, block_cache: Arc<BlockCache>) -> Self {
+        self.fid = fid;
+        self.block_cache = Some(block_cache);
+        self
+    }
+
+    /// Seek the keys of the table as ordered by `ucmp`, the comparator it
+    /// was built with.
+    pub fn with_comparator(mut self, ucmp: Arc<dyn Comparator>) -> Self {
+        self.ucmp = ucmp;
+        self
+    }
+
     pub fn size(&self) -> u64 {
         self.file_sz
     }
 
-    pub fn internal_get(&self, opt: &Options, internal_key: &[u8]) -> Option<Entry> {
-        let target = InternalKey::new(Bytes::from(internal_key.to_vec()));
-        if !self.bloom.may_contain(&self.filter_data, target.user_key()) {
-            return None;
-        }
-        // find data block first
-        let mut index_iter = BlockIterator::new(Arc::new(self.index_block.clone()));
-        let res = index_iter.seek(internal_key);
-        let e = res.as_ref()?;
-        let handler = BlockHandler::decode(e.value()).unwrap();
-
-        // find in data block
-        let mut data = vec![0_u8; handler.block_size() as usize + BLOCK_TRAILER_SIZE_];
-        self.file.read(&mut data, handler.offset() as u64).unwrap();
-        let data_block = Arc::new(Block::decode(&data));
-        let mut data_iter = BlockIterator::new(data_block);
-
-        if let Some(mut e) = data_iter.seek(internal_key) {
-            let found = InternalKey::new(e.clone().key);
-            let target = InternalKey::new(Bytes::from(internal_key.to_vec()));
-            if found.user_key() == target.user_key() {
-                let v = e.value.clone();
-                if !v.is_empty() && v[0] == 0 {
-                    e.value = Bytes::from(v[1..].to_vec());
-                } else if !v.is_empty() {
-                    let fid = (&e.value[1..9]).get_u64();
-                    let offset = (&e.value[9..17]).get_u64();
-                    let path = path_of_file(&opt.work_dir, fid, crate::file::Ext::VLOG);
-                    let mut vlog =
-                        RandomReader::new(Box::new(RandomAccessFileImpl::open(path.as_path())));
-                    e.value = Bytes::from(vlog.read_record(offset).unwrap());
+    /// The properties the table was built with, none for tables older than
+    /// format version 2.
+    pub fn properties(&self) -> Option<&TableProperties> {
+        self.properties.as_ref()
+    }
+
+    /// Whether the filter of the table lets `user_key` through.
+    pub fn may_contain(&self, user_key: &[u8]) -> bool {
+        self.filter_policy
+            .as_ref()
+            .is_none_or(|policy| policy.may_contain(&self.filter_data, user_key))
+    }
+
+    /// The range tombstones of the table, ordered by start.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_dels
+    }
+
+    pub fn internal_get(&self, vlog: &VlogCache, internal_key: &[u8]) -> Result<Option<Entry>> {
+        Ok(self
+            .multi_internal_get(vlog, &[internal_key])?
+            .pop()
+            .flatten())
+    }
+
+    /// [`internal_get`](Self::internal_get) for each of `internal_keys`, in
+    /// ascending order.
+    pub fn multi_internal_get(
+        &self,
+        vlog: &VlogCache,
+        internal_keys: &[&[u8]],
+    ) -> Result<Vec<Option<Entry>>> {
+        self.multi_raw_get(internal_keys)?
+            .into_iter()
+            .map(|found| match found {
+                Some((key, value)) => Ok(Some(Entry::new(key, resolve_value(vlog, &value)?, 0))),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Find the first entry at or past `internal_key` with the same user key,
+    /// returning its internal key and its value as stored in the table.
+    pub fn raw_get(&self, internal_key: &[u8]) -> Result<Option<(Bytes, Bytes)>> {
+        Ok(self.multi_raw_get(&[internal_key])?.pop().flatten())
+    }
+
+    /// [`raw_get`](Self::raw_get) for each of `internal_keys`, in ascending
+    /// order. Keys falling in the same data block read it once.
+    pub fn multi_raw_get(&self, internal_keys: &[&[u8]]) -> Result<Vec<Option<(Bytes, Bytes)>>> {
+        let mut index_iter =
+            BlockIterator::new(Arc::new(self.index_block.clone()), Arc::clone(&self.ucmp));
+        // the data block of the previous key, by its index entry
+        let mut block: Option<(Vec<u8>, Arc<Block>)> = None;
+        let mut found = Vec::with_capacity(internal_keys.len());
+        for internal_key in internal_keys {
+            let target = InternalKey::new(Bytes::copy_from_slice(internal_key));
+            if !self.may_contain(target.user_key()) {
+                found.push(None);
+                continue;
+            }
+            // find data block first
+            index_iter.seek(internal_key);
+            if !index_iter.valid() {
+                found.push(None);
+                continue;
+            }
+            let data = match &block {
+                Some((handle, data)) if handle[..] == *index_iter.value() => Arc::clone(data),
+                _ => {
+                    let data = self.block_at(index_iter.value())?;
+                    block = Some((index_iter.value().to_vec(), Arc::clone(&data)));
+                    data
                 }
+            };
 
-                Some(e)
+            // find in data block
+            let mut data_iter = BlockIterator::new(data, Arc::clone(&self.ucmp));
+            data_iter.seek(internal_key);
+            if !data_iter.valid() {
+                found.push(None);
+                continue;
+            }
+            let key = InternalKey::new(Bytes::copy_from_slice(data_iter.key()));
+            if key.user_key() == target.user_key() {
+                found.push(Some((key.key(), Bytes::copy_from_slice(data_iter.value()))));
             } else {
-                None
+                found.push(None);
             }
-        } else {
-            None
         }
+        Ok(found)
+    }
+
+    // read the data block an index entry points to
+    fn block_at(&self, index_value: &[u8]) -> Result<Arc<Block>> {
+        let handler = match self.format_version {
+            0..=2 => BlockHandler::decode_fixed32(index_value),
+            _ => BlockHandler::decode(index_value),
+        };
+        let handler = handler.map_err(|err| Error::corruption(self.file.name(), 0, err))?;
+        self.read_block(handler)
+    }
+
+    fn read_block(&self, handler: BlockHandler) -> Result<Arc<Block>> {
+        let Some(cache) = self.block_cache.as_ref() else {
+            return self.load_block(handler).map(Arc::new);
+        };
+        let key = (self.fid, handler.offset());
+        if let Some(block) = cache.get(&key) {
+            cache.unpin(&key)?;
+            return Ok(block);
+        }
+        let block = self.load_block(handler)?;
+        match cache.insert(key, block.clone(), block.size()) {
+            Ok(block) => {
+                cache.unpin(&key)?;
+                Ok(block)
+            }
+            // inserted by another reader, or no room in cache
+            Err(_) => Ok(Arc::new(block)),
+        }
+    }
+
+    fn load_block(&self, handler: BlockHandler) -> Result<Block> {
+        let offset = handler.offset();
+        let mut data = vec![0_u8; handler.block_size() as usize + self.format.trailer_size()];
+        self.file.read(&mut data, offset)?;
+        Block::decode(&data, self.format)
+            .map_err(|err| Error::corruption(self.file.name(), offset, err))
+    }
+}
+
+/// Decode a value stored in a table. A value is either inlined behind a `0`
+/// tag or separated into a vlog, in which case it is read back from there.
+///
+/// +-----------------+    +-------------------------+
+/// |  0 | value      |    |  1 | fid | vlog offset  |
+/// +-----------------+    +-------------------------+
+pub fn resolve_value(vlog: &VlogCache, value: &[u8]) -> Result<Bytes> {
+    if value.is_empty() || value[0] == 0 {
+        return Ok(Bytes::copy_from_slice(value.get(1..).unwrap_or_default()));
     }
+    let (fid, offset) = decode_value_pointer(value)?;
+    vlog.read_value(fid, offset)
+}
 
-    fn read_block(&self, handler: BlockHandler) -> Block {
-        let mut data = vec![0_u8; handler.block_size() as usize + BLOCK_TRAILER_SIZE_];
-        self.file.read(&mut data, handler.offset() as u64).unwrap();
-        Block::decode(&data)
+/// Decode the vlog file id and offset of a separated value.
+pub fn decode_value_pointer(value: &[u8]) -> Result<(u64, u64)> {
+    if value.len() < 17 {
+        let reason = format!("value pointer of {} bytes", value.len());
+        return Err(Error::corruption("value pointer", 0, reason));
     }
+    let fid = (&value[1..9]).get_u64();
+    let offset = (&value[9..17]).get_u64();
+    Ok((fid, offset))
 }
 
+/// Iterates a table through its index block, loading data blocks on demand.
+/// The iterator is positioned at the first entry when created. A block that
+/// fails to load ends the iteration and is reported by `status`.
 pub struct TableIterator {
     table: Arc<Table>,
     index_iter: BlockIterator,
-    block_iter: BlockIterator,
-    idx_block: usize,
-    curr: Option<Entry>,
+    block_iter: Option<BlockIterator>,
+    status: Option<Error>,
 }
 
 impl TableIterator {
     pub fn new(table: Arc<Table>) -> Result<Self> {
-        // table.index_block
-        let mut index_iter = BlockIterator::new(Arc::new(table.index_block.clone()));
-        let e = index_iter.next().unwrap();
-        let handler = e.value();
-        let offset = (&handler[..4]).get_u32();
-        let block_size = (&handler[4..]).get_u32();
-        // let block = Block::decode(data)
-        let mut data = vec![0_u8; block_size as usize + BLOCK_TRAILER_SIZE_];
-
-        if table.file.read(&mut data, offset as u64).is_err() {
-            return Err(super::TableError::DecodeTableError);
-        }
-
-        let data_block = Block::decode(&data);
-        let data_iter = data_block.into_iter();
-
-        let it = Self {
+        let index_iter =
+            BlockIterator::new(Arc::new(table.index_block.clone()), Arc::clone(&table.ucmp));
+        let mut it = Self {
             table,
             index_iter,
-            block_iter: data_iter,
-            idx_block: 0,
-            curr: None,
+            block_iter: None,
+            status: None,
         };
+        it.seek_to_first();
         Ok(it)
     }
 
-    pub fn key(&self) -> Option<InternalKey> {
-        let entry = self.curr.clone()?;
-        Some(InternalKey::new(entry.key))
+    // load the data block the index iterator points to
+    fn init_data_block(&mut self) {
+        self.block_iter = None;
+        if self.index_iter.valid() {
+            match self.table.block_at(self.index_iter.value()) {
+                Ok(block) => {
+                    let ucmp = Arc::clone(&self.table.ucmp);
+                    self.block_iter = Some(BlockIterator::new(block, ucmp));
+                }
+                Err(err) => {
+                    self.status.get_or_insert(err);
+                }
+            }
+        }
+    }
+
+    fn skip_empty_blocks_forward(&mut self) {
+        while self.block_iter.as_ref().is_some_and(|it| !it.valid()) {
+            InternalIterator::next(&mut self.index_iter);
+            self.init_data_block();
+            if let Some(it) = self.block_iter.as_mut() {
+                it.seek_to_first();
+            }
+        }
     }
 
-    pub fn item(&self) -> Option<Entry> {
-        self.curr.clone()
+    fn skip_empty_blocks_backward(&mut self) {
+        while self.block_iter.as_ref().is_some_and(|it| !it.valid()) {
+            self.index_iter.prev();
+            self.init_data_block();
+            if let Some(it) = self.block_iter.as_mut() {
+                it.seek_to_last();
+            }
+        }
     }
 }
-impl Iterator for TableIterator {
-    type Item = Entry;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // self.index_block
+impl InternalIterator for TableIterator {
+    fn valid(&self) -> bool {
+        self.block_iter.as_ref().is_some_and(|it| it.valid())
+    }
 
-        let mut res = self.block_iter.next();
-        if res.is_none() {
-            self.idx_block += 1;
-            if let Some(e) = self.index_iter.next() {
-                // let e = self.index_iter.next()?;
-                // let handler = BlockHandler::decode(&e.value).expect("Decode block fail!");
-                let handler = BlockHandler::decode(e.value).expect("Decode block fail!");
+    fn seek_to_first(&mut self) {
+        self.index_iter.seek_to_first();
+        self.init_data_block();
+        if let Some(it) = self.block_iter.as_mut() {
+            it.seek_to_first();
+        }
+        self.skip_empty_blocks_forward();
+    }
 
-                let data_block = self.table.read_block(handler);
-                self.block_iter = data_block.into_iter();
+    fn seek_to_last(&mut self) {
+        self.index_iter.seek_to_last();
+        self.init_data_block();
+        if let Some(it) = self.block_iter.as_mut() {
+            it.seek_to_last();
+        }
+        self.skip_empty_blocks_backward();
+    }
 
-                res = self.block_iter.next();
-            }
+    fn seek(&mut self, target: &[u8]) {
+        self.index_iter.seek(target);
+        self.init_data_block();
+        if let Some(it) = self.block_iter.as_mut() {
+            it.seek(target);
+        }
+        self.skip_empty_blocks_forward();
+    }
+
+    fn next(&mut self) {
+        if let Some(it) = self.block_iter.as_mut() {
+            InternalIterator::next(it);
+        }
+        self.skip_empty_blocks_forward();
+    }
+
+    fn prev(&mut self) {
+        if let Some(it) = self.block_iter.as_mut() {
+            it.prev();
+        }
+        self.skip_empty_blocks_backward();
+    }
+
+    fn key(&self) -> &[u8] {
+        self.block_iter.as_ref().unwrap().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.block_iter.as_ref().unwrap().value()
+    }
+
+    fn status(&self) -> Result<()> {
+        match &self.status {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
         }
-        self.curr = res.clone();
-        res
+    }
+}
+
+impl Iterator for TableIterator {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.valid() {
+            return None;
+        }
+        let entry = Entry::new(
+            Bytes::copy_from_slice(self.key()),
+            Bytes::copy_from_slice(self.value()),
+            0,
+        );
+        InternalIterator::next(self);
+        Some(entry)
     }
 }
 
@@ -213,22 +670,29 @@ mod table_test {
 
     use std::sync::Arc;
 
-    use bytes::Bytes;
+    use bytes::{BufMut, Bytes};
 
     use crate::{
-        file::{path_of_file, Ext, RandomAccessFileImpl},
+        file::{path_of_file, Ext, RandomAccessFileImpl, WritableFileImpl},
         mem_table::{MemTable, MemTableIterator},
+        range_del::RangeTombstone,
         sstable::{table::Table, table_builder::TableBuilder},
-        utils::Entry,
+        utils::{
+            bloom::BloomFilter,
+            build_internal_key,
+            codec::{calculate_checksum, encode_varintu32},
+            Entry, FilterPolicy, OP_TYPE_DELETE, OP_TYPE_PUT,
+        },
         version::FileMetaData,
-        Options,
+        vlog::VlogCache,
+        Error, Options,
     };
 
-    use super::TableIterator;
+    use super::{BlockCache, Footer, TableIterator, TABLE_FORMAT_VERSION, TABLE_MAGIC_NUMBER};
 
     #[test]
     fn table_seek_test() {
-        let mem = MemTable::new();
+        let mem = MemTable::default();
         for i in 0..1000 {
             let e = Entry::new(
                 Bytes::from((i as u32).to_be_bytes().to_vec()),
@@ -252,17 +716,22 @@ mod table_test {
         TableBuilder::build_table(
             path.as_path(),
             opt.clone(),
+            0,
             MemTableIterator::new(&mem),
+            &[],
             &mut file_meta,
         )
         .unwrap();
-        // let t = Table::new(opt, Box::new(RandomAccessFileImpl::open(path.as_path()))).unwrap();
-        let t = Table::new(Box::new(RandomAccessFileImpl::open(path.as_path()))).unwrap();
+        let vlog = VlogCache::new(&opt);
+        let t = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ))
+        .unwrap();
 
         for _ in 0..300 {
             let e = mem_iter.next().unwrap();
             let ikey = e.key;
-            let res = t.internal_get(&opt, &ikey);
+            let res = t.internal_get(&vlog, &ikey).unwrap();
             assert!(res.is_some());
             assert_eq!(res.clone().unwrap().key(), &ikey.to_vec());
             assert_eq!(res.unwrap().value(), &ikey[..4].to_vec());
@@ -273,4 +742,358 @@ mod table_test {
         iter.for_each(|_| count += 1);
         assert_eq!(count, 1000)
     }
+
+    #[test]
+    fn table_corruption_test() {
+        let mem = MemTable::default();
+        for i in 0..1000 {
+            let e = Entry::new(
+                Bytes::from((i as u32).to_be_bytes().to_vec()),
+                Bytes::from((i as u32).to_be_bytes().to_vec()),
+                i,
+            );
+            mem.put(e);
+        }
+
+        let opt = Options::default_opt().work_dir("work_dir/table_corruption");
+        let path = path_of_file(&opt.work_dir, 1, Ext::SST);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap();
+        };
+        std::fs::create_dir(&opt.work_dir).expect("create work direction fail!");
+
+        let mut file_meta = FileMetaData::new(1);
+        TableBuilder::build_table(
+            path.as_path(),
+            opt.clone(),
+            0,
+            MemTableIterator::new(&mem),
+            &[],
+            &mut file_meta,
+        )
+        .unwrap();
+
+        // flip a byte of the first data block
+        let mut data = std::fs::read(&path).unwrap();
+        data[10] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let t = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ))
+        .unwrap();
+        let vlog = VlogCache::new(&opt);
+        let ikey = MemTableIterator::new(&mem).next().unwrap().key;
+        let res = t.internal_get(&vlog, &ikey);
+        assert!(matches!(res, Err(Error::Corruption { .. })));
+
+        let mut iter = TableIterator::new(Arc::new(t)).unwrap();
+        assert!(iter.next().is_none());
+        let status = crate::iterator::InternalIterator::status(&iter);
+        assert!(matches!(status, Err(Error::Corruption { .. })));
+    }
+
+    #[test]
+    fn table_block_cache_test() {
+        let mem = MemTable::default();
+        for i in 0..1000 {
+            let e = Entry::new(
+                Bytes::from((i as u32).to_be_bytes().to_vec()),
+                Bytes::from((i as u32).to_be_bytes().to_vec()),
+                i,
+            );
+            mem.put(e);
+        }
+
+        let opt = Options::default_opt().work_dir("work_dir/table_block_cache");
+        let path = path_of_file(&opt.work_dir, 1, Ext::SST);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap();
+        };
+        std::fs::create_dir(&opt.work_dir).expect("create work direction fail!");
+
+        let mut file_meta = FileMetaData::new(1);
+        TableBuilder::build_table(
+            path.as_path(),
+            opt.clone(),
+            0,
+            MemTableIterator::new(&mem),
+            &[],
+            &mut file_meta,
+        )
+        .unwrap();
+
+        let cache = Arc::new(BlockCache::with_capacity(opt.cache_size));
+        let file = RandomAccessFileImpl::open(path.as_path()).unwrap();
+        let t = Table::new(Box::new(file))
+            .unwrap()
+            .with_block_cache(1, Arc::clone(&cache));
+
+        let vlog = VlogCache::new(&opt);
+        let ikey = MemTableIterator::new(&mem).next().unwrap().key;
+        for _ in 0..3 {
+            assert!(t.internal_get(&vlog, &ikey).unwrap().is_some());
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert!(stats.usage > 0);
+
+        // a second scan reads every block from the cache
+        let t = Arc::new(t);
+        assert_eq!(TableIterator::new(Arc::clone(&t)).unwrap().count(), 1000);
+        let first = cache.stats();
+        assert_eq!(TableIterator::new(t).unwrap().count(), 1000);
+        let second = cache.stats();
+        assert_eq!(second.misses, first.misses);
+        assert_eq!(second.hits - first.hits, first.misses);
+    }
+
+    // a block as the baseline builder wrote it, every key in full with the
+    // offset of each entry
+    fn legacy_block(entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![];
+        let mut offsets = vec![0_u32];
+        for (key, value) in entries {
+            encode_varintu32(&mut data, key.len() as u32);
+            data.put_slice(key);
+            encode_varintu32(&mut data, value.len() as u32);
+            data.put_slice(value);
+            offsets.push(data.len() as u32);
+        }
+        offsets.iter().for_each(|off| data.put_u32(*off));
+        data.put_u32(offsets.len() as u32);
+        let checksum = calculate_checksum(&data);
+        data.put_u64(checksum);
+        lz4_flex::compress_prepend_size(&data)
+    }
+
+    #[test]
+    fn table_legacy_format_test() {
+        let opt = Options::default_opt().work_dir("work_dir/table_legacy");
+        let path = path_of_file(&opt.work_dir, 1, Ext::SST);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap();
+        };
+        std::fs::create_dir(&opt.work_dir).expect("create work direction fail!");
+
+        let entries: Vec<_> = (0..100_u32)
+            .map(|i| {
+                let key = build_internal_key(&i.to_be_bytes(), i as u64, OP_TYPE_PUT);
+                let mut value = vec![0];
+                value.extend_from_slice(&i.to_le_bytes());
+                (key, value)
+            })
+            .collect();
+        let user_keys: Vec<_> = (0..100_u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let filter =
+            BloomFilter::new(BloomFilter::bits_per_key(100, 0.1)).create_filter(&user_keys);
+
+        // handles of two u32, of the offset and the size without the trailer
+        let handle = |offset: usize, size: usize| {
+            let mut handle = vec![];
+            handle.put_u32(offset as u32);
+            handle.put_u32(size as u32);
+            handle
+        };
+        // data blocks | filter | index | footer of the filter and index
+        // handles, with no range tombstone block
+        let mut file = vec![];
+        let mut index_entries = vec![];
+        for chunk in entries.chunks(30) {
+            let block = legacy_block(chunk);
+            let last_key = chunk.last().unwrap().0.clone();
+            index_entries.push((last_key, handle(file.len(), block.len() - 8)));
+            file.extend_from_slice(&block);
+        }
+        let filter_handle = handle(file.len(), filter.len());
+        file.extend_from_slice(&filter);
+        let index = legacy_block(&index_entries);
+        let index_handle = handle(file.len(), index.len() - 8);
+        file.extend_from_slice(&index);
+        file.extend_from_slice(&filter_handle);
+        file.extend_from_slice(&index_handle);
+        std::fs::write(&path, file).unwrap();
+
+        let t = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ))
+        .unwrap();
+        assert!(t.properties().is_none());
+        assert!(t.range_tombstones().is_empty());
+        let vlog = VlogCache::new(&opt);
+        for (i, (key, _)) in entries.iter().enumerate() {
+            let e = t.internal_get(&vlog, key).unwrap().unwrap();
+            assert_eq!(e.value(), &(i as u32).to_le_bytes()[..]);
+        }
+        let missing = build_internal_key(&100_u32.to_be_bytes(), 100, OP_TYPE_PUT);
+        assert!(t.internal_get(&vlog, &missing).unwrap().is_none());
+        let iter = TableIterator::new(Arc::new(t)).unwrap();
+        assert!(iter
+            .map(|e| e.key)
+            .eq(entries.iter().map(|(k, _)| Bytes::from(k.clone()))));
+
+        // a table of a newer format is not read
+        let mut data = std::fs::read(&path).unwrap();
+        let trailer = [
+            &(TABLE_FORMAT_VERSION + 1).to_be_bytes()[..],
+            &TABLE_MAGIC_NUMBER.to_be_bytes()[..],
+        ];
+        data.extend_from_slice(&trailer.concat());
+        std::fs::write(&path, data).unwrap();
+        let res = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ));
+        assert!(matches!(res, Err(Error::Corruption { .. })));
+    }
+
+    #[test]
+    fn table_filter_params_test() {
+        let opt = Options::default_opt().work_dir("work_dir/table_filter_params");
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap();
+        };
+        std::fs::create_dir(&opt.work_dir).expect("create work direction fail!");
+
+        // the same keys under filters of 2 and 20 bits per key
+        let build = |fid: u64, bits_per_key: usize| {
+            let path = path_of_file(&opt.work_dir, fid, Ext::SST);
+            let mut tb = TableBuilder::new(
+                opt.clone().bloom_bits_per_key(bits_per_key),
+                Box::new(WritableFileImpl::new(&path).unwrap()),
+                fid,
+            );
+            for i in 0..1000_u32 {
+                let key = build_internal_key(&(i * 2).to_be_bytes(), 1, OP_TYPE_PUT);
+                tb.add(&key, b"value").unwrap();
+            }
+            tb.finish_builder(&mut FileMetaData::new(fid)).unwrap();
+            path
+        };
+        let open = |path: &std::path::Path| {
+            Table::new(Box::new(RandomAccessFileImpl::open(path).unwrap())).unwrap()
+        };
+        let false_positives = |t: &Table| {
+            (0..1000_u32)
+                .filter(|i| t.may_contain(&(i * 2 + 1).to_be_bytes()))
+                .count()
+        };
+        let (coarse, fine) = (open(&build(1, 2)), open(&build(2, 20)));
+        for t in [&coarse, &fine] {
+            assert!((0..1000_u32).all(|i| t.may_contain(&(i * 2).to_be_bytes())));
+        }
+        assert!(fine.filter_data.len() > 2500);
+        assert!(coarse.filter_data.len() < 300);
+        assert!(false_positives(&fine) < false_positives(&coarse));
+
+        // the filter block names the policy and its parameters
+        let path = build(3, 20);
+        let file = RandomAccessFileImpl::open(path.as_path()).unwrap();
+        let handler = Footer::read(&file).unwrap().filter_handler;
+        let mut data = std::fs::read(&path).unwrap();
+        let block = &data[handler.offset() as usize..][..handler.block_size() as usize];
+        let name = BloomFilter::NAME.as_bytes();
+        assert_eq!(block[0] as usize, name.len());
+        assert_eq!(&block[1..1 + name.len()], name);
+        assert_eq!(
+            &block[1 + name.len()..][..5],
+            &[4, 0, 0, 0, 20],
+            "bits per key"
+        );
+
+        // a table of an unknown policy lets every key through
+        data[handler.offset() as usize + 1] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        let t = open(&path);
+        assert!(t.filter_policy.is_none());
+        assert_eq!(false_positives(&t), 1000);
+        assert_eq!(
+            t.raw_get(&build_internal_key(&2_u32.to_be_bytes(), 1, OP_TYPE_PUT))
+                .unwrap()
+                .unwrap()
+                .1,
+            Bytes::from_static(b"value")
+        );
+
+        // parameters too long for a bloom filter
+        data[handler.offset() as usize + 1] ^= 0xff;
+        data[handler.offset() as usize + 1 + name.len()] = 0xff;
+        std::fs::write(&path, &data).unwrap();
+        let res = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ));
+        assert!(matches!(res, Err(Error::Corruption { .. })));
+    }
+
+    #[test]
+    fn table_properties_test() {
+        let opt = Options::default_opt().work_dir("work_dir/table_properties");
+        let path = path_of_file(&opt.work_dir, 1, Ext::SST);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap();
+        };
+        std::fs::create_dir(&opt.work_dir).expect("create work direction fail!");
+
+        // every third key deleted, seqs 10 to 109
+        let mut tb = TableBuilder::new(
+            opt.clone(),
+            Box::new(WritableFileImpl::new(&path).unwrap()),
+            1,
+        );
+        tb.add_range_tombstone(RangeTombstone::new(
+            Bytes::from_static(b"a"),
+            Bytes::from_static(b"b"),
+            5,
+        ));
+        let (mut raw_key_size, mut raw_value_size) = (0, 0);
+        for i in 0..100_u32 {
+            let typ = if i % 3 == 0 {
+                OP_TYPE_DELETE
+            } else {
+                OP_TYPE_PUT
+            };
+            let key = build_internal_key(&i.to_be_bytes(), 10 + i as u64, typ);
+            let value = if typ == OP_TYPE_PUT {
+                vec![0, 1, 2]
+            } else {
+                vec![]
+            };
+            tb.add(&key, &value).unwrap();
+            raw_key_size += key.len() as u64;
+            raw_value_size += value.len() as u64;
+        }
+        let mut meta = FileMetaData::new(1);
+        tb.finish_builder(&mut meta).unwrap();
+
+        let t = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ))
+        .unwrap();
+        let props = t.properties().unwrap();
+        assert_eq!(props.num_entries, 100);
+        assert_eq!(props.num_deletions, 34);
+        assert_eq!(props.num_range_deletions, 1);
+        assert_eq!(props.raw_key_size, raw_key_size);
+        assert_eq!(props.raw_value_size, raw_value_size);
+        assert_eq!((props.smallest_seq, props.largest_seq), (5, 109));
+        assert_eq!(props.compression, "lz4");
+        assert_eq!(props.filter_policy, BloomFilter::NAME);
+
+        // a truncated table
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 10]).unwrap();
+        let res = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ));
+        assert!(matches!(res, Err(Error::Corruption { .. })));
+
+        // an unknown checksum type
+        let mut corrupted = data.clone();
+        let len = corrupted.len();
+        corrupted[len - 13] = 0xff;
+        std::fs::write(&path, corrupted).unwrap();
+        let res = Table::new(Box::new(
+            RandomAccessFileImpl::open(path.as_path()).unwrap(),
+        ));
+        assert!(matches!(res, Err(Error::Corruption { .. })));
+    }
 }
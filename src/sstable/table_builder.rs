@@ -1,20 +1,32 @@
-use std::{io::Error, path::Path};
+use std::{cmp::Ordering, path::Path};
 
 use bytes::{BufMut, Bytes};
 
 use crate::{
-    file::{path_of_file, Ext, Writable, WritableFileImpl, Writer},
-    utils::{bloom::BloomFilter, Entry, FilterPolicy},
+    file::{Writable, WritableFileImpl},
+    range_del::RangeTombstone,
+    utils::{
+        bloom::BloomFilter, compare_internal_key, parse_internal_key, Entry, FilterPolicy,
+        OP_TYPE_DELETE,
+    },
     version::{FileMetaData, InternalKey},
-    Options,
+    vlog::{VlogEntry, VlogWriter},
+    CompressionType, Options, Result,
 };
 
-use super::{block::BlockHandler, block_builder::BlockBuilder};
+use super::{
+    block::{BlockHandler, BLOCK_TRAILER_SIZE},
+    block_builder::BlockBuilder,
+    table::{encode_filter_block, Footer},
+    ChecksumType, TableProperties, TABLE_FORMAT_VERSION,
+};
 
 enum BlockType {
     Data,
     Index,
     Filter,
+    RangeDel,
+    Properties,
 }
 /// A block builder
 pub struct TableBuilder {
@@ -22,10 +34,10 @@ pub struct TableBuilder {
     #[allow(unused)]
     fid: u64,
     file: Box<dyn Writable>,
-    vlog: Option<Writer>,
+    vlog: Option<VlogWriter>,
     data_block: BlockBuilder,
     index_block: BlockBuilder,
-    offset: u32,
+    offset: u64,
     pending_handler: BlockHandler,
     last_key: Vec<u8>,
     pending_index_entry: bool,
@@ -33,14 +45,19 @@ pub struct TableBuilder {
     smallest: InternalKey,
     filters_keys: Vec<Vec<u8>>,
     filters: Vec<u8>,
+    range_dels: Vec<RangeTombstone>,
+    range_del_block: BlockBuilder,
+    props: TableProperties,
+    compression: CompressionType,
 }
 
 impl TableBuilder {
     pub fn new(file_opt: Options, file: Box<dyn Writable>, fid: u64) -> Self {
         TableBuilder {
             pending_handler: BlockHandler::new(),
-            data_block: BlockBuilder::new(),
-            index_block: BlockBuilder::new(),
+            data_block: BlockBuilder::new(file_opt.block_restart_interval),
+            // the index is searched entry by entry
+            index_block: BlockBuilder::new(1),
             offset: 0,
             file,
             vlog: None,
@@ -51,40 +68,56 @@ impl TableBuilder {
             smallest: InternalKey::from(vec![]),
             filters_keys: Vec::new(),
             filters: Vec::new(),
+            range_dels: Vec::new(),
+            range_del_block: BlockBuilder::new(file_opt.block_restart_interval),
+            props: TableProperties::default(),
+            // of level 0 unless given another with `with_level`
+            compression: file_opt.compression_for_level(0),
             file_opt,
         }
     }
 
+    /// Compress the blocks as the tables of `level` are.
+    pub fn with_level(mut self, level: usize) -> Self {
+        self.compression = self.file_opt.compression_for_level(level);
+        self
+    }
+
+    /// Build a table of the entries of `iter` and the tombstones `range_dels`,
+    /// compressed as the tables of `level` are.
     pub fn build_table<T>(
         path: &Path,
         opt: Options,
+        level: usize,
         iter: T,
+        range_dels: &[RangeTombstone],
         meta: &mut FileMetaData,
-    ) -> Result<(), anyhow::Error>
+    ) -> Result<()>
     where
         T: Iterator<Item = Entry>,
     {
         // let (mut largest, mut smallest) = (InternalKey::new(vec![]), InternalKey::new(vec![]));
         let fid = meta.number;
-        let mut tb = TableBuilder::new(opt, Box::new(WritableFileImpl::new(path)), fid);
+        let mut tb =
+            TableBuilder::new(opt, Box::new(WritableFileImpl::new(path)?), fid).with_level(level);
+        range_dels
+            .iter()
+            .for_each(|t| tb.add_range_tombstone(t.clone()));
 
-        iter.for_each(|e| {
+        for e in iter {
             let mut value_wrapper = vec![];
             if !e.value.is_empty() && e.value.len() >= tb.file_opt.kv_separate_threshold {
-                if tb.vlog.is_none() {
-                    tb.vlog = Some(Writer::new(WritableFileImpl::new(&path_of_file(
-                        &tb.file_opt.work_dir.clone(),
-                        fid,
-                        Ext::VLOG,
-                    ))));
-                    meta.vlogs.push(fid);
-                }
-                let off = tb.vlog.as_ref().unwrap().offset();
-                tb.vlog
-                    .as_ref()
-                    .unwrap()
-                    .add_recore(&e.value)
-                    .expect("write vlog failed!");
+                let vlog = match tb.vlog.as_ref() {
+                    Some(vlog) => vlog,
+                    None => {
+                        meta.vlogs.push(fid);
+                        tb.vlog
+                            .insert(VlogWriter::create(&tb.file_opt.work_dir, fid)?)
+                    }
+                };
+                let (user_key, seq, _) = parse_internal_key(&e.key);
+                let entry = VlogEntry::new(Bytes::copy_from_slice(user_key), seq, e.value.clone());
+                let off = vlog.add(&entry)?;
                 value_wrapper.put_u8(1);
                 value_wrapper.put_u64(fid);
                 value_wrapper.put_u64(off);
@@ -92,20 +125,23 @@ impl TableBuilder {
                 value_wrapper.put_u8(0);
                 value_wrapper.put_slice(&e.value);
             }
-            tb.add(&e.key, &value_wrapper);
-        });
+            tb.add(&e.key, &value_wrapper)?;
+        }
 
-        tb.finish();
+        tb.finish()?;
         tb.file.sync()?;
+        if let Some(vlog) = tb.vlog.as_ref() {
+            vlog.sync()?;
+        }
 
         meta.set_file_size(tb.file.size()?);
         meta.set_smallest(tb.smallest.clone());
         meta.set_largest(tb.largest.clone());
+        meta.set_largest_seq(tb.props.largest_seq);
         Ok(())
     }
 
-    /// TODO: prefix compaction
-    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
         if self.smallest.is_empty() {
             self.smallest = InternalKey::new(Bytes::from(key.to_vec()));
         }
@@ -119,35 +155,74 @@ impl TableBuilder {
 
         let internal_key = InternalKey::new(Bytes::from(key.to_vec()));
         self.filters_keys.push(internal_key.user_key().to_vec());
+        self.add_seq(internal_key.seq());
+        self.props.num_entries += 1;
+        if parse_internal_key(key).2 == OP_TYPE_DELETE {
+            self.props.num_deletions += 1;
+        }
+        self.props.raw_key_size += key.len() as u64;
+        self.props.raw_value_size += value.len() as u64;
 
         self.last_key = key.to_vec();
         self.data_block.add(key, value);
 
         let estimated_size = self.data_block.estimated_size();
         if estimated_size >= self.file_opt.block_size {
-            self.flush();
+            self.flush()?;
         }
+        Ok(())
     }
 
-    fn flush(&mut self) {
-        self.write_block(BlockType::Data);
+    /// Keep `tombstone` in the range deletion block of the table. Unlike
+    /// entries, tombstones may be added in any order.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.add_seq(tombstone.seq);
+        self.props.num_range_deletions += 1;
+        self.range_dels.push(tombstone);
+    }
+
+    // widen the sequence range of the table to `seq`
+    fn add_seq(&mut self, seq: u64) {
+        let props = &mut self.props;
+        if props.num_entries + props.num_range_deletions == 0 {
+            props.smallest_seq = seq;
+        }
+        props.smallest_seq = props.smallest_seq.min(seq);
+        props.largest_seq = props.largest_seq.max(seq);
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_block(BlockType::Data)?;
         self.pending_index_entry = true;
-        self.file.flush().unwrap();
+        self.file.flush()?;
+        Ok(())
     }
 
-    fn write_block(&mut self, block_type: BlockType) {
+    fn write_block(&mut self, block_type: BlockType) -> Result<()> {
+        let properties;
+        let compression = self.compression;
         let content = match block_type {
-            BlockType::Data => self.data_block.finish(),
-            BlockType::Index => self.index_block.finish(),
+            BlockType::Data => self.data_block.finish(compression),
+            BlockType::Index => self.index_block.finish(compression),
             BlockType::Filter => &self.filters,
+            BlockType::RangeDel => self.range_del_block.finish(compression),
+            BlockType::Properties => {
+                properties = self.props.encode();
+                &properties
+            }
         };
 
+        // the handle of a filter covers all of it
+        let trailer_size = match block_type {
+            BlockType::Filter => 0,
+            _ => BLOCK_TRAILER_SIZE,
+        };
         self.pending_handler.set_offset(self.offset);
         self.pending_handler
-            .set_block_size(content.len() as u32 - 8);
+            .set_block_size((content.len() - trailer_size) as u64);
 
-        self.offset += content.len() as u32;
-        self.file.append(content).unwrap();
+        self.offset += content.len() as u64;
+        self.file.append(content)?;
         match block_type {
             BlockType::Data => self.data_block.reset(),
             BlockType::Index => self.index_block.reset(),
@@ -155,21 +230,46 @@ impl TableBuilder {
                 self.filters_keys = vec![];
                 self.filters = vec![];
             }
+            BlockType::RangeDel => self.range_del_block.reset(),
+            BlockType::Properties => {}
         };
+        Ok(())
     }
 
-    pub fn finish_builder(&mut self, meta: &mut FileMetaData) -> Result<(), Error> {
-        self.finish();
+    pub fn finish_builder(&mut self, meta: &mut FileMetaData) -> Result<()> {
+        self.finish()?;
         self.file.sync()?;
         meta.set_file_size(self.file.size()?);
         meta.set_smallest(self.smallest.clone());
         meta.set_largest(self.largest.clone());
+        meta.set_largest_seq(self.props.largest_seq);
         Ok(())
     }
 
-    fn finish(&mut self) {
-        // write last data block
-        self.flush();
+    // The range of the table covers its tombstones as well as its entries.
+    fn add_range_del_bounds(&mut self) {
+        let ucmp = self.file_opt.comparator.as_ref();
+        for t in self.range_dels.iter() {
+            let start = t.internal_key();
+            if self.smallest.is_empty()
+                || compare_internal_key(ucmp, &start, &self.smallest.key()) == Ordering::Less
+            {
+                self.smallest = InternalKey::from(start);
+            }
+            let end = t.largest_key();
+            if self.largest.is_empty()
+                || compare_internal_key(ucmp, &end, &self.largest.key()) == Ordering::Greater
+            {
+                self.largest = InternalKey::from(end);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // write last data block, if any entry is left for it
+        if !self.data_block.is_empty() {
+            self.flush()?;
+        }
 
         // write index block
         if self.pending_index_entry {
@@ -178,40 +278,71 @@ impl TableBuilder {
             self.pending_index_entry = false;
         }
 
-        let bloom = BloomFilter::new(BloomFilter::bits_per_key(
-            self.filters_keys.len() as u32,
-            0.1,
-        ));
+        let bits_per_key = match self.file_opt.bloom_bits_per_key {
+            0 => BloomFilter::bits_per_key(
+                self.filters_keys.len() as u32,
+                self.file_opt.bloom_false_positive,
+            ),
+            bits_per_key => bits_per_key,
+        };
+        let bloom = BloomFilter::new(bits_per_key);
 
         // write filter block
-        self.filters = bloom.create_filter(&self.filters_keys);
-        let mut filter_handler = BlockHandler::new();
-        filter_handler.set_offset(self.offset);
-        filter_handler.set_block_size(self.filters.len() as u32);
-
-        self.write_block(BlockType::Filter);
+        self.filters = encode_filter_block(&bloom, &bloom.create_filter(&self.filters_keys));
+        self.write_block(BlockType::Filter)?;
+        let filter_handler = self.pending_handler.clone();
+
+        // write range deletion block
+        self.add_range_del_bounds();
+        let mut range_dels = std::mem::take(&mut self.range_dels);
+        let ucmp = self.file_opt.comparator.as_ref();
+        range_dels
+            .sort_by(|t1, t2| compare_internal_key(ucmp, &t1.internal_key(), &t2.internal_key()));
+        range_dels
+            .iter()
+            .for_each(|t| self.range_del_block.add(&t.internal_key(), &t.end));
+        self.write_block(BlockType::RangeDel)?;
+        let range_del_handler = self.pending_handler.clone();
+
+        // write properties block
+        self.props.compression = self.compression.name().to_string();
+        self.props.filter_policy = bloom.name().to_string();
+        self.write_block(BlockType::Properties)?;
+        let properties_handler = self.pending_handler.clone();
 
         // write index block
-        self.write_block(BlockType::Index);
+        self.write_block(BlockType::Index)?;
 
         // write footer
-        self.file.append(&filter_handler.to_vec()).unwrap();
-        self.file.append(&self.pending_handler.to_vec()).unwrap();
+        let footer = Footer {
+            range_del_handler: Some(range_del_handler),
+            filter_handler,
+            index_handler: self.pending_handler.clone(),
+            properties_handler: Some(properties_handler),
+            checksum_type: ChecksumType::Crc32,
+            format_version: TABLE_FORMAT_VERSION,
+        };
+        self.file.append(&footer.encode())?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod builder_test {
-    use std::io::Read;
+    use std::{io::Read, sync::Arc};
 
-    use bytes::{Buf, Bytes};
+    use bytes::Bytes;
 
     use crate::{
-        file::{path_of_file, Ext},
+        file::{path_of_file, Ext, RandomAccessFileImpl, WritableFileImpl},
         mem_table::{MemTable, MemTableIterator},
-        sstable::block::{Block, BLOCK_TRAILER_SIZE_},
-        utils::Entry,
+        sstable::{
+            block::{Block, BlockFormat, BlockHandler, BLOCK_TRAILER_SIZE},
+            Table, TableIterator,
+        },
+        utils::{build_internal_key, Entry, OP_TYPE_PUT},
         version::FileMetaData,
+        vlog::VlogCache,
         Options,
     };
 
@@ -219,7 +350,7 @@ mod builder_test {
 
     #[test]
     fn builder_test() {
-        let mem = MemTable::new();
+        let mem = MemTable::default();
         for i in 0..1000 {
             let e = Entry::new(
                 Bytes::from((i as u32).to_be_bytes().to_vec()),
@@ -240,7 +371,9 @@ mod builder_test {
         TableBuilder::build_table(
             path.as_path(),
             opt,
+            0,
             MemTableIterator::new(&mem),
+            &[],
             &mut file_meta,
         )
         .unwrap();
@@ -250,24 +383,28 @@ mod builder_test {
         let mut buf = Vec::new();
         file.read_to_end(&mut buf).unwrap();
 
-        let len = buf.len();
-        let index_sz = (&buf[len - 4..]).get_u32() as usize;
-        let index_offset = (&buf[len - 8..]).get_u32() as usize;
-        let index_end = index_sz + index_offset + BLOCK_TRAILER_SIZE_;
+        // the handles of the footer are padded to 20 bytes, the index handle
+        // third
+        let footer = &buf[buf.len() - 93..];
+        let index_handler = BlockHandler::decode(&footer[40..]).unwrap();
+        let index_sz = index_handler.block_size() as usize;
+        let index_offset = index_handler.offset() as usize;
+        let index_end = index_sz + index_offset + BLOCK_TRAILER_SIZE;
 
         let index = &buf[index_offset..index_end];
-        let index_block = Block::decode(index);
+        let index_block = Block::decode(index, BlockFormat::Trailer).unwrap();
 
         let index_iter = index_block.into_iter();
         let mut i: u32 = 0;
         index_iter.for_each(|e| {
             let last_key = e.key;
             let handler = e.value;
-            let offset = (&handler[..4]).get_u32() as usize;
-            let block_sz = (&handler[4..]).get_u32() as usize;
+            let handler = BlockHandler::decode(&handler).unwrap();
+            let offset = handler.offset() as usize;
+            let block_sz = handler.block_size() as usize;
 
-            let data = &buf[offset..offset + block_sz + BLOCK_TRAILER_SIZE_];
-            let data_block = Block::decode(data);
+            let data = &buf[offset..offset + block_sz + BLOCK_TRAILER_SIZE];
+            let data_block = Block::decode(data, BlockFormat::Trailer).unwrap();
             // Block::decode(data);
             let mut lkey: Vec<u8> = Vec::new();
             let iter = data_block.into_iter();
@@ -283,4 +420,46 @@ mod builder_test {
             assert_eq!(lkey, last_key);
         })
     }
+
+    #[test]
+    fn builder_large_offset_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/table_large_offset")
+            .block_size(256);
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap();
+        };
+        std::fs::create_dir(&opt.work_dir).expect("create work direction fail!");
+
+        // blocks from just before 4 GiB on, and all past it, of sparse files
+        for start in [(1_u64 << 32) - 1000, 5 << 30] {
+            let path = path_of_file(&opt.work_dir, 1, Ext::SST);
+            let file = std::fs::File::create(&path).unwrap();
+            file.set_len(start).unwrap();
+            let mut tb = TableBuilder::new(
+                opt.clone(),
+                Box::new(WritableFileImpl::new(&path).unwrap()),
+                1,
+            );
+            tb.offset = start;
+            let key = |i: u32| build_internal_key(&i.to_be_bytes(), i as u64, OP_TYPE_PUT);
+            for i in 0..1000_u32 {
+                let mut value = vec![0];
+                value.extend_from_slice(&i.to_le_bytes());
+                tb.add(&key(i), &value).unwrap();
+            }
+            let mut meta = FileMetaData::new(1);
+            tb.finish_builder(&mut meta).unwrap();
+            assert!(meta.file_size > 1 << 32);
+
+            let t = Table::new(Box::new(RandomAccessFileImpl::open(&path).unwrap())).unwrap();
+            let vlog = VlogCache::new(&opt);
+            for i in 0..1000_u32 {
+                let e = t.internal_get(&vlog, &key(i)).unwrap().unwrap();
+                assert_eq!(e.value(), &i.to_le_bytes()[..]);
+            }
+            assert_eq!(TableIterator::new(Arc::new(t)).unwrap().count(), 1000);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
 }
@@ -0,0 +1,99 @@
+use bytes::Buf;
+
+use crate::CompressionType;
+
+use super::{
+    block::{Block, BlockFormat},
+    block_builder::BlockBuilder,
+    TableError,
+};
+
+const NUM_ENTRIES: &str = "ckv.num.entries";
+const NUM_DELETIONS: &str = "ckv.num.deletions";
+const NUM_RANGE_DELETIONS: &str = "ckv.num.range-deletions";
+const RAW_KEY_SIZE: &str = "ckv.raw.key.size";
+const RAW_VALUE_SIZE: &str = "ckv.raw.value.size";
+const SMALLEST_SEQ: &str = "ckv.seq.smallest";
+const LARGEST_SEQ: &str = "ckv.seq.largest";
+const COMPRESSION: &str = "ckv.compression";
+const FILTER_POLICY: &str = "ckv.filter.policy";
+
+/// What a table holds, written to its properties block when it is built.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Entries of the data blocks, deletions included.
+    pub num_entries: u64,
+    pub num_deletions: u64,
+    pub num_range_deletions: u64,
+    /// Bytes of the internal keys of the entries.
+    pub raw_key_size: u64,
+    /// Bytes of the values as kept in the table, a separated value counting
+    /// as its pointer into the vlog.
+    pub raw_value_size: u64,
+    /// Sequence range of the entries and range tombstones, both 0 if there
+    /// are none.
+    pub smallest_seq: u64,
+    pub largest_seq: u64,
+    /// Compression of the blocks, except those it did not shrink enough,
+    /// which are kept raw.
+    pub compression: String,
+    /// Name of the filter policy of the filter block.
+    pub filter_policy: String,
+}
+
+impl TableProperties {
+    /// Encode the properties as a block of name and value pairs, ordered by
+    /// name.
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut props: Vec<(&str, Vec<u8>)> = vec![
+            (NUM_ENTRIES, self.num_entries.to_be_bytes().to_vec()),
+            (NUM_DELETIONS, self.num_deletions.to_be_bytes().to_vec()),
+            (
+                NUM_RANGE_DELETIONS,
+                self.num_range_deletions.to_be_bytes().to_vec(),
+            ),
+            (RAW_KEY_SIZE, self.raw_key_size.to_be_bytes().to_vec()),
+            (RAW_VALUE_SIZE, self.raw_value_size.to_be_bytes().to_vec()),
+            (SMALLEST_SEQ, self.smallest_seq.to_be_bytes().to_vec()),
+            (LARGEST_SEQ, self.largest_seq.to_be_bytes().to_vec()),
+            (COMPRESSION, self.compression.as_bytes().to_vec()),
+            (FILTER_POLICY, self.filter_policy.as_bytes().to_vec()),
+        ];
+        props.sort();
+        let mut builder = BlockBuilder::new(1);
+        props
+            .iter()
+            .for_each(|(name, value)| builder.add(name.as_bytes(), value));
+        builder.finish(CompressionType::None).to_vec()
+    }
+
+    /// Decode the properties block. Properties it does not know of are
+    /// skipped, so newer tables still read.
+    pub(super) fn decode(data: &[u8], format: BlockFormat) -> Result<Self, TableError> {
+        let corrupted = |name: &[u8]| {
+            let name = String::from_utf8_lossy(name);
+            TableError::DecodeBlockError(format!("bad table property {}", name))
+        };
+        let mut props = TableProperties::default();
+        for e in Block::decode(data, format)? {
+            let number = || match e.value.len() {
+                8 => Ok((&e.value[..]).get_u64()),
+                _ => Err(corrupted(&e.key)),
+            };
+            let string = || String::from_utf8(e.value.to_vec()).map_err(|_| corrupted(&e.key));
+            match std::str::from_utf8(&e.key).unwrap_or_default() {
+                NUM_ENTRIES => props.num_entries = number()?,
+                NUM_DELETIONS => props.num_deletions = number()?,
+                NUM_RANGE_DELETIONS => props.num_range_deletions = number()?,
+                RAW_KEY_SIZE => props.raw_key_size = number()?,
+                RAW_VALUE_SIZE => props.raw_value_size = number()?,
+                SMALLEST_SEQ => props.smallest_seq = number()?,
+                LARGEST_SEQ => props.largest_seq = number()?,
+                COMPRESSION => props.compression = string()?,
+                FILTER_POLICY => props.filter_policy = string()?,
+                _ => {}
+            }
+        }
+        Ok(props)
+    }
+}
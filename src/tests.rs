@@ -1 +1,8 @@
+mod column_family_test;
+mod comparator_test;
+mod compression_test;
+mod multi_get_test;
+mod table_properties_test;
+mod transaction_db_test;
+mod transaction_test;
 mod write_batch_test;
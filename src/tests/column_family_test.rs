@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use crate::{
+    lsm::{Lsm, LsmIterator},
+    write_batch::WriteBatch,
+    ColumnFamily, ColumnFamilyDescriptor, CompressionType, Error, MergeOperator, Options,
+    ReadOptions, DEFAULT_COLUMN_FAMILY,
+};
+
+fn clear_dir(work_dir: &str) {
+    if std::fs::metadata(work_dir).is_ok() {
+        std::fs::remove_dir_all(work_dir).unwrap()
+    };
+}
+
+fn keys(mut iter: LsmIterator) -> Vec<Vec<u8>> {
+    let mut keys = vec![];
+    while iter.valid() {
+        keys.push(iter.key().to_vec());
+        iter.next();
+    }
+    keys
+}
+
+// appends the operands to the value
+struct Append;
+
+impl MergeOperator for Append {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let mut value = existing.unwrap_or_default().to_vec();
+        operands.iter().for_each(|o| value.extend_from_slice(o));
+        Some(value)
+    }
+}
+
+#[test]
+fn test_column_family_isolation() {
+    let opt = Options::default_opt().work_dir("work_dir/cf_isolation");
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let users = lsm.create_column_family("users", opt.clone()).unwrap();
+    let orders = lsm.create_column_family("orders", opt.clone()).unwrap();
+    assert_ne!(users.id(), orders.id());
+    // creating it again hands back the same family
+    assert_eq!(
+        lsm.create_column_family("users", opt.clone()).unwrap(),
+        users
+    );
+    assert_eq!(lsm.column_family("orders"), Some(orders.clone()));
+    assert!(lsm.column_family(DEFAULT_COLUMN_FAMILY).is_some());
+
+    // the same key in each family is a different key
+    lsm.put(b"k1", b"default").unwrap();
+    lsm.put_cf(&users, b"k1", b"users").unwrap();
+    lsm.put_cf(&users, b"k2", b"users").unwrap();
+    assert_eq!(lsm.get(b"k1").unwrap().unwrap(), b"default");
+    assert_eq!(lsm.get_cf(&users, b"k1").unwrap().unwrap(), b"users");
+    assert_eq!(lsm.get_cf(&orders, b"k1").unwrap(), None);
+    lsm.delete_cf(&users, b"k1").unwrap();
+    assert_eq!(lsm.get_cf(&users, b"k1").unwrap(), None);
+    assert_eq!(lsm.get(b"k1").unwrap().unwrap(), b"default");
+
+    assert_eq!(keys(lsm.iter_cf(&users).unwrap()), vec![b"k2".to_vec()]);
+
+    // a batch spanning families takes one snapshot
+    let snapshot = lsm.snapshot();
+    let mut batch = WriteBatch::default();
+    batch.put_cf(&orders, b"o1", b"k2");
+    batch.delete_cf(&users, b"k2");
+    lsm.write_batch(&batch).unwrap();
+    assert_eq!(lsm.get_cf(&orders, b"o1").unwrap().unwrap(), b"k2");
+    assert_eq!(lsm.get_cf(&users, b"k2").unwrap(), None);
+    let read_opt = ReadOptions::default().snapshot(&snapshot);
+    assert_eq!(lsm.get_cf_opt(&orders, b"o1", &read_opt).unwrap(), None);
+    assert_eq!(
+        lsm.get_cf_opt(&users, b"k2", &read_opt).unwrap().unwrap(),
+        b"users"
+    );
+
+    // a family the storage does not have writes nothing
+    let unknown = ColumnFamily::new(100, "unknown");
+    let mut batch = WriteBatch::default();
+    batch.put(b"k3", b"v3");
+    batch.put_cf(&unknown, b"k3", b"v3");
+    assert!(matches!(
+        lsm.write_batch(&batch),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert_eq!(lsm.get(b"k3").unwrap(), None);
+    assert!(matches!(
+        lsm.get_cf(&unknown, b"k3"),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_column_family_recover() {
+    let opt = Options::default_opt()
+        .work_dir("work_dir/cf_recover")
+        .mem_size(1 << 12)
+        .kv_separate_threshold(4);
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let users = lsm.create_column_family("users", opt.clone()).unwrap();
+
+    // enough to flush and compact both families, the tail stays in the wal
+    for i in 0..2000_u32 {
+        let mut batch = WriteBatch::default();
+        batch.put(&i.to_be_bytes(), &i.to_le_bytes());
+        batch.put_cf(&users, &i.to_be_bytes(), &(i * 2).to_le_bytes());
+        lsm.write_batch(&batch).unwrap();
+    }
+    lsm.delete_cf(&users, &7_u32.to_be_bytes()).unwrap();
+    drop(lsm);
+
+    let descriptors = vec![ColumnFamilyDescriptor::new("users", opt.clone())];
+    let lsm = Lsm::open_cf(opt.clone(), descriptors).unwrap();
+    let users = lsm.column_family("users").unwrap();
+    assert_eq!(
+        lsm.create_column_family("users", opt.clone()).unwrap(),
+        users
+    );
+    for i in 0..2000_u32 {
+        let value = lsm.get(&i.to_be_bytes()).unwrap().unwrap();
+        assert_eq!(value, i.to_le_bytes());
+        let value = lsm.get_cf(&users, &i.to_be_bytes()).unwrap();
+        if i == 7 {
+            assert_eq!(value, None);
+        } else {
+            assert_eq!(value.unwrap(), (i * 2).to_le_bytes());
+        }
+    }
+    assert_eq!(keys(lsm.iter_cf(&users).unwrap()).len(), 1999);
+    assert_eq!(keys(lsm.iter().unwrap()).len(), 2000);
+
+    // a family created after the reopen is empty
+    let orders = lsm.create_column_family("orders", opt.clone()).unwrap();
+    assert_ne!(orders.id(), users.id());
+    assert_eq!(keys(lsm.iter_cf(&orders).unwrap()).len(), 0);
+
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_column_family_merge() {
+    let opt = Options::default_opt().work_dir("work_dir/cf_merge");
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let counters = lsm
+        .create_column_family("counters", opt.clone().merge_operator(Arc::new(Append)))
+        .unwrap();
+    let users = lsm.create_column_family("users", opt.clone()).unwrap();
+
+    lsm.put_cf(&counters, b"k", b"a").unwrap();
+    lsm.merge_cf(&counters, b"k", b"b").unwrap();
+    assert_eq!(lsm.get_cf(&counters, b"k").unwrap().unwrap(), b"ab");
+
+    // each merge is checked against the operator of its own family
+    assert!(matches!(
+        lsm.merge_cf(&users, b"k", b"b"),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        lsm.merge(b"k", b"b"),
+        Err(Error::InvalidArgument(_))
+    ));
+    let mut batch = WriteBatch::default();
+    batch.merge_cf(&counters, b"k", b"c");
+    batch.merge_cf(&users, b"k", b"c");
+    assert!(matches!(
+        lsm.write_batch(&batch),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert_eq!(lsm.get_cf(&counters, b"k").unwrap().unwrap(), b"ab");
+    assert_eq!(lsm.get_cf(&users, b"k").unwrap(), None);
+}
+
+#[test]
+fn test_column_family_open_options() {
+    let opt = Options::default_opt().work_dir("work_dir/cf_open_options");
+    clear_dir(&opt.work_dir);
+    let snappy = opt.clone().compression(CompressionType::Snappy);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let users = lsm.create_column_family("users", snappy.clone()).unwrap();
+    lsm.put(b"k1", b"default").unwrap();
+    lsm.put_cf(&users, b"k1", b"users").unwrap();
+    drop(lsm);
+
+    // the options of users are not persisted, it has to be given
+    assert!(matches!(
+        Lsm::open(opt.clone()),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    // the wal is flushed at open, each family with its own options, and a
+    // family the storage does not have is created
+    let descriptors = vec![
+        ColumnFamilyDescriptor::new("users", snappy),
+        ColumnFamilyDescriptor::new("orders", opt.clone()),
+    ];
+    let lsm = Lsm::open_cf(opt.clone(), descriptors).unwrap();
+    let users = lsm.column_family("users").unwrap();
+    let orders = lsm.column_family("orders").unwrap();
+    let compressions = |cf: &ColumnFamily| -> Vec<String> {
+        let props = lsm.table_properties_cf(cf).unwrap();
+        props.into_values().map(|p| p.compression).collect()
+    };
+    assert_eq!(compressions(&users), vec!["snappy"]);
+    let default = lsm.column_family(DEFAULT_COLUMN_FAMILY).unwrap();
+    assert_eq!(compressions(&default), vec!["lz4"]);
+    assert_eq!(lsm.get_cf(&users, b"k1").unwrap().unwrap(), b"users");
+    assert_eq!(lsm.get_cf(&orders, b"k1").unwrap(), None);
+
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
@@ -0,0 +1,84 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{lsm::Lsm, Comparator, Error, Options};
+
+fn clear_dir(work_dir: &str) {
+    if std::fs::metadata(work_dir).is_ok() {
+        std::fs::remove_dir_all(work_dir).unwrap()
+    };
+}
+
+struct ReverseComparator;
+
+impl Comparator for ReverseComparator {
+    fn name(&self) -> &str {
+        "test.ReverseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+#[test]
+fn test_reverse_comparator() {
+    let opt = Options::default_opt()
+        .work_dir("work_dir/reverse_comparator")
+        .mem_size(1 << 12)
+        .kv_separate_threshold(4)
+        .comparator(Arc::new(ReverseComparator));
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    // enough to flush and compact, the tail stays in the mem-table
+    for i in 0..2000_u32 {
+        lsm.put(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+    }
+    // a range runs from its larger key down in reverse order
+    lsm.delete_range(&1500_u32.to_be_bytes(), &1000_u32.to_be_bytes())
+        .unwrap();
+    assert!(matches!(
+        lsm.delete_range(&1000_u32.to_be_bytes(), &1500_u32.to_be_bytes()),
+        Err(Error::InvalidArgument(_))
+    ));
+    drop(lsm);
+
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    for i in 0..2000_u32 {
+        let value = lsm.get(&i.to_be_bytes()).unwrap();
+        if (1001..=1500).contains(&i) {
+            assert_eq!(value, None);
+        } else {
+            assert_eq!(value.unwrap(), i.to_le_bytes());
+        }
+    }
+
+    // iterated from the largest key down
+    let mut iter = lsm.iter().unwrap();
+    let mut expected = (0..2000_u32).rev().filter(|i| !(1001..=1500).contains(i));
+    while iter.valid() {
+        assert_eq!(iter.key(), expected.next().unwrap().to_be_bytes());
+        iter.next();
+    }
+    assert_eq!(expected.next(), None);
+
+    let mut iter = lsm
+        .range(20_u32.to_be_bytes().to_vec()..10_u32.to_be_bytes().to_vec())
+        .unwrap();
+    let mut keys = vec![];
+    while iter.valid() {
+        keys.push(u32::from_be_bytes(iter.key().try_into().unwrap()));
+        iter.next();
+    }
+    assert_eq!(keys, (11..=20).rev().collect::<Vec<_>>());
+    iter.seek_for_prev(&1600_u32.to_be_bytes());
+    assert!(!iter.valid());
+    drop(lsm);
+
+    // the order of the keys on disk is fixed once written
+    let bytewise = Options::default_opt().work_dir(&opt.work_dir);
+    assert!(matches!(
+        Lsm::open(bytewise),
+        Err(Error::InvalidArgument(_))
+    ));
+    clear_dir(&opt.work_dir);
+}
@@ -0,0 +1,78 @@
+use crate::{lsm::Lsm, CompressionType, Options};
+
+fn clear_dir(work_dir: &str) {
+    if std::fs::metadata(work_dir).is_ok() {
+        std::fs::remove_dir_all(work_dir).unwrap()
+    };
+}
+
+#[test]
+fn test_compression_per_level() {
+    let opt = Options::default_opt()
+        .work_dir("work_dir/compression_per_level")
+        .mem_size(1 << 12)
+        .compression(CompressionType::Snappy)
+        .compression_per_level(vec![CompressionType::None, CompressionType::Zstd]);
+    assert_eq!(opt.compression_for_level(0), CompressionType::None);
+    assert_eq!(opt.compression_for_level(5), CompressionType::Zstd);
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    // enough to flush overlapping tables and compact them
+    for i in (0..3000_u32).map(|i| i * 7919 % 3000) {
+        lsm.put(&i.to_be_bytes(), format!("value{:032}", i).as_bytes())
+            .unwrap();
+    }
+    drop(lsm);
+
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    for i in 0..3000_u32 {
+        let value = lsm.get(&i.to_be_bytes()).unwrap().unwrap();
+        assert_eq!(value, format!("value{:032}", i).as_bytes());
+    }
+    let props = lsm.table_properties().unwrap();
+    assert!(props.values().any(|p| p.compression == "zstd"));
+    assert!(props
+        .values()
+        .all(|p| p.compression == "none" || p.compression == "zstd"));
+    drop(lsm);
+
+    // blocks record their compression, the tables read under another one
+    let other = opt.clone().compression_per_level(vec![]);
+    let lsm = Lsm::open(other).unwrap();
+    for i in (0..3000_u32).step_by(7) {
+        assert!(lsm.get(&i.to_be_bytes()).unwrap().is_some());
+    }
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_compression_of_flush_below_level0() {
+    let opt = Options::default_opt()
+        .work_dir("work_dir/compression_flush_level")
+        .flush_on_close(true)
+        .compression_per_level(vec![
+            CompressionType::None,
+            CompressionType::None,
+            CompressionType::Zstd,
+        ]);
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    for i in 0..100_u32 {
+        lsm.put(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+    }
+    lsm.close().unwrap();
+
+    // overlapping no table, the flushed one goes down to level 2 and is
+    // compressed as the tables there
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let props = lsm.table_properties().unwrap();
+    assert_eq!(props.len(), 1);
+    assert!(props.values().all(|p| p.compression == "zstd"));
+    for i in 0..100_u32 {
+        let value = lsm.get(&i.to_be_bytes()).unwrap().unwrap();
+        assert_eq!(value, i.to_le_bytes());
+    }
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
@@ -0,0 +1,102 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{lsm::Lsm, ColumnFamily, Error, MergeOperator, Options, ReadOptions};
+
+fn clear_dir(work_dir: &str) {
+    if std::fs::metadata(work_dir).is_ok() {
+        std::fs::remove_dir_all(work_dir).unwrap()
+    };
+}
+
+// appends the operands to the value
+struct Append;
+
+impl MergeOperator for Append {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let mut value = existing.unwrap_or_default().to_vec();
+        operands.iter().for_each(|o| value.extend_from_slice(o));
+        Some(value)
+    }
+}
+
+#[test]
+fn test_multi_get() {
+    let opt = Options::default_opt()
+        .work_dir("work_dir/multi_get")
+        .mem_size(1 << 12)
+        .kv_separate_threshold(4)
+        .merge_operator(Arc::new(Append));
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    // enough to flush and compact, the tail stays in the mem-table
+    for i in 0..2000_u32 {
+        lsm.put(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+    }
+    let snapshot = lsm.snapshot();
+    lsm.delete(&10_u32.to_be_bytes()).unwrap();
+    lsm.delete_range(&100_u32.to_be_bytes(), &200_u32.to_be_bytes())
+        .unwrap();
+    lsm.merge(&20_u32.to_be_bytes(), b"+").unwrap();
+    lsm.put_with_ttl(&30_u32.to_be_bytes(), b"gone", Duration::ZERO)
+        .unwrap();
+
+    // out of order, repeated and missing keys
+    let keys: Vec<[u8; 4]> = [1999_u32, 10, 150, 20, 5, 30, 5000, 1999, 0, 1500]
+        .iter()
+        .map(|i| i.to_be_bytes())
+        .collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+    let values: Vec<_> = lsm
+        .multi_get(&keys)
+        .into_iter()
+        .map(|v| v.unwrap())
+        .collect();
+    for (key, value) in keys.iter().zip(values.iter()) {
+        assert_eq!(value, &lsm.get(key).unwrap(), "key {:?}", key);
+    }
+    let mut merged = 20_u32.to_le_bytes().to_vec();
+    merged.push(b'+');
+    assert_eq!(values[3].as_deref(), Some(&merged[..]));
+    assert_eq!(values[1], None);
+    assert_eq!(values[2], None);
+    assert_eq!(values[5], None);
+    assert_eq!(values[6], None);
+    assert_eq!(values[7].as_deref(), Some(&1999_u32.to_le_bytes()[..]));
+
+    // every key is read as of the snapshot
+    let read_opt = ReadOptions::default().snapshot(&snapshot);
+    for (i, value) in lsm.multi_get_opt(&keys, &read_opt).into_iter().enumerate() {
+        let n = u32::from_be_bytes(keys[i].try_into().unwrap());
+        let expected = (n < 2000).then(|| n.to_le_bytes().to_vec());
+        assert_eq!(value.unwrap(), expected);
+    }
+    drop(read_opt);
+    drop(snapshot);
+
+    // a family the storage does not have fails every key
+    let unknown = ColumnFamily::new(100, "unknown");
+    let values = lsm.multi_get_cf(&unknown, &keys);
+    assert_eq!(values.len(), keys.len());
+    assert!(values
+        .iter()
+        .all(|v| matches!(v, Err(Error::InvalidArgument(_)))));
+
+    drop(lsm);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let keys: Vec<[u8; 4]> = (0..2000_u32).rev().map(|i| i.to_be_bytes()).collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+    for (key, value) in keys.iter().zip(lsm.multi_get(&keys)) {
+        assert_eq!(value.unwrap(), lsm.get(key).unwrap());
+    }
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
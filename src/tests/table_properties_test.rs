@@ -0,0 +1,40 @@
+use crate::{lsm::Lsm, ColumnFamilyDescriptor, Options};
+
+fn clear_dir(work_dir: &str) {
+    if std::fs::metadata(work_dir).is_ok() {
+        std::fs::remove_dir_all(work_dir).unwrap()
+    };
+}
+
+#[test]
+fn test_table_properties() {
+    let opt = Options::default_opt()
+        .work_dir("work_dir/table_properties")
+        .mem_size(1 << 12)
+        .flush_on_close(true);
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let users = lsm.create_column_family("users", opt.clone()).unwrap();
+    assert!(lsm.table_properties().unwrap().is_empty());
+    for i in 0..1000_u32 {
+        lsm.put(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+    }
+    for i in 0..100_u32 {
+        lsm.delete(&i.to_be_bytes()).unwrap();
+    }
+    drop(lsm);
+
+    let descriptors = vec![ColumnFamilyDescriptor::new("users", opt.clone())];
+    let lsm = Lsm::open_cf(opt.clone(), descriptors).unwrap();
+    let props = lsm.table_properties().unwrap();
+    assert!(!props.is_empty());
+    // a deletion compacted to the bottom takes the put it deletes along
+    let entries: u64 = props.values().map(|p| p.num_entries).sum();
+    assert!((900..=1100).contains(&entries));
+    assert!(props.values().all(|p| p.smallest_seq <= p.largest_seq));
+    assert!(props.values().all(|p| p.largest_seq <= 1100));
+    assert!(props.values().all(|p| p.compression == "lz4"));
+    assert!(lsm.table_properties_cf(&users).unwrap().is_empty());
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
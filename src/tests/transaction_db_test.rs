@@ -0,0 +1,120 @@
+use std::{sync::mpsc, time::Duration};
+
+use crate::{
+    transaction_db::{TransactionDB, TransactionDBOptions},
+    Error, Options,
+};
+
+fn clear_dir(work_dir: &str) {
+    if std::fs::metadata(work_dir).is_ok() {
+        std::fs::remove_dir_all(work_dir).unwrap()
+    };
+}
+
+#[test]
+fn test_transaction_db_locking() {
+    let opt = Options::default_opt().work_dir("work_dir/txn_db_locking");
+    clear_dir(&opt.work_dir);
+    let txn_opt = TransactionDBOptions::default().lock_timeout(Duration::from_millis(50));
+    let db = TransactionDB::open(opt.clone(), txn_opt).unwrap();
+    db.put(b"k1", b"v1").unwrap();
+
+    let mut txn1 = db.begin_transaction();
+    assert_eq!(txn1.get_for_update(b"k1").unwrap().unwrap(), b"v1");
+    txn1.put(b"k2", b"v2").unwrap();
+    // the transaction reads its own writes, nobody else does until commit
+    assert_eq!(txn1.get(b"k2").unwrap().unwrap(), b"v2");
+    assert_eq!(db.get(b"k2").unwrap(), None);
+
+    // keys locked by txn1 cannot be written or locked by anyone else
+    let mut txn2 = db.begin_transaction();
+    assert!(matches!(txn2.put(b"k1", b"x"), Err(Error::TimedOut(_))));
+    assert!(matches!(
+        txn2.get_for_update(b"k2"),
+        Err(Error::TimedOut(_))
+    ));
+    assert!(matches!(db.delete(b"k1"), Err(Error::TimedOut(_))));
+    // plain reads take no lock
+    assert_eq!(txn2.get(b"k1").unwrap().unwrap(), b"v1");
+    txn2.put(b"k3", b"v3").unwrap();
+
+    txn1.commit().unwrap();
+    assert_eq!(db.get(b"k2").unwrap().unwrap(), b"v2");
+    // the locks are released with the commit
+    txn2.delete(b"k1").unwrap();
+    txn2.commit().unwrap();
+    assert_eq!(db.get(b"k1").unwrap(), None);
+    assert_eq!(db.get(b"k3").unwrap().unwrap(), b"v3");
+
+    // a rollback discards the writes and releases the locks
+    let mut txn = db.begin_transaction();
+    txn.put(b"k4", b"v4").unwrap();
+    txn.rollback();
+    assert_eq!(db.get(b"k4").unwrap(), None);
+    db.put(b"k4", b"v4").unwrap();
+
+    db.close().unwrap();
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_transaction_db_deadlock() {
+    let opt = Options::default_opt().work_dir("work_dir/txn_db_deadlock");
+    clear_dir(&opt.work_dir);
+    let txn_opt = TransactionDBOptions::default().lock_timeout(Duration::from_secs(10));
+    let db = TransactionDB::open(opt.clone(), txn_opt).unwrap();
+
+    let (locked_tx, locked_rx) = mpsc::channel();
+    std::thread::scope(|s| {
+        let mut txn1 = db.begin_transaction();
+        txn1.put(b"a", b"1").unwrap();
+
+        let waiter = s.spawn(|| {
+            let mut txn2 = db.begin_transaction();
+            txn2.put(b"b", b"2").unwrap();
+            locked_tx.send(()).unwrap();
+            // waits on txn1 until it gives up
+            txn2.put(b"a", b"2").unwrap();
+            txn2.commit().unwrap();
+        });
+        locked_rx.recv().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        // txn2 waits on txn1, so txn1 waiting on txn2 would never end
+        assert!(matches!(txn1.put(b"b", b"1"), Err(Error::Deadlock(_))));
+        drop(txn1);
+        waiter.join().unwrap();
+    });
+    assert_eq!(db.get(b"a").unwrap().unwrap(), b"2");
+    assert_eq!(db.get(b"b").unwrap().unwrap(), b"2");
+
+    db.close().unwrap();
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_transaction_db_concurrent_increments() {
+    let opt = Options::default_opt().work_dir("work_dir/txn_db_increments");
+    clear_dir(&opt.work_dir);
+    let db = TransactionDB::open(opt.clone(), TransactionDBOptions::default()).unwrap();
+    db.put(b"counter", &0_u64.to_be_bytes()).unwrap();
+
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                // the lock serializes the read-modify-writes, no retry needed
+                for _ in 0..50 {
+                    let mut txn = db.begin_transaction();
+                    let value = txn.get_for_update(b"counter").unwrap().unwrap();
+                    let n = u64::from_be_bytes(value.try_into().unwrap());
+                    txn.put(b"counter", &(n + 1).to_be_bytes()).unwrap();
+                    txn.commit().unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(db.get(b"counter").unwrap().unwrap(), 200_u64.to_be_bytes());
+
+    db.close().unwrap();
+    clear_dir(&opt.work_dir);
+}
@@ -0,0 +1,171 @@
+use crate::{lsm::Lsm, Error, Options};
+
+fn clear_dir(work_dir: &str) {
+    if std::fs::metadata(work_dir).is_ok() {
+        std::fs::remove_dir_all(work_dir).unwrap()
+    };
+}
+
+#[test]
+fn test_transaction_isolation() {
+    let opt = Options::default_opt().work_dir("work_dir/txn_isolation");
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    lsm.put(b"k1", b"v1").unwrap();
+
+    let mut txn = lsm.begin_transaction();
+    txn.put(b"k2", b"v2");
+    txn.delete(b"k1");
+    // the transaction reads its own writes, nobody else does until commit
+    assert_eq!(txn.get(b"k2").unwrap().unwrap(), b"v2");
+    assert_eq!(txn.get(b"k1").unwrap(), None);
+    assert_eq!(lsm.get(b"k2").unwrap(), None);
+    assert_eq!(lsm.get(b"k1").unwrap().unwrap(), b"v1");
+    txn.commit().unwrap();
+
+    assert_eq!(lsm.get(b"k2").unwrap().unwrap(), b"v2");
+    assert_eq!(lsm.get(b"k1").unwrap(), None);
+
+    // writes made after a transaction began are not seen by it
+    let mut txn = lsm.begin_transaction();
+    lsm.put(b"k3", b"v3").unwrap();
+    assert_eq!(txn.get(b"k3").unwrap(), None);
+    // nothing to write, so nothing to conflict with
+    txn.commit().unwrap();
+
+    // dropping a transaction discards its writes
+    let mut txn = lsm.begin_transaction();
+    txn.put(b"k4", b"v4");
+    drop(txn);
+    assert_eq!(lsm.get(b"k4").unwrap(), None);
+
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_transaction_conflict() {
+    let opt = Options::default_opt()
+        .work_dir("work_dir/txn_conflict")
+        .mem_size(1 << 12)
+        .kv_separate_threshold(4);
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    lsm.put(b"read", b"0").unwrap();
+
+    // a key read by the transaction is written by someone else
+    let mut txn = lsm.begin_transaction();
+    assert_eq!(txn.get(b"read").unwrap().unwrap(), b"0");
+    txn.put(b"written", b"1");
+    lsm.put(b"read", b"1").unwrap();
+    assert!(matches!(txn.commit(), Err(Error::Conflict(_))));
+    assert_eq!(lsm.get(b"written").unwrap(), None);
+
+    // two transactions write the same key, the second to commit fails
+    let mut txn1 = lsm.begin_transaction();
+    let mut txn2 = lsm.begin_transaction();
+    txn1.put(b"written", b"1");
+    txn2.put(b"written", b"2");
+    txn1.commit().unwrap();
+    assert!(matches!(txn2.commit(), Err(Error::Conflict(_))));
+    assert_eq!(lsm.get(b"written").unwrap().unwrap(), b"1");
+
+    // a range deletion over a key read is a write too, even once the
+    // mem-table holding it is flushed
+    let mut txn = lsm.begin_transaction();
+    assert_eq!(txn.get(b"read").unwrap().unwrap(), b"1");
+    txn.put(b"written", b"3");
+    lsm.delete_range(b"r", b"s").unwrap();
+    for i in 0..1000_u32 {
+        lsm.put(&i.to_be_bytes(), &i.to_be_bytes()).unwrap();
+    }
+    assert!(matches!(txn.commit(), Err(Error::Conflict(_))));
+
+    // keys untouched since the transaction began commit fine
+    let mut txn = lsm.begin_transaction();
+    assert_eq!(txn.get(b"read").unwrap(), None);
+    txn.put(b"written", b"4");
+    lsm.put(b"other", b"1").unwrap();
+    txn.commit().unwrap();
+    assert_eq!(lsm.get(b"written").unwrap().unwrap(), b"4");
+
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_transaction_column_family_conflict() {
+    let opt = Options::default_opt().work_dir("work_dir/txn_cf_conflict");
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    let users = lsm.create_column_family("users", opt.clone()).unwrap();
+    lsm.put_cf(&users, b"k", b"0").unwrap();
+
+    // the transaction reads and writes its own keys of each family
+    let mut txn = lsm.begin_transaction();
+    assert_eq!(txn.get_cf(&users, b"k").unwrap().unwrap(), b"0");
+    txn.put_cf(&users, b"written", b"1");
+    txn.delete_cf(&users, b"k");
+    assert_eq!(txn.get_cf(&users, b"written").unwrap().unwrap(), b"1");
+    assert_eq!(txn.get_cf(&users, b"k").unwrap(), None);
+    assert_eq!(txn.get(b"written").unwrap(), None);
+
+    // the same key written in another family is no conflict
+    lsm.put(b"k", b"1").unwrap();
+    txn.commit().unwrap();
+    assert_eq!(lsm.get_cf(&users, b"written").unwrap().unwrap(), b"1");
+    assert_eq!(lsm.get_cf(&users, b"k").unwrap(), None);
+
+    // a key of a family read by the transaction is written by someone else
+    let mut txn = lsm.begin_transaction();
+    assert_eq!(txn.get_cf(&users, b"written").unwrap().unwrap(), b"1");
+    txn.put(b"k", b"2");
+    lsm.put_cf(&users, b"written", b"2").unwrap();
+    assert!(matches!(txn.commit(), Err(Error::Conflict(_))));
+    assert_eq!(lsm.get(b"k").unwrap().unwrap(), b"1");
+
+    // two transactions write the same key of a family
+    let mut txn1 = lsm.begin_transaction();
+    let mut txn2 = lsm.begin_transaction();
+    txn1.put_cf(&users, b"k", b"3");
+    txn2.put_cf(&users, b"k", b"4");
+    txn1.commit().unwrap();
+    assert!(matches!(txn2.commit(), Err(Error::Conflict(_))));
+    assert_eq!(lsm.get_cf(&users, b"k").unwrap().unwrap(), b"3");
+
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
+
+#[test]
+fn test_transaction_concurrent_increments() {
+    let opt = Options::default_opt().work_dir("work_dir/txn_increments");
+    clear_dir(&opt.work_dir);
+    let lsm = Lsm::open(opt.clone()).unwrap();
+    lsm.put(b"counter", &0_u64.to_be_bytes()).unwrap();
+
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..50 {
+                    // retry the read-modify-write until nobody interferes
+                    loop {
+                        let mut txn = lsm.begin_transaction();
+                        let value = txn.get(b"counter").unwrap().unwrap();
+                        let n = u64::from_be_bytes(value.try_into().unwrap());
+                        txn.put(b"counter", &(n + 1).to_be_bytes());
+                        match txn.commit() {
+                            Ok(()) => break,
+                            Err(Error::Conflict(_)) => continue,
+                            Err(err) => panic!("{}", err),
+                        }
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(lsm.get(b"counter").unwrap().unwrap(), 200_u64.to_be_bytes());
+
+    drop(lsm);
+    clear_dir(&opt.work_dir);
+}
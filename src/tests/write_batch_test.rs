@@ -26,7 +26,7 @@ fn test_write_batch() {
         }
     }
 
-    let lsm = Lsm::open(opt.clone());
+    let lsm = Lsm::open(opt.clone()).unwrap();
     let result = lsm.write_batch(&batch);
     assert!(result.is_ok());
 
@@ -61,12 +61,12 @@ fn test_write_batch_recover_mem() {
     }
 
     {
-        let lsm = Lsm::open(opt.clone());
+        let lsm = Lsm::open(opt.clone()).unwrap();
         let result = lsm.write_batch(&batch);
         assert!(result.is_ok());
     }
 
-    let lsm = Lsm::open(opt.clone());
+    let lsm = Lsm::open(opt.clone()).unwrap();
 
     for i in 0..10 {
         let n = i as u32;
@@ -0,0 +1,110 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    lsm::{ConflictCheck, Lsm},
+    snapshot::Snapshot,
+    write_batch::WriteBatch,
+    ColumnFamily, ReadOptions, Result, WriteOptions,
+};
+
+/// An optimistic transaction over an [`Lsm`], begun by
+/// [`Lsm::begin_transaction`].
+///
+/// Writes are buffered until `commit`, and read back by the reads of the
+/// transaction. Other reads see the storage as of the snapshot taken when the
+/// transaction began. Nothing is locked: `commit` fails with
+/// [`Conflict`](crate::Error::Conflict), writing nothing, if a key the
+/// transaction read or wrote, in any column family, was written by anyone
+/// else after the snapshot. Dropping the transaction discards its writes.
+pub struct Transaction<'a> {
+    lsm: &'a Lsm,
+    snapshot: Snapshot,
+    batch: WriteBatch,
+    // own writes by column family and key, none for a deletion
+    writes: BTreeMap<(u32, Vec<u8>), Option<Vec<u8>>>,
+    // keys read or written with their column family, checked for newer
+    // writes on commit
+    tracked: BTreeSet<(u32, Vec<u8>)>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(lsm: &'a Lsm, snapshot: Snapshot) -> Self {
+        Self {
+            lsm,
+            snapshot,
+            batch: WriteBatch::default(),
+            writes: BTreeMap::new(),
+            tracked: BTreeSet::new(),
+        }
+    }
+
+    /// The sequence the transaction reads at.
+    pub fn sequence(&self) -> u64 {
+        self.snapshot.sequence()
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_in(None, key)
+    }
+
+    pub fn get_cf(&mut self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_in(Some(cf), key)
+    }
+
+    // read `key` of family `cf`, the default one if none
+    fn get_in(&mut self, cf: Option<&ColumnFamily>, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let tracked = (cf.map_or(0, |cf| cf.id()), key.to_vec());
+        if let Some(value) = self.writes.get(&tracked) {
+            return Ok(value.clone());
+        }
+        self.tracked.insert(tracked);
+        let opt = ReadOptions::default().snapshot(&self.snapshot);
+        match cf {
+            Some(cf) => self.lsm.get_cf_opt(cf, key, &opt),
+            None => self.lsm.get_opt(key, &opt),
+        }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.batch.put(key, value);
+        self.track_write(0, key, Some(value));
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamily, key: &[u8], value: &[u8]) {
+        self.batch.put_cf(cf, key, value);
+        self.track_write(cf.id(), key, Some(value));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.batch.delete(key);
+        self.track_write(0, key, None);
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamily, key: &[u8]) {
+        self.batch.delete_cf(cf, key);
+        self.track_write(cf.id(), key, None);
+    }
+
+    fn track_write(&mut self, cf: u32, key: &[u8], value: Option<&[u8]>) {
+        self.writes
+            .insert((cf, key.to_vec()), value.map(|v| v.to_vec()));
+        self.tracked.insert((cf, key.to_vec()));
+    }
+
+    pub fn commit(self) -> Result<()> {
+        self.commit_opt(&WriteOptions::default())
+    }
+
+    /// Write the buffered writes as one batch, if no key the transaction
+    /// read or wrote was written since it began.
+    pub fn commit_opt(self, opt: &WriteOptions) -> Result<()> {
+        if self.batch.count == 0 {
+            return Ok(());
+        }
+        let check = ConflictCheck {
+            sequence: self.snapshot.sequence(),
+            keys: self.tracked.into_iter().collect(),
+        };
+        self.lsm.write_checked(&self.batch, opt, check)
+    }
+}
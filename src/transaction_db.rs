@@ -0,0 +1,188 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    lock_manager::LockManager, lsm::Lsm, write_batch::WriteBatch, Options, Result, WriteOptions,
+};
+
+/// Options of a [`TransactionDB`].
+#[derive(Clone, Debug)]
+pub struct TransactionDBOptions {
+    /// How long a transaction waits for a key locked by another one before
+    /// failing with [`TimedOut`](crate::Error::TimedOut).
+    pub lock_timeout: Duration,
+    /// Number of stripes the key locks are spread over.
+    pub num_stripes: usize,
+    /// Fail a lock with [`Deadlock`](crate::Error::Deadlock) rather than wait
+    /// on a transaction that waits, directly or not, on the locker.
+    pub deadlock_detect: bool,
+}
+
+impl Default for TransactionDBOptions {
+    fn default() -> Self {
+        Self {
+            lock_timeout: Duration::from_secs(1),
+            num_stripes: 16,
+            deadlock_detect: true,
+        }
+    }
+}
+
+impl TransactionDBOptions {
+    pub fn lock_timeout(&mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self.clone()
+    }
+
+    pub fn num_stripes(&mut self, num_stripes: usize) -> Self {
+        self.num_stripes = num_stripes;
+        self.clone()
+    }
+
+    pub fn deadlock_detect(&mut self, deadlock_detect: bool) -> Self {
+        self.deadlock_detect = deadlock_detect;
+        self.clone()
+    }
+}
+
+/// An [`Lsm`] whose writes go through pessimistic transactions.
+///
+/// A transaction locks every key it writes, or reads with
+/// [`get_for_update`](PessimisticTransaction::get_for_update), until it ends,
+/// so its commit never conflicts. Writes made through the db itself lock
+/// their key for the length of the write.
+pub struct TransactionDB {
+    lsm: Lsm,
+    locks: LockManager,
+    txn_opt: TransactionDBOptions,
+    next_txn_id: AtomicU64,
+}
+
+impl TransactionDB {
+    pub fn open(opt: Options, txn_opt: TransactionDBOptions) -> Result<Self> {
+        Ok(Self {
+            lsm: Lsm::open(opt)?,
+            locks: LockManager::new(txn_opt.num_stripes, txn_opt.deadlock_detect),
+            txn_opt,
+            next_txn_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Close the underlying storage, see [`Lsm::close`].
+    pub fn close(self) -> Result<()> {
+        self.lsm.close()
+    }
+
+    pub fn begin_transaction(&self) -> PessimisticTransaction<'_> {
+        PessimisticTransaction {
+            db: self,
+            id: self.next_txn_id.fetch_add(1, Ordering::Relaxed),
+            batch: WriteBatch::default(),
+            writes: BTreeMap::new(),
+            locked: BTreeSet::new(),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lsm.get(key)
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut txn = self.begin_transaction();
+        txn.put(key, value)?;
+        txn.commit()
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut txn = self.begin_transaction();
+        txn.delete(key)?;
+        txn.commit()
+    }
+}
+
+/// A pessimistic transaction over a [`TransactionDB`], begun by
+/// [`TransactionDB::begin_transaction`].
+///
+/// Writes are buffered until `commit`, and read back by the reads of the
+/// transaction. Other reads see the latest committed state. Locks are taken
+/// as keys are written or read for update, and released when the
+/// transaction commits or is dropped; dropping it also discards its writes.
+pub struct PessimisticTransaction<'a> {
+    db: &'a TransactionDB,
+    id: u64,
+    batch: WriteBatch,
+    // own writes by key, none for a deletion
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    // keys locked by the transaction
+    locked: BTreeSet<Vec<u8>>,
+}
+
+impl PessimisticTransaction<'_> {
+    /// The id naming the transaction in lock errors.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        self.db.lsm.get(key)
+    }
+
+    /// Lock `key` and read it. Nobody else can write the key until the
+    /// transaction ends, so the value read stays the latest one.
+    pub fn get_for_update(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lock(key)?;
+        self.get(key)
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.lock(key)?;
+        self.batch.put(key, value);
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.lock(key)?;
+        self.batch.delete(key);
+        self.writes.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn lock(&mut self, key: &[u8]) -> Result<()> {
+        if self.locked.contains(key) {
+            return Ok(());
+        }
+        self.db
+            .locks
+            .lock(self.id, key, self.db.txn_opt.lock_timeout)?;
+        self.locked.insert(key.to_vec());
+        Ok(())
+    }
+
+    pub fn commit(self) -> Result<()> {
+        self.commit_opt(&WriteOptions::default())
+    }
+
+    /// Write the buffered writes as one batch and release the locks.
+    pub fn commit_opt(self, opt: &WriteOptions) -> Result<()> {
+        if self.batch.count == 0 {
+            return Ok(());
+        }
+        self.db.lsm.write_batch_opt(&self.batch, opt)
+    }
+
+    /// Discard the buffered writes and release the locks.
+    pub fn rollback(self) {}
+}
+
+impl Drop for PessimisticTransaction<'_> {
+    fn drop(&mut self) {
+        self.db.locks.unlock_all(self.id, &self.locked);
+    }
+}
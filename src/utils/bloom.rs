@@ -9,13 +9,10 @@ pub struct BloomFilter {
 }
 
 impl BloomFilter {
+    pub const NAME: &'static str = "ckv.BuiltinBloomFilter";
+
     pub fn new(bits_per_key: usize) -> Self {
-        let mut k = bits_per_key as f64 * f64::ln(2_f64);
-        if k > 30f64 {
-            k = 30f64;
-        } else if k < 1f64 {
-            k = 1f64;
-        }
+        let k = (bits_per_key as f64 * f64::ln(2_f64)).clamp(1f64, 30f64);
         Self {
             filters: Vec::new(),
             bits_per_key,
@@ -52,15 +49,32 @@ impl BloomFilter {
     }
 
     pub fn bits_per_key(num: u32, fp: f64) -> usize {
-        let size = -1.0_f64 * (num as f64) * f64::ln(fp) / (f64::ln(2_f64).powi(2));
+        let size = -(num as f64) * f64::ln(fp) / (f64::ln(2_f64).powi(2));
         if size < 0_f64 {
             return 0;
         }
         f64::ceil(size / num as f64) as usize
     }
+
+    /// The parameters the filters are built with, kept in the filter block of
+    /// a table.
+    pub fn params(&self) -> Vec<u8> {
+        (self.bits_per_key as u32).to_be_bytes().to_vec()
+    }
+
+    /// The policy built with `params`, none if they are not those of a bloom
+    /// filter.
+    pub fn from_params(params: &[u8]) -> Option<Self> {
+        let bits_per_key = u32::from_be_bytes(params.try_into().ok()?);
+        Some(Self::new(bits_per_key as usize))
+    }
 }
 
 impl FilterPolicy for BloomFilter {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
     fn may_contain(&self, filter: &[u8], key: &[u8]) -> bool {
         let n = filter.len() - 1; // exclude the k
         if filter.is_empty() || n < 1 {
@@ -78,7 +92,7 @@ impl FilterPolicy for BloomFilter {
 
         let nbits = n * 8;
         let mut h = Self::hash(key);
-        let delta = (h >> 17) | (h << 15); // rotate right 17 bits
+        let delta = h.rotate_right(17); // rotate right 17 bits
 
         for _ in 0..k {
             let bit_pos = h % (nbits as u32);
@@ -95,14 +109,14 @@ impl FilterPolicy for BloomFilter {
         if nbits < 64 {
             nbits = 64;
         }
-        let nbytes = (nbits + 7) / 8;
+        let nbytes = nbits.div_ceil(8);
         nbits = nbytes * 8;
 
         let mut filter: Vec<u8> = vec![0; nbytes + 1]; // the extra place of the length bits
 
         for key in keys {
             let mut h = Self::hash(key.as_slice());
-            let delta = (h >> 17) | (h << 15); // rotate right 17 bits
+            let delta = h.rotate_right(17); // rotate right 17 bits
             for _ in 0..self.k {
                 let bit_pos = h % (nbits as u32);
                 filter[(bit_pos / 8) as usize] |= 1 << (bit_pos % 8);
@@ -134,4 +148,13 @@ mod bloom_test {
             assert!(res);
         }
     }
+
+    #[test]
+    fn bloom_params_test() {
+        let bloom = BloomFilter::new(14);
+        let loaded = BloomFilter::from_params(&bloom.params()).unwrap();
+        assert_eq!(loaded.bits_per_key, 14);
+        assert_eq!(loaded.k, bloom.k);
+        assert!(BloomFilter::from_params(&[1, 2]).is_none());
+    }
 }
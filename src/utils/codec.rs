@@ -1,4 +1,4 @@
-use bytes::BufMut;
+use bytes::{Buf, BufMut};
 
 /// The error type of catalog operations.
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
@@ -7,6 +7,8 @@ pub enum CodecError {
     InvalidVarint(String),
     #[error("Checksum not match: expected {0}, found {1}")]
     ChecksumNotMatch(u64, u64),
+    #[error("invalid record: {0}")]
+    InvalidRecord(String),
 }
 
 pub fn varintu32_length(v: u32) -> u32 {
@@ -34,22 +36,87 @@ pub fn encode_varintu32(buf: &mut Vec<u8>, v: u32) -> u32 {
 }
 
 pub fn decode_varintu32(buf: &[u8]) -> Result<u32, CodecError> {
-    let b: u8 = 128;
     let mut v = 0;
-    let mut i = 0;
-    loop {
-        let Some(byte) = buf.get(i) else {
-            return Err(CodecError::InvalidVarint(String::from_utf8(buf.to_vec()).unwrap()));
-        };
-        v += ((byte & 0x7F) as u32) << (i * 7);
-        i += 1;
-        if (byte & b) == 0 {
-            break;
+    // at most 5 bytes make 32 bits
+    for (i, byte) in buf.iter().take(5).enumerate() {
+        v |= ((byte & 0x7F) as u32) << (i * 7);
+        if byte & 128 == 0 {
+            return Ok(v);
         }
     }
+    Err(CodecError::InvalidVarint(format!("{:?}", buf)))
+}
+
+pub fn varintu64_length(v: u64) -> u32 {
+    let mut v = v;
+    let mut len = 1;
+    while v >= 128 {
+        v >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub fn encode_varintu64(buf: &mut Vec<u8>, v: u64) -> u32 {
+    let mut v = v;
+    let mut len = 1;
+    while v >= 128 {
+        buf.put_u8((v | 128) as u8);
+        v >>= 7;
+        len += 1;
+    }
+    buf.put_u8(v as u8);
+    len
+}
+
+pub fn decode_varintu64(buf: &[u8]) -> Result<u64, CodecError> {
+    let mut v = 0;
+    // at most 10 bytes make 64 bits
+    for (i, byte) in buf.iter().take(10).enumerate() {
+        v |= ((byte & 0x7F) as u64) << (i * 7);
+        if byte & 128 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(CodecError::InvalidVarint(format!("{:?}", buf)))
+}
+
+/// Take `n` bytes off the front of `buf`.
+pub fn take_bytes<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8], CodecError> {
+    if buf.len() < n {
+        return Err(CodecError::InvalidRecord(format!(
+            "need {} bytes, {} left",
+            n,
+            buf.len()
+        )));
+    }
+    let (data, rest) = buf.split_at(n);
+    *buf = rest;
+    Ok(data)
+}
+
+/// Take a varint off the front of `buf`.
+pub fn take_varintu32(buf: &mut &[u8]) -> Result<u32, CodecError> {
+    let v = decode_varintu32(buf)?;
+    *buf = &buf[varintu32_length(v) as usize..];
+    Ok(v)
+}
+
+/// Take a 64-bit varint off the front of `buf`.
+pub fn take_varintu64(buf: &mut &[u8]) -> Result<u64, CodecError> {
+    let v = decode_varintu64(buf)?;
+    *buf = &buf[varintu64_length(v) as usize..];
     Ok(v)
 }
 
+pub fn take_u32(buf: &mut &[u8]) -> Result<u32, CodecError> {
+    Ok(take_bytes(buf, 4)?.get_u32())
+}
+
+pub fn take_u64(buf: &mut &[u8]) -> Result<u64, CodecError> {
+    Ok(take_bytes(buf, 8)?.get_u64())
+}
+
 pub fn calculate_checksum(data: &[u8]) -> u64 {
     crc32fast::hash(data) as u64
 }
@@ -65,7 +132,10 @@ pub fn verify_checksum(data: &[u8], expected: u64) -> Result<(), CodecError> {
 #[cfg(test)]
 mod codec_tests {
 
-    use crate::utils::codec::{decode_varintu32, encode_varintu32};
+    use crate::utils::codec::{
+        decode_varintu32, decode_varintu64, encode_varintu32, encode_varintu64, take_varintu64,
+        varintu64_length,
+    };
 
     #[test]
     fn codec_u32() {
@@ -110,5 +180,48 @@ mod codec_tests {
         assert_eq!(len, 4);
         let y = decode_varintu32(&buf[..]);
         assert_eq!(y.unwrap(), x);
+
+        let mut buf = vec![];
+        let len = encode_varintu32(&mut buf, u32::MAX);
+        assert_eq!(len, 5);
+        assert_eq!(decode_varintu32(&buf[..]).unwrap(), u32::MAX);
+    }
+
+    #[test]
+    fn codec_u32_malformed() {
+        // more continued bytes than 32 bits need
+        assert!(decode_varintu32(&[0xff; 6]).is_err());
+        assert!(decode_varintu32(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x01]).is_err());
+        // ends before its last byte
+        assert!(decode_varintu32(&[0x80, 0x80]).is_err());
+        assert!(decode_varintu32(&[]).is_err());
+    }
+
+    #[test]
+    fn codec_u64() {
+        for (x, expected_len) in [
+            (0_u64, 1),
+            (127, 1),
+            (1 << 7, 2),
+            (u32::MAX as u64, 5),
+            (1 << 32, 5),
+            (1 << 35, 6),
+            (u64::MAX, 10),
+        ] {
+            let mut buf = vec![];
+            let len = encode_varintu64(&mut buf, x);
+            assert_eq!(len, expected_len);
+            assert_eq!(varintu64_length(x), expected_len);
+            assert_eq!(decode_varintu64(&buf).unwrap(), x);
+
+            buf.push(0xab);
+            let mut rest = &buf[..];
+            assert_eq!(take_varintu64(&mut rest).unwrap(), x);
+            assert_eq!(rest, [0xab]);
+        }
+
+        // cut short, or running past 64 bits
+        assert!(decode_varintu64(&[0x80, 0x80]).is_err());
+        assert!(decode_varintu64(&[0xff; 11]).is_err());
     }
 }
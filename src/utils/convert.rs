@@ -2,6 +2,7 @@ pub fn u32vec_to_bytes(data: &[u32]) -> Vec<u8> {
     data.iter().flat_map(|val| val.to_be_bytes()).collect()
 }
 
+#[allow(dead_code)]
 pub fn u64_to_bytes(data: u64) -> Vec<u8> {
     data.to_be_bytes().to_vec()
 }
@@ -1,4 +1,11 @@
-use bytes::Bytes;
+use std::{
+    cmp::Ordering,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::{Comparator, Error, Result};
 
 pub mod bloom;
 pub mod codec;
@@ -6,6 +13,17 @@ pub mod convert;
 
 pub const OP_TYPE_DELETE: u8 = 0;
 pub const OP_TYPE_PUT: u8 = 1;
+/// A range tombstone: the key is the start of the range, the value its end.
+pub const OP_TYPE_RANGE_DELETE: u8 = 2;
+/// A merge operand, combined with the older entries of its key by the
+/// [`MergeOperator`](crate::MergeOperator) when read.
+pub const OP_TYPE_MERGE: u8 = 3;
+/// A put that expires: its value starts with the deadline, see
+/// [`encode_ttl_value`].
+pub const OP_TYPE_PUT_TTL: u8 = 4;
+
+/// Sequence numbers take the upper 56 bits of an internal key tag.
+pub const MAX_SEQUENCE: u64 = (1 << 56) - 1;
 
 #[derive(Clone, Debug)]
 pub struct Entry {
@@ -33,7 +51,74 @@ impl Entry {
 }
 
 pub trait FilterPolicy: Send + Sync {
+    /// Names the policy in the properties of a table.
+    fn name(&self) -> &str;
+
     fn may_contain(&self, filter: &[u8], key: &[u8]) -> bool;
 
     fn create_filter(&self, keys: &[Vec<u8>]) -> Vec<u8>;
 }
+
+// +------------------------------+
+// |  user_key | seq << 8 | type  |
+// +------------------------------+
+pub fn build_internal_key(user_key: &[u8], seq: u64, typ: u8) -> Vec<u8> {
+    let mut internal_key = Vec::with_capacity(user_key.len() + 8);
+    internal_key.put_slice(user_key);
+    internal_key.put_u64((seq << 8) | typ as u64);
+    internal_key
+}
+
+/// Split an internal key into user key, sequence number and operation type.
+pub fn parse_internal_key(internal_key: &[u8]) -> (&[u8], u64, u8) {
+    let len = internal_key.len();
+    let tag = (&internal_key[len - 8..]).get_u64();
+    (&internal_key[..len - 8], tag >> 8, tag as u8)
+}
+
+/// Internal keys are ordered by user key ascending as `ucmp` orders them, then
+/// by sequence descending.
+pub fn compare_internal_key(ucmp: &dyn Comparator, a: &[u8], b: &[u8]) -> Ordering {
+    let (user_key1, seq1, _) = parse_internal_key(a);
+    let (user_key2, seq2, _) = parse_internal_key(b);
+    match ucmp.compare(user_key1, user_key2) {
+        Ordering::Equal => seq2.cmp(&seq1),
+        other => other,
+    }
+}
+
+/// Milliseconds since the unix epoch, the clock deadlines are kept in.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// +-----------------------+
+// |  expire_at  |  value  |
+// +-----------------------+
+pub fn encode_ttl_value(expire_at: u64, value: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(value.len() + 8);
+    buf.put_u64(expire_at);
+    buf.put_slice(value);
+    buf
+}
+
+/// The value of an entry of type `typ` as read at `now`, none once it has
+/// expired. Only puts with a deadline carry one to strip.
+pub fn live_value(typ: u8, value: Bytes, now: u64) -> Result<Option<Bytes>> {
+    if typ != OP_TYPE_PUT_TTL {
+        return Ok(Some(value));
+    }
+    let (expire_at, value) = decode_ttl_value(&value)?;
+    Ok((now < expire_at).then_some(value))
+}
+
+/// Split the value of a put that expires into its deadline and value.
+pub fn decode_ttl_value(value: &Bytes) -> Result<(u64, Bytes)> {
+    if value.len() < 8 {
+        let reason = format!("expiring value of {} bytes", value.len());
+        return Err(Error::corruption("ttl value", 0, reason));
+    }
+    Ok(((&value[..8]).get_u64(), value.slice(8..)))
+}
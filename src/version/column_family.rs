@@ -0,0 +1,119 @@
+use std::{
+    collections::LinkedList,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::RwLock;
+
+use crate::Options;
+
+use super::Version;
+
+/// Name of the family every storage has, written by the plain `put`,
+/// `delete` and friends.
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+/// A handle to a column family, returned by
+/// [`Lsm::create_column_family`](crate::lsm::Lsm::create_column_family).
+///
+/// Column families are key spaces of one storage, each with its own
+/// mem-tables, tables and options. They share the wal, the manifest and the
+/// sequence numbers, so a [`WriteBatch`](crate::write_batch::WriteBatch)
+/// spanning several of them commits atomically and a snapshot covers them
+/// all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnFamily {
+    id: u32,
+    name: String,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The name of a column family and the options to open it with, given to
+/// [`Lsm::open_cf`](crate::lsm::Lsm::open_cf).
+#[derive(Clone)]
+pub struct ColumnFamilyDescriptor {
+    pub name: String,
+    pub options: Options,
+}
+
+impl ColumnFamilyDescriptor {
+    pub fn new(name: &str, options: Options) -> Self {
+        Self {
+            name: name.to_string(),
+            options,
+        }
+    }
+}
+
+/// The tables of a column family, as the versions readers and compactions
+/// still hold, and the options they are built with.
+pub struct ColumnFamilyData {
+    pub id: u32,
+    pub name: String,
+    // options are not persisted, every family is given its own again when
+    // the storage is opened
+    opt: RwLock<Arc<Options>>,
+    pub(super) versions: RwLock<LinkedList<Arc<Version>>>,
+    // wals at or below it are flushed to tables of the family
+    log_number: AtomicU64,
+}
+
+impl ColumnFamilyData {
+    pub(super) fn new(id: u32, name: &str, opt: Options, version: Version) -> Self {
+        let mut versions = LinkedList::new();
+        versions.push_back(Arc::new(version));
+        Self {
+            id,
+            name: name.to_string(),
+            opt: RwLock::new(Arc::new(opt)),
+            versions: RwLock::new(versions),
+            log_number: AtomicU64::new(0),
+        }
+    }
+
+    pub fn handle(&self) -> ColumnFamily {
+        ColumnFamily::new(self.id, &self.name)
+    }
+
+    pub fn options(&self) -> Arc<Options> {
+        Arc::clone(&self.opt.read())
+    }
+
+    pub(super) fn set_options(&self, opt: Options) {
+        *self.opt.write() = Arc::new(opt);
+    }
+
+    /// The wals at or below this number hold no entry of the family that is
+    /// not in its tables.
+    pub fn log_number(&self) -> u64 {
+        self.log_number.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn advance_log_number(&self, log_number: u64) {
+        self.log_number.fetch_max(log_number, Ordering::SeqCst);
+    }
+
+    pub fn current(&self) -> Arc<Version> {
+        let versions = self.versions.read();
+        versions.back().unwrap().clone()
+    }
+}
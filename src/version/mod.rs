@@ -2,9 +2,13 @@ use std::sync::atomic::AtomicU32;
 
 use bytes::{Buf, BufMut, Bytes};
 
+use crate::utils::codec::{take_bytes, take_u32, take_u64, CodecError};
+
+mod column_family;
 mod version_edit;
 mod version_set;
 
+pub use column_family::*;
 pub use version_edit::*;
 pub use version_set::*;
 
@@ -16,6 +20,8 @@ pub struct FileMetaData {
     pub smallest: InternalKey, // Smallest internal key served by table
     pub largest: InternalKey,  // Largest internal key served by table
     pub vlogs: Vec<u64>,
+    // sequence of the newest entry or range tombstone in the table
+    pub largest_seq: u64,
     pub allow_seek: AtomicU32,
 }
 
@@ -27,6 +33,7 @@ impl Clone for FileMetaData {
             smallest: self.smallest.clone(),
             largest: self.largest.clone(),
             vlogs: self.vlogs.clone(),
+            largest_seq: self.largest_seq,
             allow_seek: AtomicU32::new(self.allow_seek.load(std::sync::atomic::Ordering::SeqCst)),
         }
     }
@@ -41,10 +48,12 @@ impl FileMetaData {
             smallest: InternalKey::new(Bytes::new()),
             largest: InternalKey::new(Bytes::new()),
             vlogs: Vec::new(),
+            largest_seq: 0,
             allow_seek: AtomicU32::new(0),
         }
     }
 
+    #[allow(dead_code)]
     pub fn with_range(number: u64, smallest: &[u8], largest: &[u8]) -> Self {
         Self {
             // refs: 0,
@@ -53,10 +62,12 @@ impl FileMetaData {
             smallest: InternalKey::new(Bytes::from(smallest.to_vec())),
             largest: InternalKey::new(Bytes::from(largest.to_vec())),
             vlogs: Vec::new(),
+            largest_seq: 0,
             allow_seek: AtomicU32::new(0),
         }
     }
 
+    #[allow(dead_code)]
     pub fn with_internal_range(number: u64, smallest: InternalKey, largest: InternalKey) -> Self {
         Self {
             // refs: 0,
@@ -65,10 +76,15 @@ impl FileMetaData {
             smallest,
             largest,
             vlogs: Vec::new(),
+            largest_seq: 0,
             allow_seek: AtomicU32::new(0),
         }
     }
 
+    pub fn set_largest_seq(&mut self, largest_seq: u64) {
+        self.largest_seq = largest_seq;
+    }
+
     pub fn set_file_size(&mut self, file_size: u64) {
         self.file_size = file_size;
     }
@@ -78,6 +94,7 @@ impl FileMetaData {
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
     }
 
+    #[allow(dead_code)]
     pub fn allow_seek(&self) -> u32 {
         self.allow_seek.load(std::sync::atomic::Ordering::SeqCst)
     }
@@ -87,10 +104,12 @@ impl FileMetaData {
             .store(0, std::sync::atomic::Ordering::SeqCst)
     }
 
+    #[allow(dead_code)]
     pub fn number(&self) -> u64 {
         self.number
     }
 
+    #[allow(dead_code)]
     pub fn smallest(&self) -> &InternalKey {
         &self.smallest
     }
@@ -99,6 +118,7 @@ impl FileMetaData {
         self.smallest = smallest;
     }
 
+    #[allow(dead_code)]
     pub fn largest(&self) -> &InternalKey {
         &self.largest
     }
@@ -119,75 +139,58 @@ impl FileMetaData {
         self.vlogs.iter().for_each(|fid| {
             buf.put_u64(*fid);
         });
+        buf.put_u64(self.largest_seq);
         buf
     }
-    pub fn decode(data: &[u8]) -> Self {
-        let number = (&data[..8]).get_u64();
-        let file_size = (&data[8..16]).get_u64();
-        let smallest_sz = (&data[16..20]).get_u32();
-        let smallest = data[20..20 + smallest_sz as usize].to_vec();
-        let largest_sz = (&data[20 + smallest_sz as usize..]).get_u32();
-        let mut off = 24 + smallest_sz as usize;
-        let largest = data[off..off + largest_sz as usize].to_vec();
-        off += largest_sz as usize;
-        let vlen = (&data[off..]).get_u32();
-        off += 4;
+    pub fn decode(mut data: &[u8]) -> Result<Self, CodecError> {
+        let number = take_u64(&mut data)?;
+        let file_size = take_u64(&mut data)?;
+        let smallest = FileMetaData::decode_key(&mut data)?;
+        let largest = FileMetaData::decode_key(&mut data)?;
+        let vlen = take_u32(&mut data)?;
         let mut vlogs = vec![];
         for _ in 0..vlen {
-            let fid = (&data[off..]).get_u64();
-            vlogs.push(fid);
-            off += 8;
+            vlogs.push(take_u64(&mut data)?);
         }
-
-        Self {
+        // unknown for tables logged before it was kept
+        let largest_seq = if data.is_empty() {
+            u64::MAX
+        } else {
+            take_u64(&mut data)?
+        };
+
+        Ok(Self {
             number,
             file_size,
-            smallest: InternalKey::new(Bytes::from(smallest)),
-            largest: InternalKey::new(Bytes::from(largest)),
+            smallest,
+            largest,
             vlogs,
+            largest_seq,
             allow_seek: AtomicU32::new(0),
+        })
+    }
+
+    fn decode_key(data: &mut &[u8]) -> Result<InternalKey, CodecError> {
+        let sz = take_u32(data)? as usize;
+        if sz < 8 {
+            return Err(CodecError::InvalidRecord(format!(
+                "internal key of {} bytes",
+                sz
+            )));
         }
+        let key = take_bytes(data, sz)?;
+        Ok(InternalKey::new(Bytes::copy_from_slice(key)))
     }
 }
 
+/// An internal key kept as the bound of a table. It is ordered by
+/// [`compare_internal_key`](crate::utils::compare_internal_key) with the
+/// comparator of the storage.
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct InternalKey {
     key: Bytes,
 }
 
-impl PartialOrd for InternalKey {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let k1 = self.user_key();
-        let k2 = other.user_key();
-        match k1.partial_cmp(k2) {
-            Some(ord) => match ord {
-                std::cmp::Ordering::Equal => {
-                    let seq1 = self.seq();
-                    let seq2 = other.seq();
-                    seq2.partial_cmp(&seq1)
-                }
-                other => Some(other),
-            },
-            None => None,
-        }
-    }
-}
-
-impl Ord for InternalKey {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        let k1 = self.user_key();
-        let k2 = other.user_key();
-        match k1.cmp(k2) {
-            std::cmp::Ordering::Equal => {
-                let seq1 = self.seq();
-                let seq2 = other.seq();
-                seq2.cmp(&seq1)
-            }
-            other => other,
-        }
-    }
-}
-
 impl From<Vec<u8>> for InternalKey {
     fn from(key: Vec<u8>) -> Self {
         Self {
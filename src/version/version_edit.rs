@@ -1,4 +1,6 @@
-use bytes::{Buf, BufMut};
+use bytes::BufMut;
+
+use crate::utils::codec::{take_bytes, take_u32, take_u64, CodecError};
 
 use super::FileMetaData;
 
@@ -29,22 +31,15 @@ impl TableMeta {
         buf.put(file_meta_data.as_slice());
     }
 
-    pub fn decode(data: &[u8]) -> Vec<Self> {
+    pub fn decode(mut data: &[u8]) -> Result<Vec<Self>, CodecError> {
         let mut res = vec![];
-        let mut off = 0;
-
-        while off + 8 < data.len() {
-            let level = (&data[off..off + 4]).get_u32();
-            let sz = (&data[off + 4..off + 8]).get_u32();
-            if off + 8 + sz as usize > data.len() {
-                break;
-            }
-            let file_meta = FileMetaData::decode(&data[off + 8..off + 8 + sz as usize]);
-            let meta = Self { file_meta, level };
-            res.push(meta);
-            off += 8 + sz as usize;
+        while !data.is_empty() {
+            let level = take_u32(&mut data)?;
+            let sz = take_u32(&mut data)?;
+            let file_meta = FileMetaData::decode(take_bytes(&mut data, sz as usize)?)?;
+            res.push(Self { file_meta, level });
         }
-        res
+        Ok(res)
     }
 }
 
@@ -54,8 +49,19 @@ pub struct VersionEdit {
     // next_file_number: u64,
     pub delete_files: Vec<TableMeta>,
     pub add_files: Vec<TableMeta>,
-    pub add_vlogs: Vec<u64>,
+    // vlogs no longer referenced by any table
     pub delete_vlogs: Vec<u64>,
+    // bytes of a vlog that became garbage, added to what it already has
+    pub discard_vlogs: Vec<(u64, u64)>,
+    // the family whose tables are added and deleted
+    pub column_family: u32,
+    // name of the family the edit creates
+    pub add_column_family: Option<String>,
+    // name of the comparator ordering the keys of the storage
+    pub comparator: Option<String>,
+    // wals at or below it are flushed to tables of the family
+    // `column_family`, 0 if the edit flushes none
+    pub cf_log_number: u64,
     pub log_number: u64,
     pub next_file_number: u64,
     pub last_seq_number: u64,
@@ -68,32 +74,82 @@ impl VersionEdit {
             // next_file_number: 0,
             delete_files: Vec::new(),
             add_files: Vec::new(),
-            add_vlogs: Vec::new(),
             delete_vlogs: Vec::new(),
+            discard_vlogs: Vec::new(),
+            column_family: 0,
+            add_column_family: None,
+            comparator: None,
+            cf_log_number: 0,
             log_number: 0,
             next_file_number: 0,
             last_seq_number: 0,
         }
     }
 
-    pub fn decode(data: &[u8]) -> Self {
-        let log_number = (&data[..8]).get_u64();
-        let next_file_number = (&data[8..16]).get_u64();
-        let last_seq_number = (&data[16..24]).get_u64();
-        let add_file_sz = (&data[24..28]).get_u32();
-        let add_files = TableMeta::decode(&data[28..28 + add_file_sz as usize]);
-        let _delete_file_sz = (&data[28 + add_file_sz as usize..]).get_u32();
-        let delete_files = TableMeta::decode(&data[32 + add_file_sz as usize..]);
+    pub fn decode(mut data: &[u8]) -> Result<Self, CodecError> {
+        let log_number = take_u64(&mut data)?;
+        let next_file_number = take_u64(&mut data)?;
+        let last_seq_number = take_u64(&mut data)?;
+        let add_file_sz = take_u32(&mut data)?;
+        let add_files = TableMeta::decode(take_bytes(&mut data, add_file_sz as usize)?)?;
+        let delete_file_sz = take_u32(&mut data)?;
+        let delete_files = TableMeta::decode(take_bytes(&mut data, delete_file_sz as usize)?)?;
+        // edits written before vlogs were tracked end here
+        let mut delete_vlogs = vec![];
+        let mut discard_vlogs = vec![];
+        if !data.is_empty() {
+            for _ in 0..take_u32(&mut data)? {
+                delete_vlogs.push(take_u64(&mut data)?);
+            }
+            for _ in 0..take_u32(&mut data)? {
+                discard_vlogs.push((take_u64(&mut data)?, take_u64(&mut data)?));
+            }
+        }
+        // and those written before column families here, all of the default
+        let mut column_family = 0;
+        let mut add_column_family = None;
+        if !data.is_empty() {
+            column_family = take_u32(&mut data)?;
+            let name_sz = take_u32(&mut data)?;
+            if name_sz > 0 {
+                let name = take_bytes(&mut data, name_sz as usize)?;
+                let name = String::from_utf8(name.to_vec()).map_err(|_| {
+                    CodecError::InvalidRecord("column family name is not utf-8".to_string())
+                })?;
+                add_column_family = Some(name);
+            }
+        }
+        // edits written before family log numbers go on only if they name
+        // the comparator
+        let mut comparator = None;
+        if !data.is_empty() {
+            let name_sz = take_u32(&mut data)?;
+            if name_sz > 0 {
+                let name = take_bytes(&mut data, name_sz as usize)?;
+                let name = String::from_utf8(name.to_vec()).map_err(|_| {
+                    CodecError::InvalidRecord("comparator name is not utf-8".to_string())
+                })?;
+                comparator = Some(name);
+            }
+        }
+        let mut cf_log_number = 0;
+        if !data.is_empty() {
+            cf_log_number = take_u64(&mut data)?;
+        }
 
-        Self {
+        Ok(Self {
             delete_files,
             add_files,
             log_number,
             next_file_number,
             last_seq_number,
-            add_vlogs: Vec::new(),
-            delete_vlogs: Vec::new(),
-        }
+            delete_vlogs,
+            discard_vlogs,
+            column_family,
+            add_column_family,
+            comparator,
+            cf_log_number,
+        })
     }
 
     pub fn encode(&self, buf: &mut Vec<u8>) {
@@ -114,12 +170,36 @@ impl VersionEdit {
             .for_each(|f| f.encode(&mut delete_file_buf));
         buf.put_u32(delete_file_buf.len() as u32);
         buf.put_slice(&delete_file_buf);
+        // vlogs
+        buf.put_u32(self.delete_vlogs.len() as u32);
+        self.delete_vlogs.iter().for_each(|fid| buf.put_u64(*fid));
+        buf.put_u32(self.discard_vlogs.len() as u32);
+        self.discard_vlogs.iter().for_each(|(fid, bytes)| {
+            buf.put_u64(*fid);
+            buf.put_u64(*bytes);
+        });
+        // column family
+        buf.put_u32(self.column_family);
+        let name = self.add_column_family.as_deref().unwrap_or_default();
+        buf.put_u32(name.len() as u32);
+        buf.put_slice(name.as_bytes());
+        // comparator, empty if the edit names none
+        let name = self.comparator.as_deref().unwrap_or_default();
+        buf.put_u32(name.len() as u32);
+        buf.put_slice(name.as_bytes());
+        buf.put_u64(self.cf_log_number);
     }
 
     pub fn log_number(&mut self, number: u64) {
         self.log_number = number;
     }
 
+    /// Record that the wals up to `number` are flushed to the tables of the
+    /// family of the edit.
+    pub fn cf_log_number(&mut self, number: u64) {
+        self.cf_log_number = number;
+    }
+
     pub fn next_file_number(&mut self, next_file_number: u64) {
         self.next_file_number = next_file_number;
     }
@@ -138,6 +218,29 @@ impl VersionEdit {
         let table_meta = TableMeta::new(file_meta, level);
         self.delete_files.push(table_meta);
     }
+
+    pub fn column_family(&mut self, id: u32) {
+        self.column_family = id;
+    }
+
+    /// Create the column family `name` with id `id`.
+    pub fn add_column_family(&mut self, id: u32, name: &str) {
+        self.column_family = id;
+        self.add_column_family = Some(name.to_string());
+    }
+
+    /// Record `name` as the comparator of the storage.
+    pub fn comparator(&mut self, name: &str) {
+        self.comparator = Some(name.to_string());
+    }
+
+    pub fn delete_vlog(&mut self, fid: u64) {
+        self.delete_vlogs.push(fid);
+    }
+
+    pub fn discard_vlog(&mut self, fid: u64, bytes: u64) {
+        self.discard_vlogs.push((fid, bytes));
+    }
 }
 
 // #[cfg(test)]
@@ -188,3 +291,112 @@ impl VersionEdit {
 //         edit.last_seq_number(last_seq_number);
 //     }
 // }
+
+#[cfg(test)]
+mod version_edit_test {
+    use crate::version::FileMetaData;
+
+    use super::VersionEdit;
+
+    // the comparator and family log number sections of an edit naming no
+    // comparator
+    const TAIL_SIZE: usize = 4 + 8;
+
+    #[test]
+    fn edit_vlogs_codec_test() {
+        let mut edit = VersionEdit::new();
+        let mut meta = FileMetaData::with_range(7, b"a\0\0\0\0\0\0\0\x01", b"z\0\0\0\0\0\0\0\x01");
+        meta.vlogs = vec![3, 5];
+        edit.add_file(1, meta);
+        edit.delete_vlog(2);
+        edit.discard_vlog(3, 120);
+        edit.discard_vlog(5, 40);
+        edit.log_number(4);
+
+        let mut buf = vec![];
+        edit.encode(&mut buf);
+        let decoded = VersionEdit::decode(&buf).unwrap();
+        assert_eq!(decoded.add_files[0].file_meta.vlogs, vec![3, 5]);
+        assert_eq!(decoded.delete_vlogs, vec![2]);
+        assert_eq!(decoded.discard_vlogs, vec![(3, 120), (5, 40)]);
+        assert_eq!(decoded.log_number, 4);
+
+        // an edit written without the vlog and column family sections
+        let legacy = &buf[..buf.len() - TAIL_SIZE - 8 - 4 - 8 - 4 - 2 * 16];
+        let decoded = VersionEdit::decode(legacy).unwrap();
+        assert!(decoded.delete_vlogs.is_empty() && decoded.discard_vlogs.is_empty());
+        assert_eq!(decoded.add_files.len(), 1);
+
+        // a truncated vlog section is an error rather than lost stats
+        assert!(VersionEdit::decode(&buf[..buf.len() - TAIL_SIZE - 8 - 1]).is_err());
+    }
+
+    #[test]
+    fn edit_column_family_codec_test() {
+        let mut edit = VersionEdit::new();
+        edit.add_column_family(3, "users");
+        let mut buf = vec![];
+        edit.encode(&mut buf);
+        let decoded = VersionEdit::decode(&buf).unwrap();
+        assert_eq!(decoded.column_family, 3);
+        assert_eq!(decoded.add_column_family.as_deref(), Some("users"));
+
+        let mut edit = VersionEdit::new();
+        edit.column_family(3);
+        edit.add_file(
+            0,
+            FileMetaData::with_range(9, b"a\0\0\0\0\0\0\0\x01", b"z\0\0\0\0\0\0\0\x01"),
+        );
+        let mut buf = vec![];
+        edit.encode(&mut buf);
+        let decoded = VersionEdit::decode(&buf).unwrap();
+        assert_eq!(decoded.column_family, 3);
+        assert_eq!(decoded.add_column_family, None);
+
+        // an edit written before column families belongs to the default one
+        let legacy = &buf[..buf.len() - TAIL_SIZE - 8];
+        let decoded = VersionEdit::decode(legacy).unwrap();
+        assert_eq!(decoded.column_family, 0);
+        assert_eq!(decoded.add_files[0].file_meta.number, 9);
+    }
+
+    #[test]
+    fn edit_comparator_codec_test() {
+        let mut edit = VersionEdit::new();
+        edit.comparator("test.ReverseComparator");
+        edit.log_number(4);
+        let mut buf = vec![];
+        edit.encode(&mut buf);
+        let decoded = VersionEdit::decode(&buf).unwrap();
+        assert_eq!(
+            decoded.comparator.as_deref(),
+            Some("test.ReverseComparator")
+        );
+        assert_eq!(decoded.log_number, 4);
+
+        // an edit without the section names no comparator
+        let name_sz = 4 + "test.ReverseComparator".len();
+        let decoded = VersionEdit::decode(&buf[..buf.len() - 8 - name_sz]).unwrap();
+        assert_eq!(decoded.comparator, None);
+        assert!(VersionEdit::decode(&buf[..buf.len() - 8 - 1]).is_err());
+    }
+
+    #[test]
+    fn edit_cf_log_number_codec_test() {
+        let mut edit = VersionEdit::new();
+        edit.column_family(3);
+        edit.cf_log_number(12);
+        edit.log_number(11);
+        let mut buf = vec![];
+        edit.encode(&mut buf);
+        let decoded = VersionEdit::decode(&buf).unwrap();
+        assert_eq!((decoded.column_family, decoded.cf_log_number), (3, 12));
+        assert_eq!(decoded.log_number, 11);
+        assert_eq!(decoded.comparator, None);
+
+        // an edit written before family log numbers flushes none
+        let decoded = VersionEdit::decode(&buf[..buf.len() - TAIL_SIZE]).unwrap();
+        assert_eq!((decoded.column_family, decoded.cf_log_number), (3, 0));
+        assert!(VersionEdit::decode(&buf[..buf.len() - 1]).is_err());
+    }
+}
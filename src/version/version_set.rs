@@ -1,6 +1,6 @@
 use std::{
-    collections::{HashMap, HashSet, LinkedList},
-    io::ErrorKind,
+    cmp::Ordering as CmpOrdering,
+    collections::{BTreeMap, HashMap, HashSet, LinkedList},
     // io::Error,
     path::Path,
     sync::{
@@ -9,63 +9,91 @@ use std::{
     },
 };
 
-use anyhow::Ok;
-use bytes::{Buf, BufMut};
+use bytes::{BufMut, Bytes};
 use parking_lot::RwLock;
 use tracing::info;
 
 use crate::{
-    cache::Cache,
+    cache::{Cache, CacheStats},
     // cache::lru::LRUCache,
     compactor::{CompactionState, GCState, SeekTask, Task},
     file::{
-        path_of_file, Ext, RandomAccessFileImpl, SequentialFileImpl, Writable, WritableFileImpl,
-        Writer, {RandomReader, Reader},
+        path_of_file, Ext, RandomAccessFileImpl, Reader, SequentialFileImpl, Writable,
+        WritableFileImpl, Writer,
     },
+    iterator::InternalIterator,
+    merge::{self, MergeOperator},
+    range_del::{RangeTombstone, RangeTombstones},
+    snapshot::{Snapshot, SnapshotList},
     sstable::{
-        Block, MergeIterator, TableBuilder, {Table, TableIterator},
+        decode_value_pointer, BlockCache, MergeIterator, TableBuilder, TableProperties,
+        {Table, TableIterator},
     },
-    utils::{Entry, OP_TYPE_PUT},
+    utils::{
+        build_internal_key, compare_internal_key, decode_ttl_value, encode_ttl_value, live_value,
+        now_millis, parse_internal_key, Entry, OP_TYPE_DELETE, OP_TYPE_MERGE, OP_TYPE_PUT,
+        OP_TYPE_PUT_TTL,
+    },
+    vlog::{VlogCache, VlogEntry, VlogIterator, VlogWriter},
+    BytewiseComparator,
+    CompactionDecision,
+    Comparator,
+    Error,
     Options,
+    Result,
 };
 
-use super::{version_edit::VersionEdit, FileMetaData, InternalKey};
-
-// type Result<T> = core::result::Result<T, dyn Error>;
-type Result<T> = anyhow::Result<T, anyhow::Error>;
+use super::{version_edit::VersionEdit, ColumnFamilyData, FileMetaData, DEFAULT_COLUMN_FAMILY};
 
 const L0_COMPACTION_TRIGGER: u32 = 4;
 const L1_COMPACTION_TRIGGER: f64 = 1048576.0;
 const MAX_MEM_COMPACT_LEVEL: u32 = 0x2;
 // const L1_COMPACTION_TRIGGER: f64 = 100.0;
 
+/// The sequence, operation type and value of an entry found by a point read.
+pub type FoundEntry = (u64, u8, Bytes);
+
 pub struct Version {
+    // the column family the tables belong to
+    cf: u32,
+    // orders the user keys of the tables
+    ucmp: Arc<dyn Comparator>,
     files: Vec<Vec<FileMetaData>>,
     refs: AtomicU32,
-    smallest_sequence: u64,
-    smallest_log_number: u64,
+    // bytes of garbage in each vlog, left by compactions dropping the
+    // entries that pointed there
+    vlog_discards: HashMap<u64, u64>,
     table_cache: Arc<Cache<u64, Table>>,
-    #[allow(unused)]
-    index_cache: Arc<Cache<u64, Block>>,
+    block_cache: Arc<BlockCache>,
+    vlog_cache: Arc<VlogCache>,
 }
 
 impl Version {
-    pub fn new(table_cache: Arc<Cache<u64, Table>>, block_cache: Arc<Cache<u64, Block>>) -> Self {
+    pub fn new(
+        cf: u32,
+        ucmp: Arc<dyn Comparator>,
+        table_cache: Arc<Cache<u64, Table>>,
+        block_cache: Arc<BlockCache>,
+        vlog_cache: Arc<VlogCache>,
+    ) -> Self {
         let mut files: Vec<Vec<FileMetaData>> = Vec::new();
         files.resize_with(7, std::vec::Vec::new);
         Self {
+            cf,
+            ucmp,
             files,
             refs: AtomicU32::new(1),
-            smallest_sequence: 0,
-            smallest_log_number: 0,
+            vlog_discards: HashMap::new(),
             table_cache,
-            index_cache: block_cache,
+            block_cache,
+            vlog_cache,
         }
     }
 
     pub fn build(
         table_cache: Arc<Cache<u64, Table>>,
-        block_cache: Arc<Cache<u64, Block>>,
+        block_cache: Arc<BlockCache>,
+        vlog_cache: Arc<VlogCache>,
         version: Arc<Version>,
         edit: &VersionEdit,
     ) -> Self {
@@ -88,13 +116,23 @@ impl Version {
             }
         }
 
+        let mut vlog_discards = version.vlog_discards.clone();
+        for (fid, bytes) in edit.discard_vlogs.iter() {
+            *vlog_discards.entry(*fid).or_default() += bytes;
+        }
+        for fid in edit.delete_vlogs.iter() {
+            vlog_discards.remove(fid);
+        }
+
         Self {
+            cf: version.cf,
+            ucmp: Arc::clone(&version.ucmp),
             files,
             refs: AtomicU32::new(1),
-            smallest_sequence: edit.last_seq_number,
-            smallest_log_number: edit.log_number,
+            vlog_discards,
             table_cache,
-            index_cache: block_cache,
+            block_cache,
+            vlog_cache,
         }
     }
 
@@ -110,17 +148,21 @@ impl Version {
         self.refs.fetch_sub(1, Ordering::SeqCst);
     }
 
-    pub fn smallest_sequence(&self) -> u64 {
-        self.smallest_sequence
+    pub fn vlog_cache(&self) -> &VlogCache {
+        &self.vlog_cache
     }
-    pub fn smallest_log_number(&self) -> u64 {
-        self.smallest_log_number
+
+    /// Bytes of vlog `fid` known to be garbage.
+    pub fn vlog_discard(&self, fid: u64) -> u64 {
+        self.vlog_discards.get(&fid).copied().unwrap_or(0)
     }
 
+    #[allow(dead_code)]
     pub fn files(&self) -> &Vec<Vec<FileMetaData>> {
         &self.files
     }
 
+    #[allow(dead_code)]
     pub fn level_files(&self, level: usize) -> &Vec<FileMetaData> {
         &self.files[level]
     }
@@ -140,64 +182,68 @@ impl Version {
         internal_key
     }
 
-    pub fn get(&self, opt: Options, user_key: &[u8], seq: u64) -> (Option<Vec<u8>>, Option<Task>) {
+    /// The newest entry of `user_key` at or before `seq`, with its sequence
+    /// and operation type. A range tombstone covering the key is returned as
+    /// a deletion at the sequence of the tombstone.
+    pub fn get(
+        &self,
+        opt: &Options,
+        user_key: &[u8],
+        seq: u64,
+    ) -> Result<(Option<FoundEntry>, Option<Task>)> {
         // search L0 first
         let mut tmp = Vec::new();
         let internal_key = Version::build_internal_key(user_key, seq);
         let mut task = (self.files[0].len() > 5).then_some(Task::Major);
+        // sequence of the newest tombstone over the key in the tables searched
+        let mut range_del_seq = 0;
         for (i, files) in self.files.iter().enumerate() {
             if i == 0 {
                 files
                     .iter()
-                    .filter(|f| {
-                        f.smallest.user_key() <= user_key && f.largest.user_key() >= user_key
-                    })
+                    .filter(|f| self.overlaps(f, user_key, user_key))
                     .for_each(|f| tmp.push(f));
 
                 if !tmp.is_empty() {
-                    // tmp.sort_by(|a, b| a.number.partial_cmp(&b.number).unwrap());
-                    tmp.sort_by(|a, b| a.number.cmp(&b.number));
+                    // files in L0 may overlap, search the newest first
+                    tmp.sort_by_key(|f| std::cmp::Reverse(f.number));
                     for f in tmp.iter() {
                         // let path = path_of_file(&opt.work_dir, f.number, Ext::SST);
                         // let entry = self.search_sst(&path, &internal_key.clone());
-                        let entry = self.search_sst(&opt, f.number, &internal_key.clone());
+                        let (entry, del_seq) = self.search_sst(opt, f.number, &internal_key)?;
+                        range_del_seq = range_del_seq.max(del_seq);
                         if entry.is_none() {
                             let seek = f.increase_seek();
                             if seek >= 100 && task.is_none() {
                                 f.allow_seek_reset();
                                 task = Some(Task::Seek(SeekTask {
+                                    cf: self.cf,
                                     level: 0,
                                     fid: f.number,
                                 }))
                             }
                             continue;
                         }
-                        return (
-                            entry.map(|e| {
-                                let value = e.value();
-                                // let value_sz = decode_varintu32(value).unwrap();
-                                // Bytes::from(value[varintu32_length(value_sz) as usize..].to_vec())
-                                value.to_vec()
-                            }),
+                        return Ok((
+                            entry.map(|e| Version::visible_entry(e, range_del_seq)),
                             task,
-                        );
+                        ));
                     }
                 }
             } else {
                 // search other levels
-                let f = files.iter().find(|f| {
-                    f.smallest.user_key() <= user_key && f.largest.user_key() >= user_key
-                });
+                let f = files.iter().find(|f| self.overlaps(f, user_key, user_key));
                 if let Some(f) = f {
-                    let entry = self.search_sst(&opt, f.number, &internal_key.clone());
+                    let (entry, del_seq) = self.search_sst(opt, f.number, &internal_key)?;
+                    range_del_seq = range_del_seq.max(del_seq);
                     if let Some(e) = entry {
-                        // return (Some(e.value), task);
-                        return (Some(e.value.to_vec()), task);
+                        return Ok((Some(Version::visible_entry(e, range_del_seq)), task));
                     } else {
                         let seek = f.increase_seek();
                         if seek >= 100 && task.is_none() {
                             f.allow_seek_reset();
                             task = Some(Task::Seek(SeekTask {
+                                cf: self.cf,
                                 level: i as u32,
                                 fid: f.number,
                             }))
@@ -206,23 +252,169 @@ impl Version {
                 }
             }
         }
-        (None, task)
+        let deleted = (range_del_seq > 0).then(|| (range_del_seq, OP_TYPE_DELETE, Bytes::new()));
+        Ok((deleted, task))
     }
 
-    fn search_sst(&self, opt: &Options, fid: u64, internal_key: &[u8]) -> Option<Entry> {
-        let res = match self.table_cache.get(&fid) {
-            Some(t) => t.internal_get(opt, internal_key),
-            None => {
-                let path = path_of_file(&opt.work_dir, fid, Ext::SST);
-                let t = Table::new(Box::new(RandomAccessFileImpl::open(path.as_path()))).unwrap();
-                let res = t.internal_get(opt, internal_key);
-                let _e = self.table_cache.insert(fid, t, 1);
-                res
+    /// [`get`](Self::get) for each of `user_keys`, ascending and distinct, at
+    /// one sequence. The keys falling in a table are searched together.
+    pub fn multi_get(
+        &self,
+        opt: &Options,
+        user_keys: &[&[u8]],
+        seq: u64,
+    ) -> Result<(Vec<Option<FoundEntry>>, Option<Task>)> {
+        let internal_keys: Vec<Vec<u8>> = user_keys
+            .iter()
+            .map(|key| Version::build_internal_key(key, seq))
+            .collect();
+        let mut found = vec![None; user_keys.len()];
+        let mut task = (self.files[0].len() > 5).then_some(Task::Major);
+        // sequence of the newest tombstone over each key in the tables searched
+        let mut range_del_seqs = vec![0; user_keys.len()];
+        let mut pending: Vec<usize> = (0..user_keys.len()).collect();
+        for (level, files) in self.files.iter().enumerate() {
+            if pending.is_empty() {
+                break;
             }
-        };
-        let _ = self.table_cache.unpin(&fid);
+            let mut files: Vec<&FileMetaData> = files.iter().collect();
+            if level == 0 {
+                // files in L0 may overlap, search the newest first
+                files.sort_by_key(|f| std::cmp::Reverse(f.number));
+            }
+            for f in files {
+                let batch: Vec<usize> = pending
+                    .iter()
+                    .copied()
+                    .filter(|i| self.overlaps(f, user_keys[*i], user_keys[*i]))
+                    .collect();
+                if batch.is_empty() {
+                    continue;
+                }
+                let t = self.find_table(opt, f.number)?;
+                let keys: Vec<&[u8]> = batch.iter().map(|i| &internal_keys[*i][..]).collect();
+                let entries = t.multi_internal_get(&self.vlog_cache, &keys)?;
+                for (i, entry) in batch.into_iter().zip(entries) {
+                    let del_seq = self.range_del_seq(&t, user_keys[i], seq);
+                    range_del_seqs[i] = range_del_seqs[i].max(del_seq);
+                    match entry {
+                        Some(e) => found[i] = Some(Version::visible_entry(e, range_del_seqs[i])),
+                        None => {
+                            let seek = f.increase_seek();
+                            if seek >= 100 && task.is_none() {
+                                f.allow_seek_reset();
+                                task = Some(Task::Seek(SeekTask {
+                                    cf: self.cf,
+                                    level: level as u32,
+                                    fid: f.number,
+                                }))
+                            }
+                        }
+                    }
+                }
+                pending.retain(|i| found[*i].is_none());
+            }
+        }
+        for i in pending {
+            if range_del_seqs[i] > 0 {
+                found[i] = Some((range_del_seqs[i], OP_TYPE_DELETE, Bytes::new()));
+            }
+        }
+        Ok((found, task))
+    }
 
-        res
+    // an entry found by `search_sst`, a deletion if a tombstone at
+    // `range_del_seq` covers it
+    fn visible_entry(entry: Entry, range_del_seq: u64) -> FoundEntry {
+        let (_, seq, typ) = parse_internal_key(&entry.key);
+        if seq < range_del_seq {
+            (range_del_seq, OP_TYPE_DELETE, Bytes::new())
+        } else {
+            (seq, typ, entry.value)
+        }
+    }
+
+    // The newest entry of the key in table `fid`, and the sequence of the
+    // newest tombstone of the table covering it.
+    fn search_sst(
+        &self,
+        opt: &Options,
+        fid: u64,
+        internal_key: &[u8],
+    ) -> Result<(Option<Entry>, u64)> {
+        let t = self.find_table(opt, fid)?;
+        let (user_key, seq, _) = parse_internal_key(internal_key);
+        Ok((
+            t.internal_get(&self.vlog_cache, internal_key)?,
+            self.range_del_seq(&t, user_key, seq),
+        ))
+    }
+
+    // the sequence of the newest tombstone of `t` at or before `seq` covering
+    // `user_key`, 0 if there is none
+    fn range_del_seq(&self, t: &Table, user_key: &[u8], seq: u64) -> u64 {
+        t.range_tombstones()
+            .iter()
+            .filter(|r| r.seq <= seq && r.contains(self.ucmp.as_ref(), user_key))
+            .map(|r| r.seq)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The range tombstones of every table of this version.
+    pub fn range_tombstones(&self, opt: &Options) -> Result<Vec<RangeTombstone>> {
+        let mut tombstones = vec![];
+        for f in self.files.iter().flatten() {
+            let t = self.find_table(opt, f.number)?;
+            tombstones.extend_from_slice(t.range_tombstones());
+        }
+        Ok(tombstones)
+    }
+
+    /// The properties of every table of this version by file number, leaving
+    /// out tables built before tables kept properties.
+    pub fn table_properties(&self, opt: &Options) -> Result<BTreeMap<u64, TableProperties>> {
+        let mut props = BTreeMap::new();
+        for f in self.files.iter().flatten() {
+            let t = self.find_table(opt, f.number)?;
+            if let Some(p) = t.properties() {
+                props.insert(f.number, p.clone());
+            }
+        }
+        Ok(props)
+    }
+
+    fn find_table(&self, opt: &Options, fid: u64) -> Result<Arc<Table>> {
+        if let Some(t) = self.table_cache.get(&fid) {
+            self.table_cache.unpin(&fid)?;
+            return Ok(t);
+        }
+        let t = Version::open_table(opt, fid, &self.block_cache)?;
+        match self.table_cache.insert(fid, t, 1) {
+            Ok(t) => {
+                self.table_cache.unpin(&fid)?;
+                Ok(t)
+            }
+            // inserted by another reader, or no room in cache
+            Err(_) => Ok(Arc::new(Version::open_table(opt, fid, &self.block_cache)?)),
+        }
+    }
+
+    fn open_table(opt: &Options, fid: u64, block_cache: &Arc<BlockCache>) -> Result<Table> {
+        let path = path_of_file(&opt.work_dir, fid, Ext::SST);
+        let t = Table::new(Box::new(RandomAccessFileImpl::open(path.as_path())?))?;
+        Ok(t.with_block_cache(fid, Arc::clone(block_cache))
+            .with_comparator(Arc::clone(&opt.comparator)))
+    }
+
+    /// Open an iterator over every table of this version.
+    pub fn new_table_iterators(&self, opt: &Options) -> Result<Vec<Box<dyn InternalIterator>>> {
+        let mut iters: Vec<Box<dyn InternalIterator>> = vec![];
+        for f in self.files.iter().flatten() {
+            let t = self.find_table(opt, f.number)?;
+            iters.push(Box::new(TableIterator::new(t)?));
+        }
+        Ok(iters)
     }
 
     pub fn pick_level_for_mem_table_output(&self, smallest: &[u8], largest: &[u8]) -> u32 {
@@ -288,12 +480,38 @@ impl Version {
         if self.files.len() <= level as usize {
             return false;
         }
-        let overlapping: Vec<_> = self.files[level as usize]
+        self.files[level as usize]
             .iter()
-            .filter(|f| !(f.smallest.user_key() > largest || f.largest.user_key() < smallest))
-            .collect();
+            .any(|f| self.overlaps(f, smallest, largest))
+    }
 
-        !overlapping.is_empty()
+    // Whether the user keys of `f` overlap `[smallest, largest]`.
+    fn overlaps(&self, f: &FileMetaData, smallest: &[u8], largest: &[u8]) -> bool {
+        self.ucmp.compare(f.smallest.user_key(), largest) != CmpOrdering::Greater
+            && self.ucmp.compare(f.largest.user_key(), smallest) != CmpOrdering::Less
+    }
+
+    /// Whether no level below `level` holds a file that may contain
+    /// `user_key`.
+    pub fn is_base_level_for_key(&self, level: usize, user_key: &[u8]) -> bool {
+        self.files
+            .iter()
+            .skip(level + 1)
+            .flatten()
+            .all(|f| !self.overlaps(f, user_key, user_key))
+    }
+
+    // Level 0 files by smallest internal key, then by largest.
+    fn sort_by_range(&self, files: &mut [FileMetaData]) {
+        let ucmp = self.ucmp.as_ref();
+        files.sort_by(|f1, f2| {
+            match compare_internal_key(ucmp, &f1.smallest.key(), &f2.smallest.key()) {
+                CmpOrdering::Equal => {
+                    compare_internal_key(ucmp, &f1.largest.key(), &f2.largest.key())
+                }
+                other => other,
+            }
+        });
     }
 
     fn overlaping_inputs(&self, level: u32, smallest: &[u8], largest: &[u8]) -> Vec<FileMetaData> {
@@ -303,7 +521,7 @@ impl Version {
         let mut inputs = vec![];
         self.files[level as usize]
             .iter()
-            .filter(|f| !(f.smallest.user_key() > largest || f.largest.user_key() < smallest))
+            .filter(|f| self.overlaps(f, smallest, largest))
             .for_each(|f| {
                 inputs.push(f.clone());
             });
@@ -313,14 +531,17 @@ impl Version {
 }
 
 pub struct VersionSet {
-    #[allow(dead_code)]
-    versions: Arc<RwLock<LinkedList<Arc<Version>>>>,
+    families: RwLock<BTreeMap<u32, Arc<ColumnFamilyData>>>,
+    // wals at or below it are flushed to tables
+    log_number: AtomicU64,
     last_sequence: AtomicU64,
     next_file_number: AtomicU64,
+    snapshots: Arc<SnapshotList>,
     #[allow(dead_code)]
     log_file: Writer,
     table_cache: Arc<Cache<u64, Table>>,
-    index_cache: Arc<Cache<u64, Block>>,
+    block_cache: Arc<BlockCache>,
+    vlog_cache: Arc<VlogCache>,
     opt: Options,
 }
 
@@ -336,39 +557,96 @@ struct VersionSetInner {
 }
 
 impl VersionSet {
-    pub fn new(opt: Options) -> Self {
+    pub fn new(opt: Options) -> Result<Self> {
         let table_cache = Arc::new(Cache::with_capacity(1000));
-        let index_cache = Arc::new(Cache::with_capacity(opt.cache_size));
-        let versions = LinkedList::new();
-        // versions.push_back(Arc::new(Version::new(table_cache.clone())));
-        Self {
-            versions: Arc::new(RwLock::new(versions)),
+        let block_cache = Arc::new(Cache::with_capacity(opt.cache_size));
+        let vlog_cache = Arc::new(VlogCache::new(&opt));
+        Ok(Self {
+            // filled by `recover`
+            families: RwLock::new(BTreeMap::new()),
+            log_number: AtomicU64::new(0),
             next_file_number: AtomicU64::new(0),
             last_sequence: AtomicU64::new(0),
+            snapshots: Arc::new(SnapshotList::default()),
             log_file: Writer::new(WritableFileImpl::new(&path_of_file(
                 &opt.work_dir,
                 0,
                 Ext::MANIFEST,
-            ))),
+            ))?),
             table_cache,
-            index_cache,
+            block_cache,
+            vlog_cache,
             opt,
+        })
+    }
+
+    pub fn block_cache_stats(&self) -> CacheStats {
+        self.block_cache.stats()
+    }
+
+    pub fn value_cache_stats(&self) -> CacheStats {
+        self.vlog_cache.value_cache_stats()
+    }
+
+    pub fn column_family(&self, id: u32) -> Result<Arc<ColumnFamilyData>> {
+        self.families
+            .read()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| Error::InvalidArgument(format!("unknown column family {}", id)))
+    }
+
+    pub fn column_families(&self) -> Vec<Arc<ColumnFamilyData>> {
+        self.families.read().values().cloned().collect()
+    }
+
+    /// Create the column family `name` with `opt`, or hand an existing one
+    /// `opt`.
+    pub fn create_column_family(&self, name: &str, opt: Options) -> Result<Arc<ColumnFamilyData>> {
+        let mut families = self.families.write();
+        if let Some(cf) = families.values().find(|cf| cf.name == name) {
+            cf.set_options(opt);
+            return Ok(Arc::clone(cf));
         }
+        let id = families.keys().next_back().map_or(0, |id| id + 1);
+        let mut edit = VersionEdit::new();
+        edit.add_column_family(id, name);
+        edit.log_number(self.log_number());
+        self.log_edit(&mut edit)?;
+
+        let cf = Arc::new(ColumnFamilyData::new(id, name, opt, self.new_version(id)));
+        families.insert(id, Arc::clone(&cf));
+        info!("Create column family {} {:?}", id, name);
+        Ok(cf)
+    }
+
+    fn new_version(&self, cf: u32) -> Version {
+        Version::new(
+            cf,
+            Arc::clone(&self.opt.comparator),
+            Arc::clone(&self.table_cache),
+            Arc::clone(&self.block_cache),
+            Arc::clone(&self.vlog_cache),
+        )
     }
 
-    pub fn current(&self) -> Arc<Version> {
-        let versions = self.versions.read();
-        versions.back().unwrap().clone()
+    /// Take a snapshot of everything written so far.
+    pub fn new_snapshot(&self) -> Snapshot {
+        self.snapshots.acquire(|| self.last_sequence())
     }
 
+    /// The oldest sequence any reader may still see. Versions shadowed by a
+    /// newer one at or below it are invisible and can be dropped.
     pub fn smallest_sequence(&self) -> u64 {
-        let versions = self.versions.read();
-        versions.front().unwrap().smallest_sequence()
+        self.snapshots.oldest_or(|| self.last_sequence())
+    }
+
+    pub fn newest_snapshot(&self) -> Option<u64> {
+        self.snapshots.newest()
     }
 
-    pub fn smallest_log_number(&self) -> u64 {
-        let versions = self.versions.read();
-        versions.front().unwrap().smallest_log_number()
+    pub fn log_number(&self) -> u64 {
+        self.log_number.load(Ordering::SeqCst)
     }
 
     pub fn new_file_number(&self) -> u64 {
@@ -388,30 +666,43 @@ impl VersionSet {
         self.last_sequence.fetch_add(n, Ordering::SeqCst)
     }
 
+    pub fn set_last_sequence(&self, last_sequence: u64) {
+        self.last_sequence.store(last_sequence, Ordering::SeqCst)
+    }
+
+    /// Whether the tables of any column family need a compaction.
     pub fn need_compact(&self) -> bool {
-        self.current().pick_compact_level().is_some()
-        // if self.current().files[0].len() > 8 {
-        //     return true;
-        // }
-        // false
+        self.families
+            .read()
+            .values()
+            .any(|cf| cf.current().pick_compact_level().is_some())
     }
 
-    pub fn log_and_apply(&self, mut edit: VersionEdit) -> Result<()> {
-        // write manifest
+    fn log_edit(&self, edit: &mut VersionEdit) -> Result<()> {
         let mut data = vec![];
         edit.last_seq_number(self.last_sequence());
         edit.next_file_number(self.next_file_number.load(Ordering::SeqCst));
 
         edit.encode(&mut data);
         self.log_file.add_recore(&data)?;
+        Ok(())
+    }
+
+    pub fn log_and_apply(&self, mut edit: VersionEdit) -> Result<()> {
+        let cf = self.column_family(edit.column_family)?;
+        // write manifest
+        self.log_edit(&mut edit)?;
+        self.log_number.fetch_max(edit.log_number, Ordering::SeqCst);
+        cf.advance_log_number(edit.cf_log_number);
 
-        let mut versions = self.versions.write();
+        let mut versions = cf.versions.write();
 
         // modify memory metadata
         let base = versions.back().unwrap().clone();
         let current = Version::build(
             Arc::clone(&self.table_cache),
-            Arc::clone(&self.index_cache),
+            Arc::clone(&self.block_cache),
+            Arc::clone(&self.vlog_cache),
             base.clone(),
             &edit,
         );
@@ -428,16 +719,15 @@ impl VersionSet {
         }
         for table_meta in edit.add_files.iter() {
             let fid = table_meta.file_meta.number;
-            let path = path_of_file(&self.opt.work_dir, fid, Ext::SST);
-            let t = Table::new(Box::new(RandomAccessFileImpl::open(path.as_path())))?;
+            let t = Version::open_table(&self.opt, fid, &self.block_cache)?;
             self.table_cache.insert(fid, t, 1)?;
             self.table_cache.unpin(&fid)?;
         }
         Ok(())
     }
 
-    fn pick_compaction(&self) -> Option<CompactionState> {
-        let current = self.current();
+    fn pick_compaction(&self, cf: &ColumnFamilyData) -> Option<CompactionState> {
+        let current = cf.current();
         let mut base = vec![];
         let target;
 
@@ -445,18 +735,16 @@ impl VersionSet {
         let mut files = current.files[level].clone();
 
         if level == 0 {
-            files.sort_by(|f1, f2| match f1.smallest.cmp(&f2.smallest) {
-                std::cmp::Ordering::Equal => f1.largest.cmp(&f2.largest),
-                other => other,
-            });
+            current.sort_by_range(&mut files);
+            let ucmp = current.ucmp.as_ref();
             let (mut smallest, mut largest) =
                 (files[0].smallest.user_key(), files[0].largest.user_key());
             for f in files.iter() {
-                if !(f.smallest.user_key() > largest || f.largest.user_key() < smallest) {
-                    if f.smallest.user_key() < smallest {
+                if current.overlaps(f, smallest, largest) {
+                    if ucmp.compare(f.smallest.user_key(), smallest) == CmpOrdering::Less {
                         smallest = f.smallest.user_key();
                     }
-                    if f.largest.user_key() < largest {
+                    if ucmp.compare(f.largest.user_key(), largest) == CmpOrdering::Greater {
                         largest = f.largest.user_key();
                     }
                     base.push(f.clone());
@@ -477,31 +765,34 @@ impl VersionSet {
             target_level: level + 1,
             target,
             base,
+            discards: HashMap::new(),
         })
     }
 
-    fn pick_seek_compaction(&self, seek_task: &SeekTask) -> Option<CompactionState> {
+    fn pick_seek_compaction(
+        &self,
+        cf: &ColumnFamilyData,
+        seek_task: &SeekTask,
+    ) -> Option<CompactionState> {
         let level = seek_task.level as usize;
-        let current = self.current();
+        let current = cf.current();
         let mut base = vec![];
         let target;
         let mut files = current.files[level].clone();
 
         if level == 0 {
-            files.sort_by(|f1, f2| match f1.smallest.cmp(&f2.smallest) {
-                std::cmp::Ordering::Equal => f1.largest.cmp(&f2.largest),
-                other => other,
-            });
+            current.sort_by_range(&mut files);
+            let ucmp = current.ucmp.as_ref();
             let seek_f = files.iter().find(|f| f.number == seek_task.fid);
             if let Some(seek_f) = seek_f {
                 let (mut smallest, mut largest) =
                     (seek_f.smallest.user_key(), seek_f.largest.user_key());
                 for f in files.iter() {
-                    if !(f.smallest.user_key() > largest || f.largest.user_key() < smallest) {
-                        if f.smallest.user_key() < smallest {
+                    if current.overlaps(f, smallest, largest) {
+                        if ucmp.compare(f.smallest.user_key(), smallest) == CmpOrdering::Less {
                             smallest = f.smallest.user_key();
                         }
-                        if f.largest.user_key() < largest {
+                        if ucmp.compare(f.largest.user_key(), largest) == CmpOrdering::Greater {
                             largest = f.largest.user_key();
                         }
                         base.push(f.clone());
@@ -532,39 +823,44 @@ impl VersionSet {
             target_level: level + 1,
             target,
             base,
+            discards: HashMap::new(),
         })
     }
 
-    pub fn do_compaction(&self, meta: &mut FileMetaData) -> Result<Option<CompactionState>> {
-        if let Some(c) = self.pick_compaction() {
-            return self.do_compaction_inner(meta, c);
+    pub fn do_compaction(
+        &self,
+        cf: &ColumnFamilyData,
+        meta: &mut FileMetaData,
+    ) -> Result<Option<CompactionState>> {
+        if let Some(c) = self.pick_compaction(cf) {
+            return self.do_compaction_inner(cf, meta, c);
         }
         Ok(None)
     }
 
     pub fn do_seek_compaction(
         &self,
+        cf: &ColumnFamilyData,
         meta: &mut FileMetaData,
         seek_task: &SeekTask,
     ) -> Result<Option<CompactionState>> {
-        if let Some(c) = self.pick_seek_compaction(seek_task) {
+        if let Some(c) = self.pick_seek_compaction(cf, seek_task) {
             if c.base.len() + c.target.len() < 2 {
                 return Ok(None);
             }
-            return self.do_compaction_inner(meta, c);
+            return self.do_compaction_inner(cf, meta, c);
         }
         Ok(None)
     }
 
     pub fn do_compaction_inner(
         &self,
+        cf: &ColumnFamilyData,
         meta: &mut FileMetaData,
-        c: CompactionState,
+        mut c: CompactionState,
     ) -> Result<Option<CompactionState>> {
-        let skip =
-            |internal_key: InternalKey| -> bool { self.smallest_sequence() > internal_key.seq() };
-
-        let mut iters = vec![];
+        let opt = cf.options();
+        let mut tables = vec![];
         let mut files_iter = c.base.iter().chain(c.target.iter());
         let mut total_sz = 0;
         files_iter.try_for_each(|f| -> Result<()> {
@@ -572,81 +868,218 @@ impl VersionSet {
                 Some(t) => t,
                 None => {
                     let path = path_of_file(&self.opt.work_dir, f.number, Ext::SST);
-                    Arc::new(Table::new(Box::new(RandomAccessFileImpl::open(
-                        path.as_path(),
-                    )))?)
+                    let file = RandomAccessFileImpl::open(path.as_path())?;
+                    Arc::new(
+                        Table::new(Box::new(file))?.with_comparator(Arc::clone(&opt.comparator)),
+                    )
                 }
             };
             total_sz += t.size();
-            let iter = TableIterator::new(t)?;
-            iters.push(iter);
+            tables.push((f, t));
             self.table_cache.unpin(&f.number)?;
             Ok(())
         })?;
 
+        // an entry is dropped only if a newer entry of the same user key, or a
+        // range tombstone over it, is visible to every reader, i.e. to the
+        // oldest snapshot
+        let smallest_snapshot = self.smallest_sequence();
+        let mut range_dels = RangeTombstones::new(Arc::clone(&opt.comparator), smallest_snapshot);
+        // an expired put is dropped only where no older entry of its key may
+        // be left below to show through
+        let current = cf.current();
+        let now = now_millis();
+        let newest_snapshot = self.newest_snapshot();
+        tables
+            .iter()
+            .for_each(|(_, t)| range_dels.extend(t.range_tombstones()));
+        // a table whose entries are all deleted by a tombstone is not read
+        let mut iters: Vec<Box<dyn InternalIterator>> = vec![];
+        let mut kept_range_dels = vec![];
+        let mut dropped = HashSet::new();
+        for (f, t) in tables {
+            if range_dels.covers_range(f.smallest.user_key(), f.largest.user_key(), f.largest_seq) {
+                dropped.insert(f.number);
+                continue;
+            }
+            kept_range_dels.extend_from_slice(t.range_tombstones());
+            iters.push(Box::new(TableIterator::new(t)?));
+        }
+        if !dropped.is_empty() {
+            info!("drop {:?} covered by range tombstones", dropped);
+        }
+
         meta.number = self.new_file_number();
-        let merge_iter = MergeIterator::new(iters);
+        let mut merge_iter = MergeIterator::new(iters, Arc::clone(&opt.comparator));
         let path = path_of_file(&self.opt.work_dir, meta.number, Ext::SST);
-        let mut vlog_cache = HashMap::<u64, RandomReader>::new();
         let mut vlog = None;
 
         {
             let mut tb = TableBuilder::new(
-                self.opt.clone(),
-                Box::new(WritableFileImpl::new(path.as_path())),
+                opt.as_ref().clone(),
+                Box::new(WritableFileImpl::new(path.as_path())?),
                 meta.number,
-            );
-            let mut last_key = InternalKey::from(vec![]);
-            for e in merge_iter {
-                let key = InternalKey::new(e.key.clone());
-                if !(key == last_key && skip(InternalKey::new(e.key.clone()))) {
-                    last_key = key;
-                    // let mut value = e.value.clone();
-                    let mut value = e.value.to_vec();
-                    if c.base_level >= 1 && !value.is_empty() && value[0] == 1 {
-                        // do vlog merge on
-
-                        // read value in vlog
-                        assert!(value.len() >= 17); // tag(1) + fid(8) + offset(8)
-
-                        let fid = (&value[1..9]).get_u64();
-                        let offset = (&value[9..17]).get_u64();
-
-                        let log = vlog_cache.entry(fid).or_insert_with(|| {
-                            let path =
-                                path_of_file(&self.opt.work_dir, fid, crate::file::Ext::VLOG);
-                            RandomReader::new(Box::new(RandomAccessFileImpl::open(path.as_path())))
-                        });
-                        let ivalue = log.read_record(offset).unwrap();
-
-                        let vwriter = vlog.get_or_insert_with(|| {
-                            Writer::new(WritableFileImpl::new(&path_of_file(
-                                &self.opt.work_dir,
-                                meta.number,
-                                Ext::VLOG,
-                            )))
-                        });
-                        // construct value in sst
-                        let off = vwriter.offset();
-                        value.clear();
-                        value.put_u8(1);
-                        value.put_u64(meta.number);
-                        value.put_u64(off);
-
-                        vwriter.add_recore(&ivalue)?;
+            )
+            .with_level(c.target_level);
+            kept_range_dels
+                .into_iter()
+                .for_each(|t| tb.add_range_tombstone(t));
+            let merge_op = opt.merge_operator.as_deref();
+            let mut current_user_key: Option<Vec<u8>> = None;
+            // sequence of the newest value or deletion of the key, the entries
+            // below a merge operand stay
+            let mut last_sequence_for_key = u64::MAX;
+            // merge operands visible to every reader, newest first, combined
+            // once the entry ending them or the next key is met
+            let mut operands: Vec<(Bytes, Bytes)> = vec![];
+            for e in merge_iter.by_ref() {
+                let (user_key, seq, typ) = parse_internal_key(&e.key);
+                if current_user_key.as_deref() != Some(user_key) {
+                    if let Some(key) = current_user_key.as_deref() {
+                        Self::add_merge_operands(&mut tb, merge_op, key, &mut operands)?;
+                    }
+                    current_user_key = Some(user_key.to_vec());
+                    last_sequence_for_key = u64::MAX;
+                }
+                let hidden =
+                    last_sequence_for_key <= smallest_snapshot || range_dels.covers(user_key, seq);
+                let expired = !hidden
+                    && typ == OP_TYPE_PUT_TTL
+                    && seq <= smallest_snapshot
+                    && current.is_base_level_for_key(c.target_level, user_key)
+                    && live_value(typ, self.compaction_value(&e.value)?, now)?.is_none();
+                let merging = !hidden
+                    && typ == OP_TYPE_MERGE
+                    && seq <= smallest_snapshot
+                    && merge_op.is_some();
+                // a value, a deletion or a covered entry ends the operands. A
+                // put that expires keeps them apart, as their merged value
+                // would outlive it.
+                let consumed = !merging
+                    && !operands.is_empty()
+                    && (typ != OP_TYPE_PUT_TTL || hidden || expired);
+                if !merging && !consumed && !operands.is_empty() {
+                    Self::add_merge_operands(&mut tb, merge_op, user_key, &mut operands)?;
+                }
+                if consumed {
+                    let existing = if !hidden && typ == OP_TYPE_PUT {
+                        Some(self.compaction_value(&e.value)?)
+                    } else {
+                        None
+                    };
+                    let newest = parse_internal_key(&operands[0].0).1;
+                    let values: Vec<Bytes> = operands.drain(..).map(|(_, v)| v).collect();
+                    let value =
+                        merge::full_merge(merge_op, user_key, existing.as_deref(), &values)?;
+                    let key = build_internal_key(user_key, newest, OP_TYPE_PUT);
+                    tb.add(&key, &Self::inline_value(&value))?;
+                    last_sequence_for_key = newest;
+                } else if typ != OP_TYPE_MERGE {
+                    last_sequence_for_key = seq;
+                }
+                // the filter is only asked about values no snapshot sees, so
+                // none notices what it does
+                let decision = match opt.compaction_filter.as_deref() {
+                    Some(filter)
+                        if !hidden
+                            && !consumed
+                            && !expired
+                            && newest_snapshot.is_none_or(|s| seq > s)
+                            && (typ == OP_TYPE_PUT || typ == OP_TYPE_PUT_TTL) =>
+                    {
+                        let value = self.compaction_value(&e.value)?;
+                        match live_value(typ, value, now)? {
+                            Some(value) => filter.filter(c.target_level, user_key, &value),
+                            None => CompactionDecision::Keep,
+                        }
+                    }
+                    _ => CompactionDecision::Keep,
+                };
+                let filtered = decision != CompactionDecision::Keep;
+                if (hidden || merging || consumed || expired || filtered)
+                    && !e.value.is_empty()
+                    && e.value[0] == 1
+                {
+                    // the value it points to is garbage from now on
+                    let (fid, offset) = decode_value_pointer(&e.value)?;
+                    let size = self.vlog_cache.record_size(fid, offset)?;
+                    *c.discards.entry(fid).or_default() += size;
+                }
+                if merging {
+                    operands.push((e.key.clone(), self.compaction_value(&e.value)?));
+                } else if !hidden && !consumed && !expired {
+                    match decision {
+                        CompactionDecision::Keep => {
+                            // let mut value = e.value.clone();
+                            let mut value = e.value.to_vec();
+                            if c.base_level >= 1 && !value.is_empty() && value[0] == 1 {
+                                // do vlog merge on
+
+                                // read value in vlog
+                                let (fid, offset) = decode_value_pointer(&value)?;
+                                let entry = self.vlog_cache.read_entry(fid, offset)?;
+
+                                if vlog.is_none() {
+                                    vlog =
+                                        Some(VlogWriter::create(&self.opt.work_dir, meta.number)?);
+                                }
+                                let vwriter = vlog.as_ref().unwrap();
+                                // construct value in sst
+                                let off = vwriter.add(&entry)?;
+                                value.clear();
+                                value.put_u8(1);
+                                value.put_u64(meta.number);
+                                value.put_u64(off);
+                            }
+                            tb.add(&e.key, &value)?;
+                        }
+                        CompactionDecision::Remove => {
+                            // older entries of the key kept for a snapshot, or
+                            // below the output, would show through without a
+                            // deletion
+                            if seq > smallest_snapshot
+                                || !current.is_base_level_for_key(c.target_level, user_key)
+                            {
+                                let key = build_internal_key(user_key, seq, OP_TYPE_DELETE);
+                                tb.add(&key, b"")?;
+                            }
+                        }
+                        CompactionDecision::ChangeValue(value) => {
+                            let value = match typ {
+                                OP_TYPE_PUT_TTL => {
+                                    let raw = self.compaction_value(&e.value)?;
+                                    encode_ttl_value(decode_ttl_value(&raw)?.0, &value)
+                                }
+                                _ => value,
+                            };
+                            tb.add(&e.key, &Self::inline_value(&value))?;
+                        }
                     }
-                    tb.add(&e.key, &value);
                 }
             }
+            if let Some(key) = current_user_key.as_deref() {
+                Self::add_merge_operands(&mut tb, merge_op, key, &mut operands)?;
+            }
+            // an input that failed to read would silently drop its entries
+            merge_iter.status()?;
             tb.finish_builder(meta)?;
+            if let Some(vlog) = vlog.as_ref() {
+                vlog.sync()?;
+            }
             if vlog.is_none() {
                 // no new vlog produce. merge vlogs that in CompactionState to new group
-                c.base.iter().chain(c.target.iter()).for_each(|f| {
-                    meta.vlogs.append(&mut f.vlogs.clone());
-                });
+                c.base
+                    .iter()
+                    .chain(c.target.iter())
+                    .filter(|f| !dropped.contains(&f.number))
+                    .for_each(|f| {
+                        meta.vlogs.append(&mut f.vlogs.clone());
+                    });
             } else {
                 // only one vlog for sst. vlogs in CompactionState could be removed in the future
                 meta.vlogs.push(meta.number);
+                // the live values were copied out, the old vlogs go away whole
+                c.discards.clear();
                 let mut drops = vec![];
                 c.base.iter().chain(c.target.iter()).for_each(|f| {
                     drops.append(&mut f.vlogs.clone());
@@ -662,27 +1095,63 @@ impl VersionSet {
         Ok(Some(c))
     }
 
+    // Add the merge operands left of `user_key`, whose value is not in the
+    // compaction, combined as far as the merge operator allows. They are kept
+    // in the table rather than a vlog.
+    fn add_merge_operands(
+        tb: &mut TableBuilder,
+        merge_op: Option<&dyn MergeOperator>,
+        user_key: &[u8],
+        operands: &mut Vec<(Bytes, Bytes)>,
+    ) -> Result<()> {
+        let Some(op) = merge_op else {
+            return Ok(());
+        };
+        for (key, operand) in merge::partial_merge(op, user_key, std::mem::take(operands)) {
+            tb.add(&key, &Self::inline_value(&operand))?;
+        }
+        Ok(())
+    }
+
+    fn inline_value(value: &[u8]) -> Vec<u8> {
+        let mut inline = Vec::with_capacity(value.len() + 1);
+        inline.put_u8(0);
+        inline.put_slice(value);
+        inline
+    }
+
+    // the value of an entry read by compaction, bypassing the value cache
+    fn compaction_value(&self, value: &[u8]) -> Result<Bytes> {
+        if value.is_empty() || value[0] == 0 {
+            return Ok(Bytes::copy_from_slice(value.get(1..).unwrap_or_default()));
+        }
+        let (fid, offset) = decode_value_pointer(value)?;
+        Ok(self.vlog_cache.read_entry(fid, offset)?.value)
+    }
+
     pub fn remove_ssts(&self) -> Result<()> {
-        let versions = self.versions.read();
         let mut lives = HashSet::new();
         let mut lives_vlog = HashSet::new();
         let mut deletes = HashSet::new();
         let mut deletes_vlog = HashSet::new();
-        versions.iter().for_each(|v| {
-            v.files.iter().for_each(|files| {
-                files.iter().for_each(|f| {
+        for cf in self.column_families() {
+            let versions = cf.versions.read();
+            versions
+                .iter()
+                .flat_map(|v| v.files.iter().flatten())
+                .for_each(|f| {
                     lives.insert(f.number);
-                    f.vlogs.iter().for_each(|v| {
-                        lives_vlog.insert(v);
-                    });
-                })
-            })
-        });
+                    lives_vlog.extend(f.vlogs.iter().copied());
+                });
+        }
         let dir = std::fs::read_dir(Path::new(&self.opt.work_dir))?;
         for dir_entry in dir {
             if let Some(file_name) = dir_entry?.file_name().to_str() {
                 if let Some((name, ext)) = file_name.split_once('.') {
-                    let fid = name.parse::<u64>()?;
+                    // not a file of ours
+                    let Ok(fid) = name.parse::<u64>() else {
+                        continue;
+                    };
                     if ext == "sst" && !lives.contains(&fid) {
                         deletes.insert(fid);
                     } else if ext == "vlog" && !lives_vlog.contains(&fid) {
@@ -695,160 +1164,267 @@ impl VersionSet {
         deletes.iter().try_for_each(|fid| -> Result<()> {
             let path = path_of_file(&self.opt.work_dir, *fid, Ext::SST);
             std::fs::remove_file(path.as_path())?;
-            self.table_cache.evict(*fid, 1)?;
+            self.table_cache.evict(*fid)?;
             Ok(())
         })?;
         deletes_vlog.iter().try_for_each(|fid| -> Result<()> {
             let path = path_of_file(&self.opt.work_dir, *fid, Ext::VLOG);
             std::fs::remove_file(path.as_path())?;
+            self.vlog_cache.evict(*fid)?;
             Ok(())
         })?;
         Ok(())
     }
 
-    pub fn recover(&self) -> Result<()> {
-        let mut f = Reader::new(Box::new(SequentialFileImpl::new(
-            path_of_file(&self.opt.work_dir, 0, Ext::MANIFEST).as_path(),
-        )));
-        let mut edit = VersionEdit::new();
-        let mut add_files = vec![];
-        let mut delete_files = vec![];
+    /// Recover the column families from the manifest, each with the options
+    /// `cf_opts` has under its name. The default family has the options of
+    /// the storage unless `cf_opts` names it.
+    pub fn recover(&self, cf_opts: &HashMap<String, Options>) -> Result<()> {
+        let path = path_of_file(&self.opt.work_dir, 0, Ext::MANIFEST);
+        let mut f = Reader::new(Box::new(SequentialFileImpl::new(path.as_path())?));
+        // the edits of each column family folded into one, with its name
+        let mut families = BTreeMap::new();
+        families.insert(0, (DEFAULT_COLUMN_FAMILY.to_string(), VersionEdit::new()));
         let mut delete_set = HashSet::new();
         let mut log_number = 0;
         let mut last_seq_number = 0;
         let mut next_file_number = 0;
+        let mut comparator = None;
+        let mut empty = true;
 
-        let mut end = false;
-        while !end {
-            let record = f.read_record();
-            match record {
-                core::result::Result::Ok(record) => {
-                    let t_edit = VersionEdit::decode(&record);
-                    t_edit
-                        .add_files
-                        .iter()
-                        .for_each(|f| add_files.push(f.clone()));
-                    t_edit.delete_files.iter().for_each(|f| {
-                        delete_files.push(f.clone());
-                        delete_set.insert(f.file_meta.number);
-                    });
-
-                    log_number = log_number.max(t_edit.log_number);
-                    last_seq_number = last_seq_number.max(t_edit.last_seq_number);
-                    next_file_number = next_file_number.max(t_edit.next_file_number);
-                    edit.next_file_number(t_edit.next_file_number);
-                }
-                Err(err) => match err.kind() {
-                    ErrorKind::UnexpectedEof => end = true,
-                    err => panic!("{:?}", err),
-                },
+        loop {
+            let offset = f.offset();
+            let Some(record) = f.read_record()? else {
+                break;
             };
+            let t_edit = VersionEdit::decode(&record)
+                .map_err(|err| Error::corruption(f.name(), offset, err))?;
+            empty = false;
+            if t_edit.comparator.is_some() {
+                comparator = t_edit.comparator.clone();
+            }
+            if let Some(name) = t_edit.add_column_family.as_ref() {
+                families
+                    .entry(t_edit.column_family)
+                    .or_insert_with(|| (name.clone(), VersionEdit::new()));
+            }
+            let Some((_, edit)) = families.get_mut(&t_edit.column_family) else {
+                let reason = format!("edit of unknown column family {}", t_edit.column_family);
+                return Err(Error::corruption(f.name(), offset, reason));
+            };
+            edit.add_files.extend(t_edit.add_files.iter().cloned());
+            t_edit.delete_files.iter().for_each(|f| {
+                delete_set.insert(f.file_meta.number);
+            });
+            edit.discard_vlogs.extend_from_slice(&t_edit.discard_vlogs);
+            edit.delete_vlogs.extend_from_slice(&t_edit.delete_vlogs);
+            edit.cf_log_number = edit.cf_log_number.max(t_edit.cf_log_number);
+
+            log_number = log_number.max(t_edit.log_number);
+            last_seq_number = last_seq_number.max(t_edit.last_seq_number);
+            next_file_number = next_file_number.max(t_edit.next_file_number);
         }
-        add_files
+
+        // storages created before the comparator was recorded are bytewise
+        let recorded = comparator
+            .clone()
+            .or_else(|| (!empty).then(|| BytewiseComparator::NAME.to_string()));
+        if let Some(name) = recorded {
+            if name != self.opt.comparator.name() {
+                return Err(Error::InvalidArgument(format!(
+                    "comparator {} does not match {} of the storage",
+                    self.opt.comparator.name(),
+                    name
+                )));
+            }
+        }
+
+        // a family would be flushed and compacted with options of another
+        if let Some((_, (name, _))) = families
             .iter()
-            .filter(|f| !delete_set.contains(&f.file_meta.number))
-            .for_each(|f| edit.add_files.push(f.clone()));
-        edit.log_number(log_number);
-        edit.last_seq_number(last_seq_number);
-        edit.next_file_number(next_file_number);
-
-        let base = Version::new(Arc::clone(&self.table_cache), Arc::clone(&self.index_cache));
-        let ver = Version::build(
-            Arc::clone(&self.table_cache),
-            Arc::clone(&self.index_cache),
-            Arc::new(base),
-            &edit,
-        );
+            .find(|(id, (name, _))| **id != 0 && !cf_opts.contains_key(name))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "column family {:?} is not opened",
+                name
+            )));
+        }
 
-        let mut versions = self.versions.write();
-        versions.push_back(Arc::new(ver));
+        let mut cfs = self.families.write();
+        for (id, (name, mut edit)) in families {
+            let opt = cf_opts.get(&name).unwrap_or(&self.opt).clone();
+            edit.add_files
+                .retain(|f| !delete_set.contains(&f.file_meta.number));
+            let ver = Version::build(
+                Arc::clone(&self.table_cache),
+                Arc::clone(&self.block_cache),
+                Arc::clone(&self.vlog_cache),
+                Arc::new(self.new_version(id)),
+                &edit,
+            );
+            let cf = ColumnFamilyData::new(id, &name, opt, ver);
+            cf.advance_log_number(edit.cf_log_number);
+            cfs.insert(id, Arc::new(cf));
+        }
+        self.log_number.store(log_number, Ordering::SeqCst);
         self.add_last_sequence(last_seq_number);
         self.next_file_number
             .fetch_add(next_file_number, Ordering::SeqCst);
 
+        if comparator.is_none() {
+            let mut edit = VersionEdit::new();
+            edit.comparator(self.opt.comparator.name());
+            edit.log_number(log_number);
+            self.log_edit(&mut edit)?;
+        }
         Ok(())
     }
 
-    pub fn do_gc(&self, meta: &mut FileMetaData) -> Result<Option<GCState>> {
-        let current = self.current();
-        let mut target_level = 0;
-        let mut target_fid = 0;
-        let mut target_sz = 0;
-        let mut target_meta = FileMetaData::new(0);
+    /// Pick the table whose vlogs hold the largest share of garbage, if that
+    /// share is at least `ratio`.
+    ///
+    /// The garbage of a vlog is what compactions recorded as discarded in the
+    /// manifest. Tables in level 0 are left to compaction: a rewrite would
+    /// give one a newer file number than the tables that shadow it.
+    pub fn pick_gc(
+        &self,
+        cf: &ColumnFamilyData,
+        ratio: f64,
+    ) -> Result<Option<(usize, FileMetaData)>> {
+        let current = cf.current();
+        current.refs();
+        let picked = self.pick_gc_inner(&current, ratio);
+        current.derefs();
+        picked
+    }
 
-        // pick a sstable to gc
-        current
-            .files
-            .iter()
-            .rev()
-            .enumerate()
-            .for_each(|(level, files)| {
-                files.iter().for_each(|f| {
-                    if target_sz < f.vlogs.len() {
-                        target_sz = f.vlogs.len();
-                        target_fid = f.number;
-                        target_level = level;
-                        target_meta = f.clone();
-                    }
-                })
-            });
+    fn pick_gc_inner(
+        &self,
+        current: &Version,
+        ratio: f64,
+    ) -> Result<Option<(usize, FileMetaData)>> {
+        let mut picked = None;
+        let mut best_ratio = ratio;
+        for (level, files) in current.files.iter().enumerate().skip(1) {
+            for f in files.iter().filter(|f| !f.vlogs.is_empty()) {
+                let garbage = self.vlog_garbage_ratio(current, f);
+                if garbage >= best_ratio {
+                    best_ratio = garbage;
+                    picked = Some((level, f.clone()));
+                }
+            }
+        }
+        Ok(picked)
+    }
 
-        if target_sz < 2 {
-            return Ok(None);
+    fn vlog_garbage_ratio(&self, current: &Version, f: &FileMetaData) -> f64 {
+        let (mut total, mut discard) = (0, 0);
+        for fid in f.vlogs.iter() {
+            let path = path_of_file(&self.opt.work_dir, *fid, Ext::VLOG);
+            // a vlog that cannot be sized is not worth a rewrite
+            total += std::fs::metadata(path).map_or(0, |m| m.len());
+            discard += current.vlog_discard(*fid);
+        }
+        if total == 0 {
+            return 0.0;
         }
+        discard.min(total) as f64 / total as f64
+    }
+
+    /// Rewrite table `target` of `level` with its live values moved to a new
+    /// vlog, so the vlogs it referenced can be deleted.
+    ///
+    /// The vlogs are scanned in the order they were written, and an entry is
+    /// kept only if the table still points to it.
+    pub fn do_gc(
+        &self,
+        cf: &ColumnFamilyData,
+        meta: &mut FileMetaData,
+        level: usize,
+        target: FileMetaData,
+    ) -> Result<GCState> {
+        let target_fid = target.number;
 
-        let mut vlog_cache = HashMap::<u64, RandomReader>::new();
         // don't use cache, because we only need to rewrite the sstable
         let path = path_of_file(&self.opt.work_dir, target_fid, Ext::SST);
-        let t = Table::new(Box::new(RandomAccessFileImpl::open(path.as_path())))?;
+        let file = RandomAccessFileImpl::open(path.as_path())?;
+        let t =
+            Arc::new(Table::new(Box::new(file))?.with_comparator(Arc::clone(&self.opt.comparator)));
 
         let new_fid = self.new_file_number();
+        let vlog_writer = VlogWriter::create(&self.opt.work_dir, new_fid)?;
+        let mut moved = HashMap::new();
+        for fid in target.vlogs.iter() {
+            let path = path_of_file(&self.opt.work_dir, *fid, Ext::VLOG);
+            let mut vlog_iter = VlogIterator::open(&path)?;
+            while let Some((offset, entry)) = vlog_iter.next_entry()? {
+                if Self::vlog_entry_live(&t, *fid, offset, &entry)? {
+                    moved.insert((*fid, offset), vlog_writer.add(&entry)?);
+                }
+            }
+        }
+
         let new_path = path_of_file(&self.opt.work_dir, new_fid, Ext::SST);
         let mut tb = TableBuilder::new(
-            self.opt.clone(),
-            Box::new(WritableFileImpl::new(&new_path)),
+            cf.options().as_ref().clone(),
+            Box::new(WritableFileImpl::new(&new_path)?),
             new_fid,
-        );
-        let vlog_writer = Writer::new(WritableFileImpl::new(&path_of_file(
-            &self.opt.work_dir,
-            new_fid,
-            Ext::VLOG,
-        )));
-
-        let mut iter = TableIterator::new(Arc::new(t))?;
-        iter.try_for_each(|e| -> Result<()> {
+        )
+        .with_level(level);
+        t.range_tombstones()
+            .iter()
+            .for_each(|r| tb.add_range_tombstone(r.clone()));
+        let mut iter = TableIterator::new(t)?;
+        iter.by_ref().try_for_each(|e| -> Result<()> {
             let value = &e.value;
             let mut value_wrapper = value.to_vec();
 
             if !value.is_empty() && value[0] == 1 {
                 // value_ptr
-                let fid = (&value[1..9]).get_u64();
-                let offset = (&value[9..17]).get_u64();
-                let path = path_of_file(&self.opt.work_dir, fid, crate::file::Ext::VLOG);
-                let vlog = vlog_cache.entry(fid).or_insert_with(|| {
-                    RandomReader::new(Box::new(RandomAccessFileImpl::open(path.as_path())))
-                });
-
-                let ivalue = vlog.read_record(offset).unwrap();
-                let off = vlog_writer.offset();
+                let (fid, offset) = decode_value_pointer(value)?;
+                let Some(off) = moved.get(&(fid, offset)) else {
+                    let reason = format!("value of {:05}.vlog not found in its vlog", fid);
+                    return Err(Error::corruption(
+                        new_path.display().to_string(),
+                        offset,
+                        reason,
+                    ));
+                };
                 value_wrapper.clear();
                 value_wrapper.put_u8(1);
                 value_wrapper.put_u64(new_fid);
-                value_wrapper.put_u64(off);
-
-                vlog_writer.add_recore(&ivalue)?;
+                value_wrapper.put_u64(*off);
             }
-            tb.add(&e.key, &value_wrapper);
+            tb.add(&e.key, &value_wrapper)?;
             Ok(())
         })?;
+        iter.status()?;
         meta.number = new_fid;
         meta.vlogs.push(new_fid);
         tb.finish_builder(meta)?;
 
-        Ok(Some(GCState {
-            level: target_level,
-            rewrite_file: target_meta,
+        // the old vlogs are deleted once the new table is applied
+        vlog_writer.sync()?;
+
+        Ok(GCState {
+            level,
+            rewrite_file: target,
             new_file: meta.clone(),
-        }))
+        })
+    }
+
+    // Whether `table` still points to `entry`, stored at `offset` of vlog
+    // `fid`. A vlog has a single owning table, so one lookup settles it.
+    fn vlog_entry_live(table: &Table, fid: u64, offset: u64, entry: &VlogEntry) -> Result<bool> {
+        let internal_key = build_internal_key(&entry.key, entry.seq, OP_TYPE_PUT);
+        let Some((key, value)) = table.raw_get(&internal_key)? else {
+            return Ok(false);
+        };
+        // the entry may be of any type with a value, a put that expires too
+        let (user_key, seq, _) = parse_internal_key(&key);
+        if user_key != &entry.key[..] || seq != entry.seq || value.is_empty() || value[0] != 1 {
+            return Ok(false);
+        }
+        Ok(decode_value_pointer(&value)? == (fid, offset))
     }
 }
@@ -0,0 +1,361 @@
+use std::{io::ErrorKind, path::Path, sync::Arc};
+
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::{
+    cache::{Cache, CacheStats},
+    file::{
+        path_of_file, Ext, RandomAccess, RandomAccessFileImpl, RandomReader, Reader,
+        SequentialAccess, SequentialFileImpl, Writable, WritableFileImpl, Writer,
+    },
+    utils::codec::{take_bytes, take_u32, take_u64, CodecError},
+    Error, Options, Result,
+};
+
+// A vlog starts with a header, followed by one record per value:
+// +-------+---------+--------+--------+-----+
+// | magic | version | record | record | ... |
+// +-------+---------+--------+--------+-----+
+const VLOG_MAGIC: u32 = 0x636b_766c;
+const VLOG_VERSION: u32 = 1;
+const VLOG_HEADER_SIZE: u64 = 8;
+
+/// A separated value with the user key and sequence it was written under,
+/// so a vlog can be read without the table pointing into it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VlogEntry {
+    pub key: Bytes,
+    pub seq: u64,
+    pub value: Bytes,
+}
+
+impl VlogEntry {
+    pub fn new(key: Bytes, seq: u64, value: Bytes) -> Self {
+        Self { key, seq, value }
+    }
+
+    // +---------+----------+-----+-------+
+    // | key len | user key | seq | value |
+    // +---------+----------+-----+-------+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(12 + self.key.len() + self.value.len());
+        buf.put_u32(self.key.len() as u32);
+        buf.put_slice(&self.key);
+        buf.put_u64(self.seq);
+        buf.put_slice(&self.value);
+        buf
+    }
+
+    fn decode(data: Vec<u8>) -> std::result::Result<Self, CodecError> {
+        let data = Bytes::from(data);
+        let mut buf = &data[..];
+        let key_len = take_u32(&mut buf)? as usize;
+        take_bytes(&mut buf, key_len)?;
+        let seq = take_u64(&mut buf)?;
+        Ok(Self {
+            key: data.slice(4..4 + key_len),
+            seq,
+            value: data.slice(12 + key_len..),
+        })
+    }
+}
+
+fn check_header(name: &str, mut header: &[u8]) -> Result<()> {
+    let (magic, version) = (header.get_u32(), header.get_u32());
+    if magic != VLOG_MAGIC {
+        return Err(Error::corruption(
+            name,
+            0,
+            format!("bad magic {:#x}", magic),
+        ));
+    }
+    if version != VLOG_VERSION {
+        return Err(Error::corruption(
+            name,
+            0,
+            format!("unsupported version {}", version),
+        ));
+    }
+    Ok(())
+}
+
+// a vlog too short to hold a header is as broken as one with a bad header
+fn read_header(name: &str, read: impl FnOnce(&mut [u8]) -> std::io::Result<()>) -> Result<()> {
+    let mut header = [0_u8; VLOG_HEADER_SIZE as usize];
+    match read(&mut header) {
+        Ok(()) => check_header(name, &header),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+            Err(Error::corruption(name, 0, "missing header"))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Appends entries to a new vlog.
+pub(crate) struct VlogWriter {
+    writer: Writer,
+}
+
+impl VlogWriter {
+    pub fn create(work_dir: &str, fid: u64) -> Result<Self> {
+        let mut file = WritableFileImpl::new(&path_of_file(work_dir, fid, Ext::VLOG))?;
+        let mut header = Vec::with_capacity(VLOG_HEADER_SIZE as usize);
+        header.put_u32(VLOG_MAGIC);
+        header.put_u32(VLOG_VERSION);
+        file.append(&header)?;
+        Ok(Self {
+            writer: Writer::with_offset(file, VLOG_HEADER_SIZE),
+        })
+    }
+
+    /// Append `entry`, returning the offset a value pointer refers to it by.
+    pub fn add(&self, entry: &VlogEntry) -> Result<u64> {
+        let offset = self.writer.offset();
+        self.writer.add_recore(&entry.encode())?;
+        Ok(offset)
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        Ok(self.writer.sync()?)
+    }
+}
+
+/// Reads the entries of a vlog in the order they were written.
+pub struct VlogIterator {
+    reader: Reader,
+}
+
+impl VlogIterator {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = SequentialFileImpl::new(path)?;
+        read_header(&path.display().to_string(), |buf| file.read(buf))?;
+        Ok(Self {
+            reader: Reader::with_offset(Box::new(file), VLOG_HEADER_SIZE),
+        })
+    }
+
+    /// The next entry and its offset, none at the end of the vlog.
+    pub fn next_entry(&mut self) -> Result<Option<(u64, VlogEntry)>> {
+        let offset = self.reader.offset();
+        let Some(record) = self.reader.read_record()? else {
+            return Ok(None);
+        };
+        let entry = VlogEntry::decode(record)
+            .map_err(|err| Error::corruption(self.reader.name(), offset, err))?;
+        Ok(Some((offset, entry)))
+    }
+}
+
+/// Open readers of the vlog files, shared by all reads of separated values,
+/// and optionally the values read through them.
+pub struct VlogCache {
+    work_dir: String,
+    readers: Cache<u64, RandomReader>,
+    // keyed by vlog file id and record offset, charged by value size
+    values: Option<Cache<(u64, u64), Bytes>>,
+}
+
+impl VlogCache {
+    pub fn new(opt: &Options) -> Self {
+        Self {
+            work_dir: opt.work_dir.clone(),
+            readers: Cache::with_capacity(opt.vlog_cache_size),
+            values: (opt.value_cache_size > 0).then(|| Cache::with_capacity(opt.value_cache_size)),
+        }
+    }
+
+    /// Read the value stored at `offset` of vlog `fid`, from the value cache
+    /// if it is enabled.
+    pub fn read_value(&self, fid: u64, offset: u64) -> Result<Bytes> {
+        let Some(values) = self.values.as_ref() else {
+            return Ok(self.read_entry(fid, offset)?.value);
+        };
+        let key = (fid, offset);
+        if let Some(value) = values.get(&key) {
+            values.unpin(&key)?;
+            return Ok(value.as_ref().clone());
+        }
+        let value = self.read_entry(fid, offset)?.value;
+        // no room in cache, or inserted by another reader
+        if values.insert(key, value.clone(), value.len()).is_ok() {
+            values.unpin(&key)?;
+        }
+        Ok(value)
+    }
+
+    /// Read the entry stored at `offset` of vlog `fid`, bypassing the value
+    /// cache. Used by compaction, which reads each value once.
+    pub fn read_entry(&self, fid: u64, offset: u64) -> Result<VlogEntry> {
+        let reader = self.reader(fid)?;
+        VlogEntry::decode(reader.read_record(offset)?)
+            .map_err(|err| Error::corruption(reader.name(), offset, err))
+    }
+
+    /// Size of the record stored at `offset` of vlog `fid`.
+    pub fn record_size(&self, fid: u64, offset: u64) -> Result<u64> {
+        self.reader(fid)?.record_size(offset)
+    }
+
+    fn reader(&self, fid: u64) -> Result<Arc<RandomReader>> {
+        if let Some(reader) = self.readers.get(&fid) {
+            self.readers.unpin(&fid)?;
+            return Ok(reader);
+        }
+        let path = path_of_file(&self.work_dir, fid, Ext::VLOG);
+        let reader = || -> Result<RandomReader> {
+            let file = RandomAccessFileImpl::open(path.as_path())?;
+            read_header(file.name(), |buf| file.read(buf, 0))?;
+            Ok(RandomReader::new(Box::new(file)))
+        };
+        match self.readers.insert(fid, reader()?, 1) {
+            Ok(reader) => {
+                self.readers.unpin(&fid)?;
+                Ok(reader)
+            }
+            // inserted by another reader, or no room in cache
+            Err(_) => Ok(Arc::new(reader()?)),
+        }
+    }
+
+    /// Drop the reader and the cached values of a deleted vlog.
+    pub fn evict(&self, fid: u64) -> Result<()> {
+        self.readers.evict(fid)?;
+        if let Some(values) = self.values.as_ref() {
+            values.evict_if(|(vfid, _)| *vfid == fid);
+        }
+        Ok(())
+    }
+
+    pub fn value_cache_stats(&self) -> CacheStats {
+        self.values
+            .as_ref()
+            .map(|values| values.stats())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod vlog_test {
+    use bytes::Bytes;
+
+    use crate::{
+        file::{path_of_file, Ext, WritableFileImpl, Writer},
+        Error, Options,
+    };
+
+    use super::{VlogCache, VlogEntry, VlogIterator, VlogWriter};
+
+    fn clean_dir(opt: &Options) {
+        if std::fs::metadata(&opt.work_dir).is_ok() {
+            std::fs::remove_dir_all(&opt.work_dir).unwrap();
+        };
+        std::fs::create_dir_all(&opt.work_dir).unwrap();
+    }
+
+    fn entry(i: u32) -> VlogEntry {
+        let key = Bytes::from(format!("key{}", i));
+        VlogEntry::new(key, i as u64, Bytes::from(i.to_be_bytes().to_vec()))
+    }
+
+    #[test]
+    fn vlog_cache_test() {
+        let opt = Options::default_opt()
+            .work_dir("work_dir/vlog_cache")
+            .value_cache_size(1 << 10);
+        clean_dir(&opt);
+
+        let path = path_of_file(&opt.work_dir, 1, Ext::VLOG);
+        let writer = VlogWriter::create(&opt.work_dir, 1).unwrap();
+        let mut offsets = vec![];
+        for i in 0..10_u32 {
+            offsets.push(writer.add(&entry(i)).unwrap());
+        }
+        writer.sync().unwrap();
+
+        let cache = VlogCache::new(&opt);
+        for _ in 0..2 {
+            for (i, offset) in offsets.iter().enumerate() {
+                let value = cache.read_value(1, *offset).unwrap();
+                assert_eq!(value, (i as u32).to_be_bytes().to_vec());
+            }
+        }
+        let stats = cache.value_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.usage), (10, 10, 40));
+
+        // the deleted vlog is neither served from cache nor readable
+        std::fs::remove_file(&path).unwrap();
+        cache.evict(1).unwrap();
+        assert_eq!(cache.value_cache_stats().usage, 0);
+        assert!(cache.read_value(1, offsets[0]).is_err());
+    }
+
+    #[test]
+    fn vlog_format_test() {
+        let opt = Options::default_opt().work_dir("work_dir/vlog_format");
+        clean_dir(&opt);
+
+        let writer = VlogWriter::create(&opt.work_dir, 1).unwrap();
+        let mut offsets = vec![];
+        for i in 0..10_u32 {
+            offsets.push(writer.add(&entry(i)).unwrap());
+        }
+        writer.sync().unwrap();
+
+        // a scan sees the entries at the offsets value pointers refer to
+        let path = path_of_file(&opt.work_dir, 1, Ext::VLOG);
+        let mut iter = VlogIterator::open(&path).unwrap();
+        let mut i = 0;
+        while let Some((offset, e)) = iter.next_entry().unwrap() {
+            assert_eq!((offset, e), (offsets[i as usize], entry(i)));
+            i += 1;
+        }
+        assert_eq!(i, 10);
+        let cache = VlogCache::new(&opt);
+        assert_eq!(cache.read_entry(1, offsets[3]).unwrap(), entry(3));
+
+        // a file without the vlog header is rejected by both readers
+        let mut data = std::fs::read(&path).unwrap();
+        data[0] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        let corrupted = |res: crate::Result<_>| matches!(res, Err(Error::Corruption { .. }));
+        assert!(corrupted(VlogIterator::open(&path).map(|_| ())));
+        assert!(corrupted(
+            VlogCache::new(&opt).read_entry(1, offsets[0]).map(|_| ())
+        ));
+        std::fs::write(&path, &data[..3]).unwrap();
+        assert!(corrupted(VlogIterator::open(&path).map(|_| ())));
+    }
+
+    #[test]
+    fn vlog_large_offset_test() {
+        let opt = Options::default_opt().work_dir("work_dir/vlog_large_offset");
+        clean_dir(&opt);
+
+        // entries written past 4 GiB of a sparse vlog
+        let path = path_of_file(&opt.work_dir, 1, Ext::VLOG);
+        drop(VlogWriter::create(&opt.work_dir, 1).unwrap());
+        let start = 5_u64 << 30;
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(start)
+            .unwrap();
+        let writer = VlogWriter {
+            writer: Writer::with_offset(WritableFileImpl::new(&path).unwrap(), start),
+        };
+        let mut offsets = vec![];
+        for i in 0..10_u32 {
+            offsets.push(writer.add(&entry(i)).unwrap());
+        }
+        writer.sync().unwrap();
+        assert!(offsets[0] >= start);
+
+        let cache = VlogCache::new(&opt);
+        for (i, offset) in offsets.iter().enumerate() {
+            assert_eq!(cache.read_entry(1, *offset).unwrap(), entry(i as u32));
+        }
+        drop(writer);
+        clean_dir(&opt);
+    }
+}
@@ -1,23 +1,346 @@
-use bytes::Bytes;
+use std::time::Duration;
 
-use crate::utils::{Entry, OP_TYPE_DELETE, OP_TYPE_PUT};
+use bytes::{Buf, BufMut, Bytes};
 
-#[derive(Debug, Default)]
+use crate::{
+    utils::{
+        codec::{decode_varintu32, encode_varintu32, varintu32_length, CodecError},
+        encode_ttl_value, now_millis, Entry, OP_TYPE_DELETE, OP_TYPE_MERGE, OP_TYPE_PUT,
+        OP_TYPE_PUT_TTL, OP_TYPE_RANGE_DELETE,
+    },
+    ColumnFamily,
+};
+
+/// Version of the WAL record layout written by [`WriteBatch::encode`].
+///
+/// Records of the first layout hold a single put and start with the high
+/// byte of their sequence, which is always 0, so a non-zero first byte
+/// tells the layouts apart. Version 1 records have no column family, all of
+/// their operations are of the default one.
+pub(crate) const WAL_RECORD_VERSION: u8 = 2;
+
+/// Operations written atomically, possibly to several column families.
+#[derive(Debug, Default, Clone)]
 pub struct WriteBatch {
-    pub(crate) data: Vec<(Entry, u8)>,
+    // each operation with the id of its column family
+    pub(crate) data: Vec<(u32, Entry, u8)>,
     pub(crate) count: usize,
 }
 
 impl WriteBatch {
     pub fn put(&mut self, key: &[u8], value: &[u8]) {
-        let e = Entry::new(Bytes::from(key.to_vec()), Bytes::from(value.to_vec()), 0);
-        self.data.push((e, OP_TYPE_PUT));
-        self.count += 1;
+        self.add(0, key, value, OP_TYPE_PUT);
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamily, key: &[u8], value: &[u8]) {
+        self.add(cf.id(), key, value, OP_TYPE_PUT);
+    }
+
+    /// Put `value` for `ttl` from now. Once it has passed, the key reads as
+    /// deleted and compactions into the bottom level drop the entry.
+    pub fn put_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: Duration) {
+        self.add_with_ttl(0, key, value, ttl);
+    }
+
+    pub fn put_with_ttl_cf(&mut self, cf: &ColumnFamily, key: &[u8], value: &[u8], ttl: Duration) {
+        self.add_with_ttl(cf.id(), key, value, ttl);
     }
 
     pub fn delete(&mut self, key: &[u8]) {
-        let e = Entry::new(Bytes::from(key.to_vec()), Bytes::from(""), 0);
-        self.data.push((e, OP_TYPE_DELETE));
+        self.add(0, key, b"", OP_TYPE_DELETE);
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamily, key: &[u8]) {
+        self.add(cf.id(), key, b"", OP_TYPE_DELETE);
+    }
+
+    /// Delete every key in `[start, end)`. It takes one sequence number, like
+    /// any other operation of the batch.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.add(0, start, end, OP_TYPE_RANGE_DELETE);
+    }
+
+    pub fn delete_range_cf(&mut self, cf: &ColumnFamily, start: &[u8], end: &[u8]) {
+        self.add(cf.id(), start, end, OP_TYPE_RANGE_DELETE);
+    }
+
+    /// Merge `operand` into the value of `key`, with the merge operator of
+    /// the storage it is written to.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) {
+        self.add(0, key, operand, OP_TYPE_MERGE);
+    }
+
+    pub fn merge_cf(&mut self, cf: &ColumnFamily, key: &[u8], operand: &[u8]) {
+        self.add(cf.id(), key, operand, OP_TYPE_MERGE);
+    }
+
+    fn add(&mut self, cf: u32, key: &[u8], value: &[u8], op: u8) {
+        let e = Entry::new(Bytes::from(key.to_vec()), Bytes::from(value.to_vec()), 0);
+        self.data.push((cf, e, op));
         self.count += 1;
     }
+
+    fn add_with_ttl(&mut self, cf: u32, key: &[u8], value: &[u8], ttl: Duration) {
+        let expire_at = now_millis().saturating_add(ttl.as_millis() as u64);
+        self.add(
+            cf,
+            key,
+            &encode_ttl_value(expire_at, value),
+            OP_TYPE_PUT_TTL,
+        );
+    }
+
+    /// Append the operations of `other` to this batch.
+    pub fn append(&mut self, other: &WriteBatch) {
+        self.data.extend(other.data.iter().cloned());
+        self.count += other.count;
+    }
+
+    pub(crate) fn approximate_size(&self) -> usize {
+        self.data
+            .iter()
+            .map(|(_, e, _)| e.key.len() + e.value.len())
+            .sum()
+    }
+
+    // A batch is logged as one record, so it is either replayed whole or
+    // not at all.
+    //
+    // +---------+-----+-------+------------------------------------------------+
+    // | version | seq | count | op | cf | key_sz | key | value_sz | value | ... |
+    // +---------+-----+-------+------------------------------------------------+
+    //    u8      u64    u32     u8 varint varint       varint
+    pub(crate) fn encode(&self, seq: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_u8(WAL_RECORD_VERSION);
+        buf.put_u64(seq);
+        buf.put_u32(self.count as u32);
+        for (cf, e, op) in self.data.iter() {
+            buf.put_u8(*op);
+            encode_varintu32(&mut buf, *cf);
+            encode_varintu32(&mut buf, e.key.len() as u32);
+            buf.put_slice(&e.key);
+            encode_varintu32(&mut buf, e.value.len() as u32);
+            buf.put_slice(&e.value);
+        }
+        buf
+    }
+
+    /// Decode a WAL record into the sequence of its first entry and the batch.
+    pub(crate) fn decode(record: &[u8]) -> Result<(u64, WriteBatch), CodecError> {
+        match record.first() {
+            Some(0) => WriteBatch::decode_v0(record),
+            Some(1) => WriteBatch::decode_v1(record, false),
+            Some(&WAL_RECORD_VERSION) => WriteBatch::decode_v1(record, true),
+            Some(v) => Err(CodecError::InvalidRecord(format!(
+                "unknown wal record version {}",
+                v
+            ))),
+            None => Err(CodecError::InvalidRecord("empty wal record".to_string())),
+        }
+    }
+
+    // +-----+--------+-----+----------+-------+
+    // | seq | key_sz | key | value_sz | value |
+    // +-----+--------+-----+----------+-------+
+    fn decode_v0(mut record: &[u8]) -> Result<(u64, WriteBatch), CodecError> {
+        if record.len() < 8 {
+            return Err(CodecError::InvalidRecord(
+                "truncated wal record".to_string(),
+            ));
+        }
+        let seq = record.get_u64();
+        let key = WriteBatch::decode_slice(&mut record)?;
+        let value = WriteBatch::decode_slice(&mut record)?;
+        let mut batch = WriteBatch::default();
+        batch.put(key, value);
+        Ok((seq, batch))
+    }
+
+    // version 1 and 2 records, the latter with the column family of each
+    // operation
+    fn decode_v1(mut record: &[u8], with_cf: bool) -> Result<(u64, WriteBatch), CodecError> {
+        if record.len() < 13 {
+            return Err(CodecError::InvalidRecord(
+                "truncated wal record".to_string(),
+            ));
+        }
+        record.advance(1);
+        let seq = record.get_u64();
+        let count = record.get_u32() as usize;
+        let mut batch = WriteBatch::default();
+        for _ in 0..count {
+            if record.is_empty() {
+                return Err(CodecError::InvalidRecord(
+                    "truncated wal record".to_string(),
+                ));
+            }
+            let op = record.get_u8();
+            let cf = if with_cf {
+                let cf = decode_varintu32(record)?;
+                record.advance(varintu32_length(cf) as usize);
+                cf
+            } else {
+                0
+            };
+            let key = WriteBatch::decode_slice(&mut record)?;
+            let value = WriteBatch::decode_slice(&mut record)?;
+            match op {
+                OP_TYPE_PUT | OP_TYPE_DELETE | OP_TYPE_RANGE_DELETE | OP_TYPE_MERGE
+                | OP_TYPE_PUT_TTL => batch.add(cf, key, value, op),
+                op => {
+                    return Err(CodecError::InvalidRecord(format!(
+                        "unknown operation type {}",
+                        op
+                    )))
+                }
+            }
+        }
+        if !record.is_empty() {
+            return Err(CodecError::InvalidRecord(
+                "trailing bytes in wal record".to_string(),
+            ));
+        }
+        Ok((seq, batch))
+    }
+
+    fn decode_slice<'a>(record: &mut &'a [u8]) -> Result<&'a [u8], CodecError> {
+        let sz = decode_varintu32(record)?;
+        let start = varintu32_length(sz) as usize;
+        let end = start + sz as usize;
+        if record.len() < end {
+            return Err(CodecError::InvalidRecord(
+                "truncated wal record".to_string(),
+            ));
+        }
+        let data = &record[start..end];
+        record.advance(end);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod write_batch_test {
+    use std::time::Duration;
+
+    use bytes::BufMut;
+
+    use crate::{
+        utils::{
+            codec::encode_varintu32, live_value, now_millis, OP_TYPE_DELETE, OP_TYPE_MERGE,
+            OP_TYPE_PUT, OP_TYPE_PUT_TTL, OP_TYPE_RANGE_DELETE,
+        },
+        ColumnFamily,
+    };
+
+    use super::WriteBatch;
+
+    #[test]
+    fn encode_decode_test() {
+        let mut batch = WriteBatch::default();
+        batch.put(b"k1", b"v1");
+        batch.delete(b"k2");
+        batch.put(b"k3", b"");
+        batch.delete_range(b"k4", b"k6");
+        batch.merge(b"k7", b"+1");
+
+        let record = batch.encode(42);
+        let (seq, decoded) = WriteBatch::decode(&record).unwrap();
+        assert_eq!(seq, 42);
+        assert_eq!(decoded.count, 5);
+        let ops: Vec<_> = decoded
+            .data
+            .iter()
+            .map(|(_, e, op)| (e.key.to_vec(), e.value.to_vec(), *op))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (b"k1".to_vec(), b"v1".to_vec(), OP_TYPE_PUT),
+                (b"k2".to_vec(), vec![], OP_TYPE_DELETE),
+                (b"k3".to_vec(), vec![], OP_TYPE_PUT),
+                (b"k4".to_vec(), b"k6".to_vec(), OP_TYPE_RANGE_DELETE),
+                (b"k7".to_vec(), b"+1".to_vec(), OP_TYPE_MERGE),
+            ]
+        );
+
+        // a torn record is rejected as a whole
+        assert!(WriteBatch::decode(&record[..record.len() - 1]).is_err());
+        assert!(WriteBatch::decode(&record[..20]).is_err());
+    }
+
+    #[test]
+    fn put_with_ttl_test() {
+        let mut batch = WriteBatch::default();
+        batch.put_with_ttl(b"k1", b"v1", Duration::from_secs(60));
+
+        let (_, decoded) = WriteBatch::decode(&batch.encode(1)).unwrap();
+        let (_, e, op) = &decoded.data[0];
+        assert_eq!((&e.key[..], *op), (&b"k1"[..], OP_TYPE_PUT_TTL));
+        let now = now_millis();
+        assert_eq!(
+            live_value(*op, e.value.clone(), now).unwrap().unwrap(),
+            &b"v1"[..]
+        );
+        assert_eq!(
+            live_value(*op, e.value.clone(), now + 60_000).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn decode_v0_test() {
+        let mut record = vec![];
+        record.put_u64(7);
+        encode_varintu32(&mut record, 3);
+        record.put_slice(b"key");
+        encode_varintu32(&mut record, 5);
+        record.put_slice(b"value");
+
+        let (seq, batch) = WriteBatch::decode(&record).unwrap();
+        assert_eq!(seq, 7);
+        assert_eq!(batch.count, 1);
+        let (_, e, op) = &batch.data[0];
+        assert_eq!(
+            (&e.key[..], &e.value[..], *op),
+            (&b"key"[..], &b"value"[..], OP_TYPE_PUT)
+        );
+    }
+
+    #[test]
+    fn column_family_test() {
+        let cf = ColumnFamily::new(300, "users");
+        let mut batch = WriteBatch::default();
+        batch.put(b"k1", b"v1");
+        batch.put_cf(&cf, b"k1", b"v2");
+        batch.delete_cf(&cf, b"k2");
+
+        let (_, decoded) = WriteBatch::decode(&batch.encode(1)).unwrap();
+        let ops: Vec<_> = decoded
+            .data
+            .iter()
+            .map(|(cf, e, op)| (*cf, e.key.to_vec(), e.value.to_vec(), *op))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (0, b"k1".to_vec(), b"v1".to_vec(), OP_TYPE_PUT),
+                (300, b"k1".to_vec(), b"v2".to_vec(), OP_TYPE_PUT),
+                (300, b"k2".to_vec(), vec![], OP_TYPE_DELETE),
+            ]
+        );
+
+        // a version 1 record, without families, is of the default one
+        let mut record = vec![1];
+        record.put_u64(9);
+        record.put_u32(1);
+        record.put_u8(OP_TYPE_DELETE);
+        encode_varintu32(&mut record, 3);
+        record.put_slice(b"key");
+        encode_varintu32(&mut record, 0);
+        let (seq, batch) = WriteBatch::decode(&record).unwrap();
+        assert_eq!(seq, 9);
+        let (cf, e, op) = &batch.data[0];
+        assert_eq!((*cf, &e.key[..], *op), (0, &b"key"[..], OP_TYPE_DELETE));
+    }
 }